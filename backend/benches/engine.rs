@@ -0,0 +1,65 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use qr_haggis::Game;
+
+// The engine's hot paths during a bot search: listing the legal moves of a
+// position, validating combinations, reading hands and playing out a hand.
+
+/// A seeded two-player deal a few moves in, so that the table isn't empty
+fn position() -> Game {
+    let mut game = Game::new_with_seed(7);
+    for _ in 0..3 {
+        let card_ids = game.legal_moves().pop().unwrap();
+        game.try_play_cards(game.current_player, &card_ids).unwrap();
+    }
+    game
+}
+
+fn legal_moves(c: &mut Criterion) {
+    let game = position();
+    c.bench_function("legal_moves", |b| b.iter(|| black_box(&game).legal_moves()));
+}
+
+fn validate_combinations(c: &mut Criterion) {
+    let game = Game::new_with_seed(7);
+    let moves = game.legal_moves();
+    c.bench_function("validate_combinations", |b| {
+        b.iter(|| {
+            moves
+                .iter()
+                .filter(|card_ids| black_box(&game).can_play_cards(card_ids))
+                .count()
+        })
+    });
+}
+
+fn hand_queries(c: &mut Criterion) {
+    let game = position();
+    c.bench_function("hand_queries", |b| {
+        b.iter(|| {
+            let game = black_box(&game);
+            (game.hand_sizes(), game.is_game_over())
+        })
+    });
+}
+
+fn playout(c: &mut Criterion) {
+    c.bench_function("playout", |b| {
+        b.iter(|| {
+            let mut game = Game::new_with_seed(black_box(7));
+            while !game.is_game_over() {
+                let card_ids = game.legal_moves().pop().unwrap();
+                game.try_play_cards(game.current_player, &card_ids).unwrap();
+            }
+            game
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    legal_moves,
+    validate_combinations,
+    hand_queries,
+    playout
+);
+criterion_main!(benches);
@@ -1,10 +1,102 @@
 use std::collections::HashMap;
 
-use crate::game::{constant::*, location::Location, player::Player, Game};
+use crate::game::{
+    bet::Bet,
+    card::{deck_size, CardId},
+    constant::*,
+    error::DecodeError,
+    game_match::MatchKey,
+    history::{Replay, UndoState},
+    location::Location,
+    player::Player,
+    wildcard_shape::WildcardShape,
+    Game,
+};
+use hmac::{Hmac, Mac, NewMac};
 use num_bigint::BigUint;
+use sha2::Sha256;
+
+mod move_message;
+mod text;
+mod v1;
+
+pub(crate) use move_message::{
+    decode_key_share, decode_move, encode_key_share, encode_move, is_resync_request,
+    resync_request, state_hash, KEY_SHARE_LEN,
+};
+pub(crate) use text::{decode_text, encode_text};
+
+// Every compressed game starts with a header byte. The high nibble is MAGIC,
+// which tells our games apart from other data, and the low nibble is the
+// version of the layout that follows. Version 0 is the byte-aligned layout
+// below, and version 1 is the mixed-radix layout in v1.rs, which is what
+// encode_game writes. Version 0 games can still be decoded. The messages in
+// move_message.rs use the same header with other magics.
+//
+// Earlier versions of the app sent two-player games in the version 0 layout
+// without a header or a checksum. Those are HEADERLESS_LEN bytes long, which
+// no game with a header is, so they are decoded by their length alone.
+const MAGIC: u8 = 0xA0;
+const VERSION_MASK: u8 = 0x0F;
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 1;
+
+// An unsigned game ends with a CRC-8 of the header and the layout, so that
+// damaged data is rejected instead of decoding into a believable but wrong
+// game. A two-player game is 8 header bits, 232 layout bits and 8 checksum
+// bits, which leaves 24 bits of the 272-bit budget for a MAC instead of the
+// checksum. Three-player games don't fit the budget, as v1.rs explains.
+const CHECKSUM_LEN: usize = 1;
+const CRC_POLYNOMIAL: u8 = 0x07;
+
+// A game with a match key is signed: its header has AUTHENTICATED_MAGIC
+// instead of MAGIC, and a MAC of the header and the layout takes the place of
+// the checksum. The MAC is HMAC-SHA256 cut down to the 32 bits that are left,
+// so a forger has to guess among 2^32 MACs, and a wrong guess is rejected.
+// Damage that the checksum would catch breaks the MAC as well, so a damaged
+// signed game is rejected as unauthenticated.
+const AUTHENTICATED_MAGIC: u8 = 0x90;
+const MAC_LEN: usize = 4;
+
+fn mac(key: &MatchKey, data: &[u8]) -> [u8; MAC_LEN] {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(&key.0).expect("HMAC takes keys of any length");
+    hmac.update(data);
+    let mut mac = [0; MAC_LEN];
+    mac.copy_from_slice(&hmac.finalize().into_bytes()[..MAC_LEN]);
+    mac
+}
+
+/// CRC-8 with the polynomial x^8 + x^2 + x + 1, which detects any error in
+/// a single burst of up to 8 bits
+fn checksum(data: &[u8]) -> u8 {
+    let mut crc = 0;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                crc << 1 ^ CRC_POLYNOMIAL
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
 
 const GROUPING_ARRAY_BYTE_LEN: usize = (2 * (DECK_SIZE - HAGGIS_SIZE) + 7) / 8;
 const CARD_ORDER_BYTE_LEN: usize = 20;
+const FLAGS_BYTE_IDX: usize = CARD_ORDER_BYTE_LEN + 2 + GROUPING_ARRAY_BYTE_LEN;
+const HEADERLESS_LEN: usize = FLAGS_BYTE_IDX + 1;
+
+// The three-player layout is longer than the two-player layout, so decode_v0
+// tells them apart by length.
+const NUM_DEALT_THREE_PLAYERS: usize = DECK_SIZE_THREE_PLAYERS - SET_ASIDE_SIZE_THREE_PLAYERS;
+const CARD_ORDER_BYTE_LEN_THREE_PLAYERS: usize = 30;
+const GROUPING_ARRAY_BYTE_LEN_THREE_PLAYERS: usize = (3 * NUM_DEALT_THREE_PLAYERS + 7) / 8;
+const GROUPING_ARRAY_IDX_THREE_PLAYERS: usize = CARD_ORDER_BYTE_LEN_THREE_PLAYERS + MAX_NUM_PLAYERS;
+const BETS_BYTE_IDX_THREE_PLAYERS: usize =
+    GROUPING_ARRAY_IDX_THREE_PLAYERS + GROUPING_ARRAY_BYTE_LEN_THREE_PLAYERS;
+const FLAGS_BYTE_IDX_THREE_PLAYERS: usize = BETS_BYTE_IDX_THREE_PLAYERS + 1;
 
 // Goal: represent a player's initial hand in as few bytes as possible.
 //
@@ -70,19 +162,20 @@ fn n_choose_k(n: usize, k: usize) -> u32 {
     binomial as u32
 }
 
+// card_order_goal lists the first cards of a permutation of 0..deck_size
 // max return value:
-// 42 * 41 * ... * 9 - 1
-fn compress_card_order(card_order_goal: &[usize]) -> BigUint {
-    assert!(card_order_goal.len() == DECK_SIZE - HAGGIS_SIZE);
-
+// 42 * 41 * ... * 9 - 1 for two players
+// 54 * 53 * ... * 4 - 1 for three players
+#[cfg(test)]
+fn compress_card_order(card_order_goal: &[usize], deck_size: usize) -> BigUint {
     let mut compressed = BigUint::new(vec![0]);
 
-    let mut curr_card_order: Vec<usize> = (0..DECK_SIZE).collect(); // can be removed
-    let mut card_value_to_index: Vec<usize> = (0..DECK_SIZE).collect();
+    let mut curr_card_order: Vec<usize> = (0..deck_size).collect(); // can be removed
+    let mut card_value_to_index: Vec<usize> = (0..deck_size).collect();
 
     let mut distances = Vec::new();
 
-    for i in 0..(DECK_SIZE - HAGGIS_SIZE) {
+    for i in 0..card_order_goal.len() {
         let card_value_goal = card_order_goal[i];
         // We want to swap two cards: (let x = curr_card_order[i]) and card_order_goal[i]
         // So we need to find card_order_goal[i] in curr_card_order
@@ -94,19 +187,23 @@ fn compress_card_order(card_order_goal: &[usize]) -> BigUint {
     }
 
     while let Some(distance) = distances.pop() {
-        let card_possibilities = DECK_SIZE - distances.len();
+        let card_possibilities = deck_size - distances.len();
         compressed = BigUint::new(vec![distance as u32]) + (card_possibilities * compressed);
     }
 
     compressed
 }
 
-fn decompress_card_order(mut compressed: BigUint) -> Option<Vec<usize>> {
-    let mut card_possibilities: u32 = DECK_SIZE as u32;
+fn decompress_card_order(
+    mut compressed: BigUint,
+    deck_size: usize,
+    len: usize,
+) -> Option<Vec<usize>> {
+    let mut card_possibilities: u32 = deck_size as u32;
 
-    let mut curr_card_order: Vec<usize> = (0..DECK_SIZE).collect();
+    let mut curr_card_order: Vec<usize> = (0..deck_size).collect();
 
-    for i in 0..(DECK_SIZE - HAGGIS_SIZE) {
+    for i in 0..len {
         let distance = (&compressed % card_possibilities).to_u32_digits();
         let distance = if distance.len() == 0 {
             0
@@ -123,13 +220,23 @@ fn decompress_card_order(mut compressed: BigUint) -> Option<Vec<usize>> {
         return None;
     }
 
-    curr_card_order.truncate(DECK_SIZE - HAGGIS_SIZE);
+    curr_card_order.truncate(len);
     Some(curr_card_order)
 }
 
+#[cfg(test)]
+fn compress_card_order_bytes(card_order: &[usize], deck_size: usize, byte_len: usize) -> Vec<u8> {
+    let mut bytes = compress_card_order(card_order, deck_size).to_bytes_be();
+    while bytes.len() < byte_len {
+        bytes.insert(0, 0);
+    }
+    bytes
+}
+
 // bit == 0 means the first bit (head of the combination),
 // bit == 1 means the second bit (head of the group of combinations)
 // grouping_array_idx is relative to the cards on table, not including the cards in hand
+#[cfg(test)]
 fn set_1_for_grouping_array(grouping_array: &mut u128, grouping_array_idx: usize, bit: usize) {
     let bit_idx = 2 * grouping_array_idx + bit;
     *grouping_array = *grouping_array | 1 << bit_idx;
@@ -144,65 +251,82 @@ fn read_bit_from_grouping_array(
     (*grouping_array & 1 << bit_idx) > 0
 }
 
-// Standard card order when sending a qr code:
-// - my hand
-// - opponent's hand
-// - group of combinations
-// - next group of combinations, after a player passed
-// - ...
-pub(crate) fn encode_game(game: &Game) -> Vec<u8> {
-    let mut my_hand = Vec::new();
-    let mut opponent_hand = Vec::new();
-    let mut cards_on_table: HashMap<usize, Vec<usize>> = HashMap::new();
-    for (card_id, location) in game.locations.iter().enumerate() {
-        match location {
-            Location::Hand(Player::Me) => my_hand.push(card_id),
-            Location::Hand(Player::Opponent) => opponent_hand.push(card_id),
-            Location::Table { order, .. } => {
-                cards_on_table.entry(*order).or_default().push(card_id)
-            }
-            Location::Haggis => {}
-        }
+// Each bet is stored in 2 bits of the flags byte
+fn bet_to_bits(bet: Bet) -> u8 {
+    match bet {
+        Bet::None => 0,
+        Bet::Little => 1,
+        Bet::Big => 2,
     }
+}
 
-    let my_hand_size = my_hand.len();
-    let opponent_hand_size = opponent_hand.len();
-
-    // Each card gets 2 bits
-    // The first bit is 1 iff the card is the last card of its combination
-    // The second bit is 1 iff the card is the last card of its combination group
-    let mut grouping_array = 0_u128;
-    let mut card_order = [0; DECK_SIZE - HAGGIS_SIZE];
+fn bet_from_bits(bits: u8) -> Option<Bet> {
+    match bits {
+        0 => Some(Bet::None),
+        1 => Some(Bet::Little),
+        2 => Some(Bet::Big),
+        _ => None,
+    }
+}
 
-    // Card order: cards_in_my_hand, cards_in_opponents_hand, cards_on_the_table_in_order
+// Bits stored for each card on the table
+const END_OF_COMBINATION: usize = 0;
+const END_OF_COMBINATION_GROUP: usize = 1;
+/// Only stored by the three-player layout
+const AFTER_PASS: usize = 2;
 
-    for (i, card_id) in my_hand.iter().enumerate() {
-        card_order[i] = *card_id;
-    }
+// The END_OF_COMBINATION_GROUP bit is only meaningful for the last card of a
+// combination. A combination with a declared wildcard shape has at least 3
+// cards, so the bit of its first card marks that a shape was declared, and
+// the bit of its second card is set if the shape is WildcardShape::MoreSuits.
+const SHAPE_DECLARED_CARD: usize = 0;
+const MORE_SUITS_CARD: usize = 1;
 
-    for (i, card_id) in opponent_hand.iter().enumerate() {
-        card_order[my_hand_size + i] = *card_id;
+/// List the cards on the table in the order they were played, along with
+/// the grouping bits of each card
+fn cards_on_table_in_order(game: &Game) -> Vec<(usize, [bool; 3])> {
+    let mut cards_on_table: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (card_id, location) in game.locations.iter().enumerate() {
+        if let Location::Table { order, .. } = location {
+            cards_on_table.entry(*order).or_default().push(card_id)
+        }
     }
 
-    // u128 => u8[16]
-    let mut i: usize = 0;
+    let mut cards_in_order: Vec<(usize, [bool; 3])> = Vec::new();
     for order in 0.. {
         match cards_on_table.get(&order) {
             Some(combination) => {
-                // mark the end of a combination
-                for card_id in combination {
-                    card_order[i + my_hand_size + opponent_hand_size] = *card_id;
-                    i += 1;
+                let combination_start = cards_in_order.len();
+                for &card_id in combination {
+                    cards_in_order.push((card_id, [false; 3]));
+                }
+
+                if let Location::Table {
+                    wildcard_shape: Some(shape),
+                    ..
+                } = game.locations[combination[0]]
+                {
+                    let shape_declared_bits =
+                        &mut cards_in_order[combination_start + SHAPE_DECLARED_CARD].1;
+                    shape_declared_bits[END_OF_COMBINATION_GROUP] = true;
+                    let more_suits_bits =
+                        &mut cards_in_order[combination_start + MORE_SUITS_CARD].1;
+                    more_suits_bits[END_OF_COMBINATION_GROUP] = shape == WildcardShape::MoreSuits;
                 }
-                set_1_for_grouping_array(&mut grouping_array, i - 1, 0);
+
+                let (_, bits) = cards_in_order.last_mut().unwrap();
+                // mark the end of a combination
+                bits[END_OF_COMBINATION] = true;
 
                 if let Location::Table {
-                    in_last_combination_before_pass: true,
+                    in_last_combination_before_pass,
+                    after_pass,
                     ..
                 } = game.locations[combination[0]]
                 {
                     // mark the end of a group of combinations
-                    set_1_for_grouping_array(&mut grouping_array, i - 1, 1);
+                    bits[END_OF_COMBINATION_GROUP] = in_last_combination_before_pass;
+                    bits[AFTER_PASS] = after_pass;
                 }
             }
             None => {
@@ -214,22 +338,223 @@ pub(crate) fn encode_game(game: &Game) -> Vec<u8> {
                 let curr_captured_by = game.locations[combination[0]].captured_by();
                 if curr_captured_by.is_some() {
                     // mark the end of a group of combinations
-                    set_1_for_grouping_array(&mut grouping_array, i - 1, 1);
+                    let (_, bits) = cards_in_order.last_mut().unwrap();
+                    bits[END_OF_COMBINATION_GROUP] = true;
                 }
                 break;
             }
         }
     }
 
+    cards_in_order
+}
+
+/// Replay the cards on the table, in the order they were played, on top of a
+/// game where only the cards in hand have been placed. read_bit(i, bit) reads
+/// a grouping bit of the ith card on the table.
+fn replay_table(
+    game: &mut Game,
+    cards_on_table: &[usize],
+    read_bit: impl Fn(usize, usize) -> bool,
+) -> Option<()> {
+    let card_ids: Vec<CardId> = cards_on_table
+        .iter()
+        .map(|&card_id| CardId(card_id))
+        .collect();
+    let mut replay = Replay::new(game, &card_ids)?;
+    let mut combination = Vec::new();
+    for (grouping_array_idx, &card_id) in card_ids.iter().enumerate() {
+        combination.push(card_id);
+        if read_bit(grouping_array_idx, END_OF_COMBINATION) {
+            let combination_start = grouping_array_idx + 1 - combination.len();
+            let read_shape_bit = |card: usize| {
+                card + 1 < combination.len()
+                    && read_bit(combination_start + card, END_OF_COMBINATION_GROUP)
+            };
+            let shape = if read_shape_bit(SHAPE_DECLARED_CARD) {
+                if read_shape_bit(MORE_SUITS_CARD) {
+                    Some(WildcardShape::MoreSuits)
+                } else {
+                    Some(WildcardShape::MoreRanks)
+                }
+            } else if read_shape_bit(MORE_SUITS_CARD) {
+                return None;
+            } else {
+                None
+            };
+            // Any other bits of cards before the end of the combination are unused
+            for card in (MORE_SUITS_CARD + 1)..(combination.len() - 1) {
+                if read_bit(combination_start + card, END_OF_COMBINATION_GROUP) {
+                    return None;
+                }
+            }
+
+            if read_bit(grouping_array_idx, AFTER_PASS) {
+                replay.pass()?;
+            }
+            // The replay will reject wildcards played by the wrong player
+            replay.play(combination, shape)?;
+            combination = Vec::new();
+
+            if read_bit(grouping_array_idx, END_OF_COMBINATION_GROUP) {
+                replay.end_group()?;
+            }
+        }
+    }
+
+    if combination.is_empty() {
+        *game = replay.game;
+        Some(())
+    } else {
+        None
+    }
+}
+
+fn empty_game(num_players: usize, viewer: Player, bets: [Bet; MAX_NUM_PLAYERS]) -> Game {
+    Game {
+        locations: vec![Location::Haggis; deck_size(num_players)].into(),
+        num_players,
+        current_player: Player::First,
+        viewer,
+        last_combination_type: None,
+        last_player: None,
+        passes: 0,
+        first_out: None,
+        next_order: 0,
+        bets,
+        match_scores: [0; MAX_NUM_PLAYERS],
+        undo: UndoState::default(),
+        match_key: None,
+    }
+}
+
+/// Compress game, signed with its match key if it has one
+pub(crate) fn encode_game(game: &Game) -> Vec<u8> {
+    let magic = match game.match_key {
+        Some(_) => AUTHENTICATED_MAGIC,
+        None => MAGIC,
+    };
+    let mut compressed_game = vec![magic | VERSION];
+    compressed_game.append(&mut v1::encode(game));
+    match &game.match_key {
+        Some(key) => compressed_game.extend_from_slice(&mac(key, &compressed_game)),
+        None => compressed_game.push(checksum(&compressed_game)),
+    }
+    compressed_game
+}
+
+/// Decode a game that isn't signed
+pub(crate) fn decode_game(compressed_game: &[u8]) -> Result<Game, DecodeError> {
+    decode_game_with_key(compressed_game, None)
+}
+
+/// Decode a game, which must be signed with key if there is one, and must not
+/// be signed otherwise. The decoded game has no match key.
+pub(crate) fn decode_game_with_key(
+    compressed_game: &[u8],
+    key: Option<&MatchKey>,
+) -> Result<Game, DecodeError> {
+    if compressed_game.len() == HEADERLESS_LEN {
+        return match key {
+            Some(_) => Err(DecodeError::Unauthenticated),
+            None => decode_v0(compressed_game).ok_or(DecodeError::Malformed),
+        };
+    }
+    if compressed_game.len() < HEADER_LEN {
+        return Err(DecodeError::NotAGame);
+    }
+    let (header, payload) = compressed_game.split_at(HEADER_LEN);
+    let is_signed = match header[0] & !VERSION_MASK {
+        MAGIC => false,
+        AUTHENTICATED_MAGIC => true,
+        _ => return Err(DecodeError::NotAGame),
+    };
+    let version = header[0] & VERSION_MASK;
+    if version > VERSION {
+        return Err(DecodeError::UnsupportedVersion);
+    }
+
+    let payload = match (is_signed, key) {
+        (false, None) => {
+            if payload.len() < CHECKSUM_LEN {
+                return Err(DecodeError::Malformed);
+            }
+            let (payload, checksum_bytes) = payload.split_at(payload.len() - CHECKSUM_LEN);
+            if checksum(&compressed_game[..HEADER_LEN + payload.len()]) != checksum_bytes[0] {
+                return Err(DecodeError::ChecksumMismatch);
+            }
+            payload
+        }
+        (true, Some(key)) => {
+            if payload.len() < MAC_LEN {
+                return Err(DecodeError::Malformed);
+            }
+            let (payload, mac_bytes) = payload.split_at(payload.len() - MAC_LEN);
+            if mac(key, &compressed_game[..HEADER_LEN + payload.len()]) != mac_bytes {
+                return Err(DecodeError::Unauthenticated);
+            }
+            payload
+        }
+        _ => return Err(DecodeError::Unauthenticated),
+    };
+    match version {
+        0 => decode_v0(payload),
+        _ => v1::decode(payload),
+    }
+    .ok_or(DecodeError::Malformed)
+}
+
+// Everything is stored relative to the viewer of the game, who sends the qr
+// code. Standard card order when sending a qr code:
+// - my hand
+// - opponent's hand
+// - (second opponent's hand)
+// - group of combinations
+// - next group of combinations, after a player passed
+// - ...
+// Games are no longer written in version 0, but tests still write them to
+// check that old games decode.
+#[cfg(test)]
+fn encode_v0(game: &Game) -> Vec<u8> {
+    if game.num_players == MAX_NUM_PLAYERS {
+        return encode_three_player_game(game);
+    }
+
+    let opponent = game.viewer.other();
+    let my_hand = game.get_hand(game.viewer);
+    let opponent_hand = game.get_hand(opponent);
+    let my_hand_size = my_hand.len();
+    let opponent_hand_size = opponent_hand.len();
+
+    // Each card gets 2 bits
+    // The first bit is 1 iff the card is the last card of its combination
+    // The second bit is 1 iff the card is the last card of its combination group
+    let mut grouping_array = 0_u128;
+
+    // Card order: cards_in_my_hand, cards_in_opponents_hand, cards_on_the_table_in_order
+    let mut card_order: Vec<usize> = my_hand
+        .iter()
+        .chain(&opponent_hand)
+        .map(|id| id.0)
+        .collect();
+
+    for (i, (card_id, bits)) in cards_on_table_in_order(game).into_iter().enumerate() {
+        card_order.push(card_id);
+        for (bit, &is_set) in bits[..=END_OF_COMBINATION_GROUP].iter().enumerate() {
+            if is_set {
+                set_1_for_grouping_array(&mut grouping_array, i, bit);
+            }
+        }
+    }
+
+    // u128 => u8[16]
     let size_of_u128 = std::mem::size_of::<u128>();
     let grouping_array_bytes =
         &grouping_array.to_be_bytes()[size_of_u128 - GROUPING_ARRAY_BYTE_LEN..size_of_u128];
 
-    let compressed_card_order = compress_card_order(&card_order);
-    let mut compressed_card_order_bytes = compressed_card_order.to_bytes_be();
-    while compressed_card_order_bytes.len() < CARD_ORDER_BYTE_LEN {
-        compressed_card_order_bytes.insert(0, 0);
-    }
+    assert!(card_order.len() == DECK_SIZE - HAGGIS_SIZE);
+    let mut compressed_card_order_bytes =
+        compress_card_order_bytes(&card_order, DECK_SIZE, CARD_ORDER_BYTE_LEN);
 
     let mut compressed_game = Vec::new();
 
@@ -237,18 +562,26 @@ pub(crate) fn encode_game(game: &Game) -> Vec<u8> {
     // compressed_card_order as bytes (20 bytes),
     // hand sizes (2 bytes),
     // grouping array (33 elements, 9 bytes)
-    // Player::Me went first bool (1 byte)
+    // flags (1 byte): I went first bool in bit 0, my bet in bits 1-2,
+    //     opponent's bet in bits 3-4
     compressed_game.append(&mut compressed_card_order_bytes);
     compressed_game.push(my_hand_size as u8);
     compressed_game.push(opponent_hand_size as u8);
     compressed_game.append(&mut grouping_array_bytes.to_vec());
-    compressed_game.push(game.me_went_first as u8);
+    compressed_game.push(
+        game.me_went_first() as u8
+            | bet_to_bits(game.bets[game.viewer.index()]) << 1
+            | bet_to_bits(game.bets[opponent.index()]) << 3,
+    );
 
     compressed_game
 }
 
-pub(crate) fn decode_game(compressed_game: &[u8]) -> Option<Game> {
-    if compressed_game.len() < CARD_ORDER_BYTE_LEN + 2 + GROUPING_ARRAY_BYTE_LEN + 1 {
+fn decode_v0(compressed_game: &[u8]) -> Option<Game> {
+    if compressed_game.len() > FLAGS_BYTE_IDX_THREE_PLAYERS {
+        return decode_three_player_game(compressed_game);
+    }
+    if compressed_game.len() < FLAGS_BYTE_IDX + 1 {
         return None;
     }
     // Separate compressed game into sections
@@ -257,11 +590,15 @@ pub(crate) fn decode_game(compressed_game: &[u8]) -> Option<Game> {
     let opponent_hand_size = compressed_game[CARD_ORDER_BYTE_LEN + 1] as usize;
     let grouping_array_bytes = &compressed_game
         [CARD_ORDER_BYTE_LEN + 2..CARD_ORDER_BYTE_LEN + 2 + GROUPING_ARRAY_BYTE_LEN];
-    let me_went_first = *compressed_game.last().unwrap() != 0;
+    let flags = compressed_game[FLAGS_BYTE_IDX];
+    let me_went_first = flags & 1 != 0;
+    let my_bet = bet_from_bits(flags >> 1 & 0b11)?;
+    let opponent_bet = bet_from_bits(flags >> 3 & 0b11)?;
 
     // Recover the big int from the slice and decompress it
     let compressed_card_order = BigUint::from_bytes_be(card_order_bytes);
-    let card_order = decompress_card_order(compressed_card_order)?;
+    let card_order =
+        decompress_card_order(compressed_card_order, DECK_SIZE, DECK_SIZE - HAGGIS_SIZE)?;
 
     let net_hand_size = my_hand_size + opponent_hand_size;
     // Verify the hand sizes make sense
@@ -278,58 +615,149 @@ pub(crate) fn decode_game(compressed_game: &[u8]) -> Option<Game> {
     let grouping_array = u128::from_be_bytes(fixed_grouping_array_bytes);
 
     //create the game struct with the informations given above
-    let mut game = Game {
-        locations: Vec::new(),
-        current_player: if me_went_first {
-            Player::Me
-        } else {
-            Player::Opponent
-        },
-        me_went_first,
-        last_combination_type: None,
-        next_order: 0,
+    let (me, opponent) = if me_went_first {
+        (Player::First, Player::Second)
+    } else {
+        (Player::Second, Player::First)
     };
-    for _ in 0..DECK_SIZE {
-        //default is haggis
-        game.locations.push(Location::Haggis);
-    }
+    let mut bets = [Bet::None; MAX_NUM_PLAYERS];
+    bets[me.index()] = my_bet;
+    bets[opponent.index()] = opponent_bet;
+    let mut game = empty_game(NUM_PLAYERS, me, bets);
 
     //my hand
-    for i in 0..my_hand_size {
-        let card_id = card_order[i];
-        game.locations[card_id] = Location::Hand(Player::Me);
+    for &card_id in &card_order[0..my_hand_size] {
+        game.locations.set(card_id, Location::Hand(me));
     }
 
     //opponents hand
-    for i in my_hand_size..net_hand_size {
-        let card_id = card_order[i];
-        game.locations[card_id] = Location::Hand(Player::Opponent);
+    for &card_id in &card_order[my_hand_size..net_hand_size] {
+        game.locations.set(card_id, Location::Hand(opponent));
     }
 
     //using grouping array to parse cards on the table, also replay the game at the same time
-    let num_cards_on_table = DECK_SIZE - HAGGIS_SIZE - net_hand_size;
+    replay_table(&mut game, &card_order[net_hand_size..], |i, bit| {
+        bit != AFTER_PASS && read_bit_from_grouping_array(&grouping_array, i, bit)
+    })?;
 
-    let mut combination = Vec::new();
-    for grouping_array_idx in 0..num_cards_on_table {
-        let card_id = card_order[net_hand_size + grouping_array_idx];
-        combination.push(card_id);
-        let is_last_card_of_combination =
-            read_bit_from_grouping_array(&grouping_array, grouping_array_idx, 0);
-        let is_last_card_of_combination_group =
-            read_bit_from_grouping_array(&grouping_array, grouping_array_idx, 1);
-        if is_last_card_of_combination {
-            if !game.can_play_cards(&combination) {
-                return None;
+    Some(game)
+}
+
+// Three-player layout:
+// - compressed card order (30 bytes): the 51 dealt cards in the standard order
+// - hand sizes (3 bytes)
+// - grouping array (51 elements, 3 bits each, 20 bytes): the two-player bits
+//     plus a bit marking combinations that were played right after a pass
+// - bets (1 byte): 2 bits per player
+// - flags (1 byte): number of turns from me to the first player in bits 0-1,
+//     bit 2 is set if the last move was a pass that did not end the
+//     combination group
+// Hands and bets are listed from me in turn order.
+#[cfg(test)]
+fn encode_three_player_game(game: &Game) -> Vec<u8> {
+    let mut card_order = Vec::with_capacity(NUM_DEALT_THREE_PLAYERS);
+    let mut hand_sizes = Vec::with_capacity(MAX_NUM_PLAYERS);
+    let mut bets = 0;
+    for seats_after_me in 0..MAX_NUM_PLAYERS {
+        let player = game.viewer.after(seats_after_me, MAX_NUM_PLAYERS);
+        let hand = game.get_hand(player);
+        hand_sizes.push(hand.len() as u8);
+        card_order.extend(hand.iter().map(|id| id.0));
+        bets |= bet_to_bits(game.bets[player.index()]) << (2 * seats_after_me);
+    }
+
+    let mut grouping_array = [0_u8; GROUPING_ARRAY_BYTE_LEN_THREE_PLAYERS];
+    for (i, (card_id, bits)) in cards_on_table_in_order(game).into_iter().enumerate() {
+        card_order.push(card_id);
+        for (bit, &is_set) in bits.iter().enumerate() {
+            if is_set {
+                let bit_idx = 3 * i + bit;
+                grouping_array[bit_idx / 8] |= 1 << (bit_idx % 8);
             }
-            game.play_cards(&combination);
-            combination = Vec::new();
         }
-        if is_last_card_of_combination_group {
-            if !game.can_play_cards(&Vec::new()) {
-                return None;
-            }
-            game.play_cards(&Vec::new());
+    }
+
+    let turns_to_first_player = (MAX_NUM_PLAYERS - game.viewer.index()) % MAX_NUM_PLAYERS;
+    let flags = turns_to_first_player as u8 | ((game.passes > 0) as u8) << 2;
+
+    assert!(card_order.len() == NUM_DEALT_THREE_PLAYERS);
+    let mut compressed_game = compress_card_order_bytes(
+        &card_order,
+        DECK_SIZE_THREE_PLAYERS,
+        CARD_ORDER_BYTE_LEN_THREE_PLAYERS,
+    );
+    compressed_game.append(&mut hand_sizes);
+    compressed_game.extend_from_slice(&grouping_array);
+    compressed_game.push(bets);
+    compressed_game.push(flags);
+
+    compressed_game
+}
+
+fn decode_three_player_game(compressed_game: &[u8]) -> Option<Game> {
+    if compressed_game.len() != FLAGS_BYTE_IDX_THREE_PLAYERS + 1 {
+        return None;
+    }
+    let card_order_bytes = &compressed_game[0..CARD_ORDER_BYTE_LEN_THREE_PLAYERS];
+    let hand_sizes =
+        &compressed_game[CARD_ORDER_BYTE_LEN_THREE_PLAYERS..GROUPING_ARRAY_IDX_THREE_PLAYERS];
+    let grouping_array =
+        &compressed_game[GROUPING_ARRAY_IDX_THREE_PLAYERS..BETS_BYTE_IDX_THREE_PLAYERS];
+    let bets_byte = compressed_game[BETS_BYTE_IDX_THREE_PLAYERS];
+    let flags = compressed_game[FLAGS_BYTE_IDX_THREE_PLAYERS];
+
+    let turns_to_first_player = (flags & 0b11) as usize;
+    if turns_to_first_player >= MAX_NUM_PLAYERS {
+        return None;
+    }
+    let me = Player::from_index((MAX_NUM_PLAYERS - turns_to_first_player) % MAX_NUM_PLAYERS);
+    let mut bets = [Bet::None; MAX_NUM_PLAYERS];
+    for seats_after_me in 0..MAX_NUM_PLAYERS {
+        let player = me.after(seats_after_me, MAX_NUM_PLAYERS);
+        bets[player.index()] = bet_from_bits(bets_byte >> (2 * seats_after_me) & 0b11)?;
+    }
+    let ends_with_pass = flags & 0b100 != 0;
+
+    let card_order = decompress_card_order(
+        BigUint::from_bytes_be(card_order_bytes),
+        DECK_SIZE_THREE_PLAYERS,
+        NUM_DEALT_THREE_PLAYERS,
+    )?;
+
+    // Verify the hand sizes make sense
+    if hand_sizes
+        .iter()
+        .any(|&size| size as usize > INIT_HAND_SIZE)
+    {
+        return None;
+    }
+    let net_hand_size: usize = hand_sizes.iter().map(|&size| size as usize).sum();
+    if net_hand_size == 0 {
+        return None;
+    }
+
+    let mut game = empty_game(MAX_NUM_PLAYERS, me, bets);
+
+    let mut hand_start = 0;
+    for (seats_after_me, &hand_size) in hand_sizes.iter().enumerate() {
+        let hand_end = hand_start + hand_size as usize;
+        let player = me.after(seats_after_me, MAX_NUM_PLAYERS);
+        for &card_id in &card_order[hand_start..hand_end] {
+            game.locations.set(card_id, Location::Hand(player));
         }
+        hand_start = hand_end;
+    }
+
+    replay_table(&mut game, &card_order[net_hand_size..], |i, bit| {
+        let bit_idx = 3 * i + bit;
+        grouping_array[bit_idx / 8] & 1 << (bit_idx % 8) != 0
+    })?;
+
+    if ends_with_pass {
+        game.try_play_cards(game.current_player, &[]).ok()?;
+        // A pass that ends the combination group would have been stored in
+        // the grouping array instead
+        game.last_combination_type.as_ref()?;
     }
 
     Some(game)
@@ -338,18 +766,35 @@ pub(crate) fn decode_game(compressed_game: &[u8]) -> Option<Game> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::game::card::wildcard_ids;
     use rand::seq::SliceRandom;
     use rand::thread_rng;
 
+    /// Recompute the checksum of a game that a test changed on purpose
+    fn update_checksum(encoded_game: &mut Vec<u8>) {
+        encoded_game.truncate(encoded_game.len() - CHECKSUM_LEN);
+        encoded_game.push(checksum(encoded_game));
+    }
+
+    /// Compress game as version 0, the way earlier versions of the app did
+    fn encode_v0_game(game: &Game) -> Vec<u8> {
+        let mut encoded_game = vec![MAGIC];
+        encoded_game.append(&mut encode_v0(game));
+        encoded_game.push(checksum(&encoded_game));
+        encoded_game
+    }
+
     #[test]
     fn test_card_order_compress_decompress() {
         let mut rng = thread_rng();
         let mut card_order_goal: Vec<usize> = (0..DECK_SIZE).collect();
         card_order_goal.shuffle(&mut rng);
         card_order_goal.truncate(DECK_SIZE - HAGGIS_SIZE);
-        let card_order_result = decompress_card_order(compress_card_order(
-            &card_order_goal[0..(DECK_SIZE - HAGGIS_SIZE)],
-        ));
+        let card_order_result = decompress_card_order(
+            compress_card_order(&card_order_goal[0..(DECK_SIZE - HAGGIS_SIZE)], DECK_SIZE),
+            DECK_SIZE,
+            DECK_SIZE - HAGGIS_SIZE,
+        );
         assert_eq!(&card_order_goal, &card_order_result.unwrap());
     }
 
@@ -367,60 +812,88 @@ mod test {
         assert_eq!(game, my_result.unwrap());
     }
 
-    #[test]
-    fn test_rand_game_encode_decode() {
+    /// A two-player deal that the first player leads
+    fn rand_game() -> Game {
         use Location::*;
         use Player::*;
-        let mut game = Game {
+        Game {
             locations: vec![
-                Hand(Opponent),
+                Hand(Second),
                 Haggis,
-                Hand(Opponent),
-                Hand(Opponent),
+                Hand(Second),
+                Hand(Second),
                 Haggis,
                 Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(First),
                 Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
+                Hand(Second),
+                Hand(First),
+                Hand(Second),
                 Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
                 Haggis,
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
                 Haggis,
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
+                Hand(First),
+                Hand(Second),
+                Hand(Second),
                 Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-            ],
-            current_player: Me,
-            me_went_first: true,
+                Hand(Second),
+                Hand(First),
+                Hand(Second),
+                Hand(Second),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+            ]
+            .into(),
+            current_player: First,
+            num_players: 2,
+            viewer: First,
             last_combination_type: None,
+            last_player: None,
+            passes: 0,
+            first_out: None,
             next_order: 0,
-        };
+            bets: [Bet::None; MAX_NUM_PLAYERS],
+            match_scores: [0; MAX_NUM_PLAYERS],
+            undo: UndoState::default(),
+            match_key: None,
+        }
+    }
+
+    /// rand_game with each wildcard in the hand of the seat it belongs to.
+    /// Version 1 deals the wildcards to their seats, so it can't store
+    /// rand_game itself
+    fn rand_game_with_own_wildcards() -> Game {
+        let mut game = rand_game();
+        for seat in 0..game.num_players {
+            for card_id in wildcard_ids(seat) {
+                game.locations
+                    .set(card_id, Location::Hand(Player::from_index(seat)));
+            }
+        }
+        game
+    }
+
+    #[test]
+    fn test_rand_game_encode_decode() {
+        let mut game = rand_game_with_own_wildcards();
 
         game.play_cards(&vec![11, 12, 13]);
 
@@ -433,60 +906,41 @@ mod test {
         assert_eq!(game, my_result.unwrap());
     }
 
+    #[test]
+    fn test_decode_headerless_game() {
+        let mut game = rand_game();
+        game.play_cards(&[11, 12, 13]);
+        game.play_cards(&[]);
+        game.play_cards(&[10]);
+        game.play_cards(&[6]);
+
+        // The same game as encoded by earlier versions of the app, first by
+        // the first player and then by the second player
+        let first_player_code = [
+            0x02, 0x8d, 0x6c, 0x92, 0xca, 0xa2, 0x74, 0xb7, 0x0e, 0x45, 0x32, 0x3a, 0x5c, 0xdd,
+            0x76, 0xca, 0x39, 0xe2, 0x1c, 0x2f, 0x0d, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x70, 0x01,
+        ];
+        let second_player_code = [
+            0x03, 0xb1, 0xff, 0x6d, 0x4b, 0x7b, 0x8f, 0x9c, 0x14, 0xf3, 0x83, 0x62, 0x1c, 0x3f,
+            0xe4, 0x2a, 0x2b, 0x0f, 0x9c, 0xc4, 0x10, 0x0d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x70, 0x00,
+        ];
+        assert_eq!(decode_game(&first_player_code), Ok(game.clone()));
+        game.viewer = Player::Second;
+        assert_eq!(decode_game(&second_player_code), Ok(game));
+
+        // Headerless games aren't signed
+        let key = MatchKey::from_code("haggis");
+        assert_eq!(
+            decode_game_with_key(&first_player_code, Some(&key)),
+            Err(DecodeError::Unauthenticated)
+        );
+    }
+
     #[test]
     fn test_preserve_last_player_pass() {
-        use Location::*;
-        use Player::*;
-        let mut game = Game {
-            locations: vec![
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Opponent),
-                Haggis,
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-            ],
-            current_player: Me,
-            me_went_first: true,
-            last_combination_type: None,
-            next_order: 0,
-        };
+        let mut game = rand_game_with_own_wildcards();
 
         game.play_cards(&vec![11, 12, 13]);
 
@@ -497,6 +951,265 @@ mod test {
         assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
     }
 
+    #[test]
+    fn test_bets_encode_decode() {
+        let mut game = Game::new();
+        game.declare_bet(Player::First, Bet::Big).unwrap();
+        game.play_cards(&[36]);
+        game.declare_bet(Player::Second, Bet::Little).unwrap();
+        game.play_cards(&[]);
+
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+    }
+
+    #[test]
+    fn test_reject_invalid_bet() {
+        let mut encoded_game = encode_v0_game(&Game::new());
+        encoded_game[HEADER_LEN + FLAGS_BYTE_IDX] |= 0b11 << 1;
+        update_checksum(&mut encoded_game);
+        assert_eq!(decode_game(&encoded_game), Err(DecodeError::Malformed));
+    }
+
+    #[test]
+    fn test_reject_opponent_wildcard_on_table() {
+        // Forge a state where the first player has played the second player's wildcard
+        let mut game = Game::new();
+        game.locations.set(
+            NUM_NORMAL + NUM_WILDCARDS_PER_PLAYER,
+            Location::Table {
+                captured_by: None,
+                order: 0,
+                in_last_combination_before_pass: false,
+                after_pass: false,
+                wildcard_shape: None,
+            },
+        );
+        game.next_order = 1;
+        game.current_player = Player::Second;
+
+        // Version 1 can't store it, since each card it puts on the table
+        // comes from the hand of the player whose turn it is
+        assert_eq!(
+            decode_game(&encode_v0_game(&game)),
+            Err(DecodeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_three_player_encode_decode() {
+        let mut game = Game::new_for_players(3);
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+
+        game.declare_bet(Player::First, Bet::Little).unwrap();
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        // A pass that does not end the combination group
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+
+        game.play_cards(&[53]);
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+
+        game.play_cards(&[]);
+        game.play_cards(&[]);
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+        assert_eq!(
+            encode_v0_game(&game).len(),
+            HEADER_LEN + FLAGS_BYTE_IDX_THREE_PLAYERS + 1 + CHECKSUM_LEN
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_keeps_viewer() {
+        let mut game = Game::new();
+        game.declare_bet(Player::First, Bet::Little).unwrap();
+        game.play_cards(&[36]);
+        game.viewer = Player::Second;
+        game.declare_bet(Player::Second, Bet::Big).unwrap();
+        game.play_cards(&[]);
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+
+        let mut game = Game::new_for_players(3);
+        game.play_cards(&[36]);
+        game.declare_bet(Player::Second, Bet::Big).unwrap();
+        for &viewer in &[Player::First, Player::Second, Player::Third] {
+            game.viewer = viewer;
+            assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_finished_hand_encode_decode() {
+        for &num_players in &[2, 3] {
+            let mut game = Game::new_for_players_with_seed(num_players, 8);
+            while !game.is_game_over() {
+                let legal_moves = game.legal_moves();
+                let card_ids = legal_moves
+                    .iter()
+                    .max_by_key(|card_ids| card_ids.len())
+                    .unwrap();
+                game.play_cards(card_ids);
+                assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_three_player_card_order_fits() {
+        // The largest compressed card order is 54 * 53 * ... * 4 - 1
+        let mut max_compressed = BigUint::from(1_u32);
+        for card_possibilities in (SET_ASIDE_SIZE_THREE_PLAYERS + 1)..=DECK_SIZE_THREE_PLAYERS {
+            max_compressed *= card_possibilities;
+        }
+        max_compressed -= 1_u32;
+        assert!(max_compressed.to_bytes_be().len() <= CARD_ORDER_BYTE_LEN_THREE_PLAYERS);
+    }
+
+    /// Swap card_id with a card in my hand
+    fn give_to_me(game: &mut Game, card_id: usize) {
+        let my_card = game.get_hand(Player::First)[0].0;
+        game.locations.swap(card_id, my_card);
+    }
+
+    #[test]
+    fn test_wildcard_shape_encode_decode() {
+        let mut game = Game::new();
+        give_to_me(&mut game, 32);
+        let mut undeclared_game = game.clone();
+
+        // 7♣ J Q as three 7s
+        game.try_play_cards_with_shape(
+            Player::First,
+            &[32, 36, 37],
+            Some(WildcardShape::MoreSuits),
+        )
+        .unwrap();
+        assert_eq!(game, decode_game(&encode_game(&game)).unwrap());
+
+        undeclared_game.play_cards(&[32, 36, 37]);
+        let decoded_game = decode_game(&encode_game(&undeclared_game)).unwrap();
+        assert_eq!(undeclared_game, decoded_game);
+        assert_ne!(game, decoded_game);
+    }
+
+    #[test]
+    fn test_reject_shape_of_unambiguous_combination() {
+        // 7♣ J is a pair, which can only be played one way
+        let mut game = Game::new();
+        give_to_me(&mut game, 32);
+        game.play_cards(&[32, 36]);
+        let mut encoded_game = encode_v0_game(&game);
+        assert!(decode_game(&encoded_game).is_ok());
+
+        // Mark the first card on the table as declaring a shape. Bit 1 of the
+        // grouping array is in the last byte of the grouping array.
+        encoded_game[HEADER_LEN + CARD_ORDER_BYTE_LEN + 2 + GROUPING_ARRAY_BYTE_LEN - 1] |= 0b10;
+        update_checksum(&mut encoded_game);
+        assert_eq!(decode_game(&encoded_game), Err(DecodeError::Malformed));
+    }
+
+    #[test]
+    fn test_v0_games_still_decode() {
+        let mut game = Game::new();
+        give_to_me(&mut game, 32);
+        game.declare_bet(Player::First, Bet::Big).unwrap();
+        game.try_play_cards_with_shape(
+            Player::First,
+            &[32, 36, 37],
+            Some(WildcardShape::MoreSuits),
+        )
+        .unwrap();
+        game.play_cards(&[]);
+        assert_eq!(decode_game(&encode_v0_game(&game)), Ok(game));
+
+        let mut game = Game::new_for_players(3);
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        assert_eq!(decode_game(&encode_v0_game(&game)), Ok(game));
+    }
+
+    #[test]
+    fn test_header() {
+        let mut encoded_game = encode_game(&Game::new());
+        assert_eq!(encoded_game[0], MAGIC | VERSION);
+
+        encoded_game[0] = MAGIC | (VERSION + 1);
+        assert_eq!(
+            decode_game(&encoded_game),
+            Err(DecodeError::UnsupportedVersion)
+        );
+
+        // Data without our header is not a game, unless it is a headerless
+        // game as sent by earlier versions of the app
+        assert_eq!(
+            decode_game(&encoded_game[HEADER_LEN..]),
+            Err(DecodeError::NotAGame)
+        );
+        assert_eq!(decode_game(&[]), Err(DecodeError::NotAGame));
+        let game = Game::new_with_seed(5);
+        let headerless_game = encode_v0(&game);
+        assert_eq!(headerless_game.len(), HEADERLESS_LEN);
+        assert_eq!(decode_game(&headerless_game), Ok(game));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut game = Game::new_for_players_with_seed(2, 7);
+        game.play_cards(&game.legal_moves()[0].clone());
+        let encoded_game = encode_game(&game);
+        assert!(encoded_game.len() * 8 <= 272);
+
+        // Every single-bit error is caught, including in the checksum itself
+        for bit in 8 * HEADER_LEN..8 * encoded_game.len() {
+            let mut damaged = encoded_game.clone();
+            damaged[bit / 8] ^= 1 << (bit % 8);
+            assert_eq!(decode_game(&damaged), Err(DecodeError::ChecksumMismatch));
+        }
+
+        let mut truncated = encoded_game.clone();
+        truncated.pop();
+        assert_eq!(decode_game(&truncated), Err(DecodeError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn test_authenticated_game() {
+        let mut game = Game::new_for_players_with_seed(2, 7);
+        game.play_cards(&game.legal_moves()[0].clone());
+        let unsigned_game = encode_game(&game);
+        let key = MatchKey::from_code("correct horse");
+        game.match_key = Some(key.clone());
+        let signed_game = encode_game(&game);
+        assert_eq!(
+            signed_game.len(),
+            unsigned_game.len() - CHECKSUM_LEN + MAC_LEN
+        );
+        assert!(signed_game.len() * 8 <= 272);
+
+        let decoded_game = decode_game_with_key(&signed_game, Some(&key)).unwrap();
+        game.match_key = None;
+        assert_eq!(decoded_game, game);
+
+        // A signed game needs the right key, and a key needs a signed game
+        let other_key = MatchKey::from_code("battery staple");
+        for &(data, key) in &[
+            (&signed_game, Some(&other_key)),
+            (&signed_game, None),
+            (&unsigned_game, Some(&key)),
+        ] {
+            assert_eq!(
+                decode_game_with_key(data, key),
+                Err(DecodeError::Unauthenticated)
+            );
+        }
+
+        // A changed bit breaks the MAC
+        let mut forged_game = signed_game.clone();
+        forged_game[HEADER_LEN] ^= 1;
+        assert_eq!(
+            decode_game_with_key(&forged_game, Some(&key)),
+            Err(DecodeError::Unauthenticated)
+        );
+    }
+
     #[test]
     fn test_n_choose_k() {
         assert_eq!(3796297200, n_choose_k(36, 14));
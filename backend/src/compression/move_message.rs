@@ -0,0 +1,240 @@
+use sha2::{Digest, Sha256};
+
+use super::v1::{shape_from_digit, shape_to_digit};
+use super::{checksum, encode_game, mac, CHECKSUM_LEN, HEADER_LEN, MAC_LEN, VERSION_MASK};
+use crate::game::{
+    card::CardId, error::DecodeError, game_match::MatchKey, history::Move, player::Player, Game,
+};
+
+// A move message carries a single move instead of the whole game, for players
+// who already have the game from before the move. It starts with a header byte
+// like a compressed game, but with its own magic, followed by:
+// - the state hash of the game before the move
+// - the shape declared for the combination (0 for none, like in version 1)
+// - the id of each card played, one byte each, with no cards for a pass
+// - the MAC of everything before it, if the game has a match key
+// and ends with the same checksum as a compressed game. Like a compressed
+// game, a signed move message has its own magic.
+//
+// A player whose game doesn't match the state hash can't apply the move, so
+// they send a resync request, which is only a header and a checksum, and the
+// other player answers with the whole game.
+//
+// Before the first game of a match, the two players can send each other a key
+// share, which is a header, the share and a checksum. See KeyExchange.
+const MOVE_MAGIC: u8 = 0xB0;
+const AUTHENTICATED_MOVE_MAGIC: u8 = 0xD0;
+const RESYNC_MAGIC: u8 = 0xC0;
+const KEY_SHARE_MAGIC: u8 = 0xE0;
+const MOVE_VERSION: u8 = 0;
+pub(crate) const STATE_HASH_LEN: usize = 8;
+pub(crate) const KEY_SHARE_LEN: usize = 32;
+
+/// The first bytes of the SHA-256 of the compressed game, as seen from the
+/// first seat and without a signature so that every player gets the same hash
+pub(crate) fn state_hash(game: &Game) -> [u8; STATE_HASH_LEN] {
+    let mut game = game.clone();
+    game.viewer = Player::First;
+    game.match_key = None;
+    let digest = Sha256::digest(&encode_game(&game));
+    let mut hash = [0; STATE_HASH_LEN];
+    hash.copy_from_slice(&digest[..STATE_HASH_LEN]);
+    hash
+}
+
+/// Write played_move, which was played in game_before, signed with the match
+/// key of game_before if it has one
+pub(crate) fn encode_move(game_before: &Game, played_move: &Move) -> Vec<u8> {
+    let magic = match game_before.match_key {
+        Some(_) => AUTHENTICATED_MOVE_MAGIC,
+        None => MOVE_MAGIC,
+    };
+    let mut message = vec![magic | MOVE_VERSION];
+    message.extend_from_slice(&state_hash(game_before));
+    let (card_ids, shape): (&[CardId], _) = match played_move {
+        Move::Pass => (&[], None),
+        Move::Play(card_ids) => (card_ids, None),
+        Move::PlayWithShape(card_ids, shape) => (card_ids, Some(*shape)),
+    };
+    message.push(shape_to_digit(shape) as u8);
+    message.extend(card_ids.iter().map(|card_id| card_id.0 as u8));
+    if let Some(key) = &game_before.match_key {
+        message.extend_from_slice(&mac(key, &message));
+    }
+    message.push(checksum(&message));
+    message
+}
+
+/// Read a move message into the state hash of the game it was played in and
+/// the move. The message must be signed with key if there is one.
+pub(crate) fn decode_move(
+    message: &[u8],
+    key: Option<&MatchKey>,
+) -> Result<([u8; STATE_HASH_LEN], Move), DecodeError> {
+    let (magic, other_magic) = match key {
+        Some(_) => (AUTHENTICATED_MOVE_MAGIC, MOVE_MAGIC),
+        None => (MOVE_MAGIC, AUTHENTICATED_MOVE_MAGIC),
+    };
+    let mut body = match check_frame(message, magic) {
+        Err(DecodeError::NotAGame) if check_frame(message, other_magic).is_ok() => {
+            return Err(DecodeError::Unauthenticated)
+        }
+        result => result?,
+    };
+    if let Some(key) = key {
+        if body.len() < MAC_LEN {
+            return Err(DecodeError::Malformed);
+        }
+        let (signed, mac_bytes) = body.split_at(body.len() - MAC_LEN);
+        if mac(key, &message[..HEADER_LEN + signed.len()]) != mac_bytes {
+            return Err(DecodeError::Unauthenticated);
+        }
+        body = signed;
+    }
+    if body.len() < STATE_HASH_LEN + 1 {
+        return Err(DecodeError::Malformed);
+    }
+    let (hash_bytes, body) = body.split_at(STATE_HASH_LEN);
+    let mut hash = [0; STATE_HASH_LEN];
+    hash.copy_from_slice(hash_bytes);
+
+    let card_ids: Vec<CardId> = body[1..].iter().map(|&id| CardId(id as usize)).collect();
+    let played_move = match (body[0], shape_from_digit(body[0] as usize)) {
+        (0, _) if card_ids.is_empty() => Move::Pass,
+        (0, _) => Move::Play(card_ids),
+        (_, Some(shape)) if !card_ids.is_empty() => Move::PlayWithShape(card_ids, shape),
+        _ => return Err(DecodeError::Malformed),
+    };
+    Ok((hash, played_move))
+}
+
+pub(crate) fn resync_request() -> Vec<u8> {
+    let mut message = vec![RESYNC_MAGIC | MOVE_VERSION];
+    message.push(checksum(&message));
+    message
+}
+
+pub(crate) fn is_resync_request(message: &[u8]) -> bool {
+    matches!(check_frame(message, RESYNC_MAGIC), Ok(body) if body.is_empty())
+}
+
+pub(crate) fn encode_key_share(share: &[u8; KEY_SHARE_LEN]) -> Vec<u8> {
+    let mut message = vec![KEY_SHARE_MAGIC | MOVE_VERSION];
+    message.extend_from_slice(share);
+    message.push(checksum(&message));
+    message
+}
+
+/// The share in message, if it is a key share
+pub(crate) fn decode_key_share(message: &[u8]) -> Option<[u8; KEY_SHARE_LEN]> {
+    let body = check_frame(message, KEY_SHARE_MAGIC).ok()?;
+    if body.len() != KEY_SHARE_LEN {
+        return None;
+    }
+    let mut share = [0; KEY_SHARE_LEN];
+    share.copy_from_slice(body);
+    Some(share)
+}
+
+/// The part of message between its header and its checksum
+fn check_frame(message: &[u8], magic: u8) -> Result<&[u8], DecodeError> {
+    if message.len() < HEADER_LEN || message[0] & !VERSION_MASK != magic {
+        return Err(DecodeError::NotAGame);
+    }
+    if message[0] & VERSION_MASK > MOVE_VERSION {
+        return Err(DecodeError::UnsupportedVersion);
+    }
+    if message.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(DecodeError::Malformed);
+    }
+    let (body, checksum_bytes) = message.split_at(message.len() - CHECKSUM_LEN);
+    if checksum(body) != checksum_bytes[0] {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+    Ok(&body[HEADER_LEN..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compression::decode_game;
+    use crate::game::wildcard_shape::WildcardShape;
+
+    #[test]
+    fn test_encode_decode_move() {
+        let game = Game::new_with_seed(2);
+        let moves = vec![
+            Move::Pass,
+            Move::Play(vec![CardId(3)]),
+            Move::PlayWithShape(
+                vec![CardId(3), CardId(36), CardId(37)],
+                WildcardShape::MoreRanks,
+            ),
+        ];
+        for played_move in moves {
+            let message = encode_move(&game, &played_move);
+            assert_eq!(
+                decode_move(&message, None),
+                Ok((state_hash(&game), played_move))
+            );
+            // A move message is not a game
+            assert_eq!(decode_game(&message), Err(DecodeError::NotAGame));
+        }
+    }
+
+    #[test]
+    fn test_state_hash_is_the_same_for_every_viewer() {
+        let mut game = Game::new_with_seed(2);
+        let hash = state_hash(&game);
+        game.viewer = Player::Second;
+        assert_eq!(state_hash(&game), hash);
+        game.play_cards(&[36]);
+        assert_ne!(state_hash(&game), hash);
+    }
+
+    #[test]
+    fn test_reject_damaged_move() {
+        let game = Game::new_with_seed(2);
+        let message = encode_move(&game, &Move::Play(vec![CardId(3)]));
+        for i in HEADER_LEN..message.len() {
+            let mut damaged = message.clone();
+            damaged[i] ^= 1;
+            assert_eq!(
+                decode_move(&damaged, None),
+                Err(DecodeError::ChecksumMismatch)
+            );
+        }
+
+        // A shape needs cards to apply to
+        let mut message = encode_move(&game, &Move::Pass);
+        let shape_idx = HEADER_LEN + STATE_HASH_LEN;
+        message[shape_idx] = shape_to_digit(Some(WildcardShape::MoreSuits)) as u8;
+        message.pop();
+        message.push(checksum(&message));
+        assert_eq!(decode_move(&message, None), Err(DecodeError::Malformed));
+    }
+
+    #[test]
+    fn test_resync_request() {
+        assert!(is_resync_request(&resync_request()));
+        let game = Game::new_with_seed(2);
+        assert!(!is_resync_request(&encode_move(&game, &Move::Pass)));
+        assert!(!is_resync_request(&encode_game(&game)));
+    }
+
+    #[test]
+    fn test_key_share() {
+        let share = [7; KEY_SHARE_LEN];
+        let message = encode_key_share(&share);
+        assert_eq!(decode_key_share(&message), Some(share));
+        assert!(!is_resync_request(&message));
+        assert_eq!(decode_move(&message, None), Err(DecodeError::NotAGame));
+
+        let game = Game::new_with_seed(2);
+        assert_eq!(decode_key_share(&resync_request()), None);
+        assert_eq!(decode_key_share(&encode_game(&game)), None);
+        let mut damaged = message;
+        damaged[1] ^= 1;
+        assert_eq!(decode_key_share(&damaged), None);
+    }
+}
@@ -0,0 +1,100 @@
+// Compressed games can also be written as text, for links and chat messages.
+// The bytes are written in Base45 (RFC 9285), which only uses the characters
+// of the alphanumeric mode of qr codes, and the text starts with PREFIX so
+// that other text isn't mistaken for a game. Base45
+// text in alphanumeric mode takes a few more bits than the same bytes in byte
+// mode, so our own qr codes still hold bytes.
+//
+// Every 2 bytes are read as a number below 45^3 and written as 3 digits, least
+// significant first. A byte left over at the end is written as 2 digits.
+pub(crate) const PREFIX: &str = "HAGGIS:";
+const ALPHABET: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+pub(crate) fn encode_text(bytes: &[u8]) -> String {
+    let mut text = String::from(PREFIX);
+    for chunk in bytes.chunks(2) {
+        let (mut number, num_digits) = match chunk {
+            [high, low] => ((*high as usize) << 8 | *low as usize, 3),
+            [byte] => (*byte as usize, 2),
+            _ => unreachable!(),
+        };
+        for _ in 0..num_digits {
+            text.push(ALPHABET[number % 45] as char);
+            number /= 45;
+        }
+    }
+    text
+}
+
+/// Read text written by encode_text. Letters can be in either case, and whitespace
+/// around the text is ignored. Returns None if the text isn't a game.
+pub(crate) fn decode_text(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim().to_ascii_uppercase();
+    let digits = text
+        .strip_prefix(PREFIX)?
+        .bytes()
+        .map(|character| ALPHABET.iter().position(|&digit| digit == character))
+        .collect::<Option<Vec<usize>>>()?;
+
+    let mut bytes = Vec::with_capacity(digits.len() * 2 / 3);
+    for chunk in digits.chunks(3) {
+        let number = chunk
+            .iter()
+            .rev()
+            .fold(0, |number, digit| number * 45 + digit);
+        match chunk.len() {
+            3 if number <= 0xFFFF => bytes.extend_from_slice(&[(number >> 8) as u8, number as u8]),
+            2 if number <= 0xFF => bytes.push(number as u8),
+            _ => return None,
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rfc_examples() {
+        // The examples from RFC 9285, section 4.3
+        assert_eq!(encode_text(b"AB"), format!("{}BB8", PREFIX));
+        assert_eq!(encode_text(b"Hello!!"), format!("{}%69 VD92EX0", PREFIX));
+        assert_eq!(encode_text(b"base-45"), format!("{}UJCLQE7W581", PREFIX));
+        assert_eq!(
+            decode_text(&format!("{}QED8WEX0", PREFIX)),
+            Some(b"ietf!".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                decode_text(&encode_text(&bytes[..len])),
+                Some(bytes[..len].to_vec())
+            );
+        }
+        let text = encode_text(&[0xFF, 0xFF, 0xFF]);
+        assert_eq!(
+            decode_text(&format!("  {}\n", text.to_lowercase())),
+            Some(vec![0xFF; 3])
+        );
+    }
+
+    #[test]
+    fn test_reject_bad_text() {
+        // No prefix, a character outside the alphabet, a leftover digit, and
+        // numbers too large for their bytes
+        for &text in &[
+            "BB8",
+            "HAGGIS:BB8=",
+            "HAGGIS:BB8B",
+            "HAGGIS:GGW",
+            "HAGGIS:::",
+        ] {
+            assert_eq!(decode_text(text), None, "{}", text);
+        }
+    }
+}
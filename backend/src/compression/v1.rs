@@ -0,0 +1,561 @@
+use num_bigint::BigUint;
+
+use super::{
+    bet_from_bits, bet_to_bits, cards_on_table_in_order, empty_game, END_OF_COMBINATION,
+    END_OF_COMBINATION_GROUP, NUM_DEALT_THREE_PLAYERS,
+};
+use crate::game::{
+    bet::Bet,
+    card::{normal_card_ids, wildcard_ids, CardId},
+    constant::*,
+    history::{History, Move},
+    location::Location,
+    player::Player,
+    wildcard_shape::WildcardShape,
+    Game,
+};
+
+// Version 1 stores the same information as version 0, and the scores of the
+// match, as digits of a single mixed-radix number instead of byte-aligned
+// fields, so no field wastes the values its bits can't take. Digits that only
+// some games need, like the shape of a combination, are only stored by those
+// games. The decoder knows whether a digit is there, and its radix, from the
+// digits it has already read.
+//
+// Instead of the order of every dealt card, version 1 stores the deal and then
+// each card on the table as one of the cards left in the hand of the player
+// who played it. The decoder replays the combinations as it reads them, so it
+// knows whose turn it is, and the hand sizes follow from the replay.
+//
+// Digits, from least to most significant:
+// - the number of turns from me to the first player (radix: number of players)
+// - the bet of each player, from me in turn order (radix 3 each)
+// - the score of each player in the match before this hand, from me in turn
+//     order (radix: four times the target score each)
+// - the normal cards dealt to each seat, in seat order, as one of the sets of
+//     14 cards that can be picked from the normal cards that no earlier seat
+//     was dealt (radix: the number of those sets). The other normal cards are
+//     the Haggis, or set aside with three players.
+// - the number of cards on the table (radix: the number of dealt cards)
+// - for each card on the table, in the order it was played:
+//     - with three players, if it starts a combination that doesn't start its
+//         group, whether a player passed right before it (radix 2)
+//     - its position among the cards in the hand of the player who played it,
+//         leaving out the cards before it in the same combination (radix: the
+//         number of those cards)
+//     - whether it continues the combination, ends it, or ends the
+//         combination group too (radix 3, or radix 2 for the last card on the
+//         table, which always ends a combination)
+//     - if it ends a combination that has at least 2 wildcards, the shape that
+//         was declared: none, more ranks or more suits (radix 3). Each player
+//         has 3 wildcards, so there is at most one of these per player.
+// - with three players, if the last combination did not end its group,
+//     whether the last move was a pass (radix 2)
+//
+// The payload is the number as big-endian bytes, padded to a fixed length for
+// each number of players, which is how the decoder tells them apart.
+
+// A two-player game takes at most 231 bits, which leaves room for the header
+// and a checksum or a MAC within the 272-bit budget, as compression.rs
+// explains. A third hand, the bigger deck, the passes between combinations and
+// a third score take the largest three-player game to 397 bits, so
+// three-player games are the one exception and need a larger qr code.
+
+/// Bytes needed by the largest two-player game, which is under 232 bits
+const PAYLOAD_LEN: usize = 29;
+/// Bytes needed by the largest three-player game, which is under 400 bits
+const PAYLOAD_LEN_THREE_PLAYERS: usize = 50;
+
+const NUM_BETS: usize = 3;
+
+// What a card on the table says about the combination it is in
+const CONTINUE_COMBINATION: usize = 0;
+const END_COMBINATION: usize = 1;
+const END_GROUP: usize = 2;
+const NUM_CARD_ENDINGS: usize = 3;
+
+const NUM_SHAPES: usize = 3;
+/// The fewest wildcards a combination needs to have a declared shape
+const MIN_WILDCARDS_FOR_SHAPE: usize = 2;
+
+/// The digits of a mixed-radix number, from least to most significant
+#[derive(Default)]
+struct Digits(Vec<(BigUint, BigUint)>);
+
+impl Digits {
+    fn push(&mut self, digit: usize, radix: usize) {
+        assert!(digit < radix);
+        self.push_big(BigUint::from(digit), BigUint::from(radix));
+    }
+
+    fn push_big(&mut self, digit: BigUint, radix: BigUint) {
+        self.0.push((digit, radix));
+    }
+
+    fn to_number(&self) -> BigUint {
+        self.0
+            .iter()
+            .rev()
+            .fold(BigUint::from(0_u32), |number, (digit, radix)| {
+                number * radix + digit
+            })
+    }
+}
+
+/// Reads the digits of a mixed-radix number, from least to most significant
+struct DigitReader(BigUint);
+
+impl DigitReader {
+    fn read(&mut self, radix: usize) -> usize {
+        let digit = self.read_big(&BigUint::from(radix)).to_u32_digits();
+        digit.first().map_or(0, |&digit| digit as usize)
+    }
+
+    fn read_big(&mut self, radix: &BigUint) -> BigUint {
+        let digit = &self.0 % radix;
+        self.0 /= radix;
+        digit
+    }
+
+    /// Whether every digit has been read, so no data was left over
+    fn is_done(&self) -> bool {
+        self.0 == BigUint::from(0_u32)
+    }
+}
+
+fn num_dealt(num_players: usize) -> usize {
+    if num_players == NUM_PLAYERS {
+        DECK_SIZE - HAGGIS_SIZE
+    } else {
+        NUM_DEALT_THREE_PLAYERS
+    }
+}
+
+fn payload_len(num_players: usize) -> usize {
+    if num_players == NUM_PLAYERS {
+        PAYLOAD_LEN
+    } else {
+        PAYLOAD_LEN_THREE_PLAYERS
+    }
+}
+
+/// Match scores are stored up to this total. Before a hand, the totals are
+/// below the target score unless they are tied, and no hand scores as much as
+/// the target, so only a run of tied hands can reach it. Larger totals are
+/// stored as the largest.
+fn score_radix(num_players: usize) -> usize {
+    let target_score = if num_players == NUM_PLAYERS {
+        TARGET_SCORE
+    } else {
+        TARGET_SCORE_THREE_PLAYERS
+    };
+    4 * target_score
+}
+
+/// The number of ways to pick k cards out of n
+fn num_subsets(n: usize, k: usize) -> BigUint {
+    if k > n {
+        return BigUint::from(0_u32);
+    }
+    (0..k).fold(BigUint::from(1_u32), |count, i| count * (n - i) / (i + 1))
+}
+
+/// The number of a set of positions among the sets of the same size, in the
+/// combinatorial number system. positions must be sorted.
+fn subset_to_number(positions: &[usize]) -> BigUint {
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| num_subsets(position, i + 1))
+        .sum()
+}
+
+/// The sorted positions of the set of size positions with the given number
+fn subset_from_number(mut number: BigUint, size: usize) -> Vec<usize> {
+    let mut positions = vec![0; size];
+    for i in (0..size).rev() {
+        let mut position = i;
+        while num_subsets(position + 1, i + 1) <= number {
+            position += 1;
+        }
+        number -= num_subsets(position, i + 1);
+        positions[i] = position;
+    }
+    positions
+}
+
+pub(super) fn shape_to_digit(shape: Option<WildcardShape>) -> usize {
+    match shape {
+        None => 0,
+        Some(WildcardShape::MoreRanks) => 1,
+        Some(WildcardShape::MoreSuits) => 2,
+    }
+}
+
+pub(super) fn shape_from_digit(digit: usize) -> Option<WildcardShape> {
+    match digit {
+        1 => Some(WildcardShape::MoreRanks),
+        2 => Some(WildcardShape::MoreSuits),
+        _ => None,
+    }
+}
+
+fn num_wildcards(combination: &[CardId]) -> usize {
+    combination
+        .iter()
+        .filter(|card_id| card_id.wildcard_seat().is_some())
+        .count()
+}
+
+/// The normal cards that seat was dealt, as positions in undealt, which are
+/// taken out of undealt
+fn take_dealt_cards(deal: &Game, seat: Player, undealt: &mut Vec<usize>) -> Vec<usize> {
+    let positions: Vec<usize> = (0..undealt.len())
+        .filter(|&position| deal.locations[undealt[position]] == Location::Hand(seat))
+        .collect();
+    for &position in positions.iter().rev() {
+        undealt.remove(position);
+    }
+    positions
+}
+
+/// The cards in the hand of the player whose turn it is, without the cards of
+/// the combination they are playing
+fn cards_to_play(game: &Game, combination: &[CardId]) -> Vec<CardId> {
+    let mut cards = game.get_hand(game.current_player);
+    cards.retain(|card_id| !combination.contains(card_id));
+    cards
+}
+
+/// Play combination with shape, and end the combination group after it if
+/// ends_group. Returns None if the move is not legal.
+fn replay_combination(
+    game: &mut Game,
+    combination: Vec<CardId>,
+    shape: Option<WildcardShape>,
+    ends_group: bool,
+) -> Option<()> {
+    let played_move = match shape {
+        Some(shape) => Move::PlayWithShape(combination, shape),
+        None => Move::Play(combination),
+    };
+    game.play_move(&played_move).ok()?;
+    if ends_group {
+        // The last combination group is captured when the hand is
+        // finished, without anyone passing
+        game.finish_hand();
+        // With three players, everyone else has to pass to end the group
+        while game.last_combination_type.is_some() {
+            game.play_move(&Move::Pass).ok()?;
+        }
+    }
+    Some(())
+}
+
+pub(super) fn encode(game: &Game) -> Vec<u8> {
+    let num_players = game.num_players;
+    let mut digits = Digits::default();
+
+    let turns_to_first_player = (num_players - game.viewer.index()) % num_players;
+    digits.push(turns_to_first_player, num_players);
+    let players: Vec<Player> = (0..num_players)
+        .map(|seats_after_me| game.viewer.after(seats_after_me, num_players))
+        .collect();
+    for player in &players {
+        digits.push(bet_to_bits(game.bets[player.index()]) as usize, NUM_BETS);
+    }
+    let score_radix = score_radix(num_players);
+    for player in &players {
+        let score = game.match_scores[player.index()].min(score_radix - 1);
+        digits.push(score, score_radix);
+    }
+
+    let deal = History::from_game(game)
+        .expect("encode: the game can't be replayed from a deal")
+        .deal;
+    let mut undealt = normal_card_ids(num_players);
+    for seat in 0..num_players {
+        let radix = num_subsets(undealt.len(), INIT_HAND_SIZE_WO_WILDCARD);
+        let positions = take_dealt_cards(&deal, Player::from_index(seat), &mut undealt);
+        digits.push_big(subset_to_number(&positions), radix);
+    }
+
+    let cards_on_table = cards_on_table_in_order(game);
+    digits.push(cards_on_table.len(), num_dealt(num_players));
+    let mut replay = deal;
+    let mut combination = Vec::new();
+    let mut group_has_combination = false;
+    for (i, &(card_id, bits)) in cards_on_table.iter().enumerate() {
+        let (after_pass, wildcard_shape) = match game.locations[card_id] {
+            Location::Table {
+                after_pass,
+                wildcard_shape,
+                ..
+            } => (after_pass, wildcard_shape),
+            _ => unreachable!(),
+        };
+        if combination.is_empty() && num_players == MAX_NUM_PLAYERS && group_has_combination {
+            digits.push(after_pass as usize, 2);
+            if after_pass {
+                replay.play_move(&Move::Pass).expect("encode: illegal pass");
+            }
+        }
+        let cards = cards_to_play(&replay, &combination);
+        let position = cards
+            .iter()
+            .position(|&other| other == CardId(card_id))
+            .expect("encode: card played from another hand");
+        digits.push(position, cards.len());
+        combination.push(CardId(card_id));
+
+        let ending = if !bits[END_OF_COMBINATION] {
+            CONTINUE_COMBINATION
+        } else if bits[END_OF_COMBINATION_GROUP] {
+            END_GROUP
+        } else {
+            END_COMBINATION
+        };
+        if i + 1 == cards_on_table.len() {
+            digits.push(ending - END_COMBINATION, NUM_CARD_ENDINGS - END_COMBINATION);
+        } else {
+            digits.push(ending, NUM_CARD_ENDINGS);
+        }
+        if ending == CONTINUE_COMBINATION {
+            continue;
+        }
+
+        if num_wildcards(&combination) >= MIN_WILDCARDS_FOR_SHAPE {
+            digits.push(shape_to_digit(wildcard_shape), NUM_SHAPES);
+        }
+        group_has_combination = ending != END_GROUP;
+        let combination = std::mem::take(&mut combination);
+        replay_combination(
+            &mut replay,
+            combination,
+            wildcard_shape,
+            !group_has_combination,
+        )
+        .expect("encode: illegal combination");
+    }
+
+    if num_players == MAX_NUM_PLAYERS && group_has_combination {
+        digits.push((game.passes > 0) as usize, 2);
+    }
+
+    let mut payload = digits.to_number().to_bytes_be();
+    let payload_len = payload_len(num_players);
+    assert!(payload.len() <= payload_len);
+    while payload.len() < payload_len {
+        payload.insert(0, 0);
+    }
+    payload
+}
+
+pub(super) fn decode(payload: &[u8]) -> Option<Game> {
+    let num_players = match payload.len() {
+        PAYLOAD_LEN => NUM_PLAYERS,
+        PAYLOAD_LEN_THREE_PLAYERS => MAX_NUM_PLAYERS,
+        _ => return None,
+    };
+    let mut digits = DigitReader(BigUint::from_bytes_be(payload));
+
+    let turns_to_first_player = digits.read(num_players);
+    let me = Player::from_index((num_players - turns_to_first_player) % num_players);
+    let players: Vec<Player> = (0..num_players)
+        .map(|seats_after_me| me.after(seats_after_me, num_players))
+        .collect();
+    let mut bets = [Bet::None; MAX_NUM_PLAYERS];
+    for player in &players {
+        bets[player.index()] = bet_from_bits(digits.read(NUM_BETS) as u8)?;
+    }
+    let mut game = empty_game(num_players, me, bets);
+    let score_radix = score_radix(num_players);
+    for player in &players {
+        game.match_scores[player.index()] = digits.read(score_radix);
+    }
+
+    let mut undealt = normal_card_ids(num_players);
+    for seat in 0..num_players {
+        let radix = num_subsets(undealt.len(), INIT_HAND_SIZE_WO_WILDCARD);
+        let number = digits.read_big(&radix);
+        let positions = subset_from_number(number, INIT_HAND_SIZE_WO_WILDCARD);
+        let player = Player::from_index(seat);
+        for &position in positions.iter().rev() {
+            game.locations
+                .set(undealt.remove(position), Location::Hand(player));
+        }
+        for card_id in wildcard_ids(seat) {
+            game.locations.set(card_id, Location::Hand(player));
+        }
+    }
+
+    let num_cards_on_table = digits.read(num_dealt(num_players));
+    let mut combination = Vec::new();
+    let mut group_has_combination = false;
+    for i in 0..num_cards_on_table {
+        if combination.is_empty()
+            && num_players == MAX_NUM_PLAYERS
+            && group_has_combination
+            && digits.read(2) == 1
+        {
+            game.play_move(&Move::Pass).ok()?;
+        }
+        let cards = cards_to_play(&game, &combination);
+        if cards.is_empty() {
+            return None;
+        }
+        combination.push(cards[digits.read(cards.len())]);
+
+        let ending = if i + 1 == num_cards_on_table {
+            END_COMBINATION + digits.read(NUM_CARD_ENDINGS - END_COMBINATION)
+        } else {
+            digits.read(NUM_CARD_ENDINGS)
+        };
+        if ending == CONTINUE_COMBINATION {
+            continue;
+        }
+
+        let shape = if num_wildcards(&combination) >= MIN_WILDCARDS_FOR_SHAPE {
+            shape_from_digit(digits.read(NUM_SHAPES))
+        } else {
+            None
+        };
+        group_has_combination = ending != END_GROUP;
+        // The replay rejects combinations that can't be played, and shapes
+        // declared for combinations that can only be played one way
+        let combination = std::mem::take(&mut combination);
+        replay_combination(&mut game, combination, shape, !group_has_combination)?;
+    }
+
+    if num_players == MAX_NUM_PLAYERS && group_has_combination && digits.read(2) == 1 {
+        game.play_move(&Move::Pass).ok()?;
+        // A pass that ends the combination group would have been stored as
+        // the ending of the last card instead
+        game.last_combination_type.as_ref()?;
+    }
+
+    if digits.is_done() {
+        Some(game)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compression::{decode_game, encode_game, CHECKSUM_LEN, HEADER_LEN, MAC_LEN};
+    use crate::game::game_match::MatchKey;
+
+    /// The product of the largest radix of every digit that a game can store,
+    /// which is larger than any number encode stores
+    fn max_number(num_players: usize) -> BigUint {
+        // The hand is over before the last player plays their last card
+        let max_table_size = num_dealt(num_players) - 1;
+        let mut radixes = vec![
+            BigUint::from(num_players),
+            BigUint::from(NUM_BETS).pow(num_players as u32),
+            BigUint::from(score_radix(num_players)).pow(num_players as u32),
+            (0..num_players)
+                .map(|seat| {
+                    let num_undealt = normal_card_ids(num_players).len();
+                    num_subsets(
+                        num_undealt - seat * INIT_HAND_SIZE_WO_WILDCARD,
+                        INIT_HAND_SIZE_WO_WILDCARD,
+                    )
+                })
+                .product(),
+            BigUint::from(num_dealt(num_players)),
+            // Every card of every hand played, one position at a time
+            (1..=INIT_HAND_SIZE)
+                .fold(BigUint::from(1_u32), |product, num_cards| {
+                    product * num_cards
+                })
+                .pow(num_players as u32),
+            // Every card but one on the table, where the last card has one
+            // ending fewer
+            BigUint::from(NUM_CARD_ENDINGS).pow(max_table_size as u32 - 1)
+                * (NUM_CARD_ENDINGS - END_COMBINATION),
+            BigUint::from(NUM_SHAPES).pow(num_players as u32),
+        ];
+        if num_players == MAX_NUM_PLAYERS {
+            // A pass before every combination but the first, and at the end
+            radixes.push(BigUint::from(2_u32).pow(max_table_size as u32));
+        }
+        radixes
+            .into_iter()
+            .fold(BigUint::from(1_u32), |product, radix| product * radix)
+    }
+
+    #[test]
+    fn test_worst_case_size() {
+        let max_bits = (max_number(NUM_PLAYERS) - 1_u32).bits();
+        assert!(max_bits <= 8 * PAYLOAD_LEN as u64);
+        assert_eq!(max_bits, 231);
+
+        let max_bits = (max_number(MAX_NUM_PLAYERS) - 1_u32).bits();
+        assert!(max_bits <= 8 * PAYLOAD_LEN_THREE_PLAYERS as u64);
+        assert_eq!(max_bits, 397);
+    }
+
+    #[test]
+    fn test_two_player_games_fit_budget() {
+        let mut game = Game::new_for_players_with_seed(NUM_PLAYERS, 2);
+        assert_eq!(8 * encode_game(&game).len(), 248);
+        // The MAC of a signed game fills the rest of the 272-bit budget
+        game.match_key = Some(MatchKey::from_code("haggis"));
+        assert_eq!(8 * encode_game(&game).len(), 272);
+        assert_eq!(8 * (HEADER_LEN + PAYLOAD_LEN + MAC_LEN), 272);
+    }
+
+    #[test]
+    fn test_three_player_games_exceed_budget() {
+        let mut game = Game::new_for_players_with_seed(MAX_NUM_PLAYERS, 2);
+        assert_eq!(8 * encode_game(&game).len(), 416);
+        game.match_key = Some(MatchKey::from_code("haggis"));
+        assert_eq!(8 * encode_game(&game).len(), 440);
+    }
+
+    #[test]
+    fn test_played_hands_encode_decode() {
+        for &num_players in &[NUM_PLAYERS, MAX_NUM_PLAYERS] {
+            for seed in 0..5 {
+                let mut game = Game::new_for_players_with_seed(num_players, seed);
+                let mut play_smallest = true;
+                loop {
+                    let encoded_game = encode_game(&game);
+                    assert_eq!(
+                        encoded_game.len(),
+                        HEADER_LEN + payload_len(num_players) + CHECKSUM_LEN
+                    );
+                    assert_eq!(decode_game(&encoded_game), Ok(game.clone()));
+                    if game.is_game_over() {
+                        break;
+                    }
+                    // Alternate between the smallest and largest moves, so
+                    // that hands have both long groups and passes
+                    let legal_moves = game.legal_moves();
+                    let card_ids = if play_smallest {
+                        legal_moves.first()
+                    } else {
+                        legal_moves.last()
+                    };
+                    play_smallest = !play_smallest;
+                    game.try_play_cards(game.current_player, card_ids.unwrap())
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reject_leftover_digits() {
+        let game = Game::new();
+        let mut payload = encode(&game);
+        assert_eq!(decode(&payload), Some(game));
+        // No game has digits whose radixes multiply to more than this, so
+        // there are always digits left over
+        payload[0] = 0xFF;
+        assert_eq!(decode(&payload), None);
+    }
+}
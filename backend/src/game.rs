@@ -1,19 +1,42 @@
-use crate::compression::{compress_hand, decode_game, encode_game};
+use crate::compression::{
+    compress_hand, decode_game_with_key, decode_move, decode_text, encode_game, encode_move,
+    encode_text, is_resync_request, resync_request, state_hash,
+};
+use bet::Bet;
 use card::*;
+use card_set::CardSet;
 use combination_type::*;
 use constant::*;
+use error::{DecodeError, GameError, MoveMessageError, ReceiveError};
+use game_match::MatchKey;
+use history::{History, UndoState};
 use image::{load_from_memory_with_format, DynamicImage, ImageBuffer, ImageFormat::Png, Rgba};
-use location::Location;
+use location::{Location, Locations};
 use player::Player;
 use qrcode::QrCode;
 use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+use score::ScoreBreakdown;
 use wasm_bindgen::prelude::*;
+use wildcard_shape::WildcardShape;
 
+pub mod bet;
 pub mod card;
+pub mod card_set;
+pub mod combination;
 mod combination_type;
 pub mod constant;
+pub mod error;
+pub mod game_match;
+pub mod hidden;
+pub mod history;
+mod legal_moves;
 pub mod location;
+mod notation;
 pub mod player;
+pub mod score;
+pub mod validate;
+pub mod wildcard_shape;
 
 #[cfg(test)]
 mod tests;
@@ -23,22 +46,54 @@ mod tests;
 // - CombinationGroup: I pass, you pass
 // - Game (called hand in the rulebook): I empty my hand, you empty your hand
 
+// With three players, a pass only ends the combination group once every other
+// player who still has cards has passed in a row. A player who goes out stops
+// taking turns, and the hand ends when only one player has cards left.
+
 #[wasm_bindgen]
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Game {
     /// The location of a card with id x is locations[x].
     #[wasm_bindgen(skip)]
-    pub locations: Vec<Location>,
+    pub locations: Locations,
+    /// 2 or 3
+    #[wasm_bindgen(skip)]
+    pub num_players: usize,
     #[wasm_bindgen(skip)]
     pub current_player: Player,
+    /// The seat of the player using this game. Only the wasm-facing queries
+    /// depend on it; the rest of the state is stored by seat.
     #[wasm_bindgen(skip)]
-    pub me_went_first: bool,
+    pub viewer: Player,
     /// Type (including disambiguations) of the last combination played
     #[wasm_bindgen(skip)]
     pub last_combination_type: Option<CombinationType>,
+    /// The player who played the last combination of this combination group
+    #[wasm_bindgen(skip)]
+    pub last_player: Option<Player>,
+    /// The number of passes since the last combination was played
+    #[wasm_bindgen(skip)]
+    pub passes: usize,
+    /// The first player to empty their hand
+    #[wasm_bindgen(skip)]
+    pub first_out: Option<Player>,
     /// The order that the next card combination will have
     #[wasm_bindgen(skip)]
     pub next_order: usize,
+    /// The bet declared by each player, indexed by Player::index
+    #[wasm_bindgen(skip)]
+    pub bets: [Bet; MAX_NUM_PLAYERS],
+    /// The running total of each player's score in the match before this
+    /// hand, indexed by Player::index
+    #[wasm_bindgen(skip)]
+    pub match_scores: [usize; MAX_NUM_PLAYERS],
+    /// Moves that can be redone, and which moves can still be undone
+    #[wasm_bindgen(skip)]
+    pub undo: UndoState,
+    /// The key that signs this game when it is sent, and that the games sent
+    /// to it must be signed with. None if games aren't signed.
+    #[wasm_bindgen(skip)]
+    pub match_key: Option<MatchKey>,
 }
 #[wasm_bindgen]
 pub enum CardFrontendState {
@@ -57,48 +112,43 @@ pub enum GameStage {
     Play,
     Wait,
     GameOver,
+    /// Like Play, but I can still declare a bet because I haven't played a card yet
+    Bet,
 }
 
 #[wasm_bindgen]
 impl Game {
     pub fn new() -> Self {
-        let mut game = Game {
-            locations: Vec::new(),
-            current_player: Player::Me,
-            me_went_first: true,
-            last_combination_type: None,
-            next_order: 0,
-        };
-        for _ in 0..DECK_SIZE {
-            game.locations.push(Location::Haggis);
-        }
-        // Even though we only loop over the first 28 indices, we still
-        // need to shuffle all 36 normal cards so that the Haggis gets randomized.
-        let mut indices: Vec<_> = (0..NUM_NORMAL).collect();
-        indices.shuffle(&mut rand::thread_rng());
+        Game::new_for_players(NUM_PLAYERS)
+    }
 
-        // Cleaner version of the for loops:
-        for &i in &indices[0..INIT_HAND_SIZE_WO_WILDCARD] {
-            game.locations[i] = Location::Hand(Player::Me);
-        }
-        for &i in &indices[INIT_HAND_SIZE_WO_WILDCARD..(INIT_HAND_SIZE_WO_WILDCARD * 2)] {
-            game.locations[i] = Location::Hand(Player::Opponent);
-        }
+    /// Deal a hand for 2 or 3 players. I sit in the first seat.
+    pub fn new_for_players(num_players: usize) -> Self {
+        Game::new_with_rng(num_players, &mut rand::thread_rng())
+    }
 
-        for i in NUM_NORMAL..(NUM_NORMAL + NUM_WILDCARDS_PER_PLAYER) {
-            game.locations[i] = Location::Hand(Player::Me);
-        }
-        for i in (NUM_NORMAL + NUM_WILDCARDS_PER_PLAYER)..DECK_SIZE {
-            game.locations[i] = Location::Hand(Player::Opponent);
-        }
+    /// A game without a deal, which takes the seat of the first game that it
+    /// receives. Players who don't lead the hand start with it.
+    pub fn undealt() -> Self {
+        Game::undealt_for_players(NUM_PLAYERS)
+    }
 
-        game
+    /// Deal the same two-player hand every time for the same seed
+    pub fn new_with_seed(seed: u64) -> Self {
+        Game::new_for_players_with_seed(NUM_PLAYERS, seed)
+    }
+
+    /// Deal the same hand for 2 or 3 players every time for the same seed
+    pub fn new_for_players_with_seed(num_players: usize, seed: u64) -> Self {
+        Game::new_with_rng(num_players, &mut ChaCha20Rng::seed_from_u64(seed))
     }
 
-    pub fn from_qr_code(&mut self, image_data: &[u8]) -> bool {
+    /// Read a qr code of the game that another player sent. Returns the reason
+    /// the game was not accepted, or undefined if it was.
+    pub fn from_qr_code(&mut self, image_data: &[u8]) -> Option<String> {
         match load_from_memory_with_format(image_data, Png) {
-            Ok(image) => self.read_qr_code(image).is_ok(),
-            Err(_) => false,
+            Ok(image) => self.read_qr_code(image).err().map(str::to_string),
+            Err(_) => Some("Image is not a png".to_string()),
         }
     }
 
@@ -116,13 +166,31 @@ impl Game {
         array
     }
 
-    pub fn from_compressed(&mut self, data: &[u8]) -> bool {
-        if let Some(game) = decode_game(data) {
-            *self = game;
-            self.switch_perspective();
-            true
-        } else {
-            false
+    /// Accept a compressed game that another player sent, if it follows from
+    /// this game by their moves. Returns the reason the game was not accepted,
+    /// or undefined if it was.
+    pub fn from_compressed(&mut self, data: &[u8]) -> Option<ReceiveError> {
+        self.receive_compressed(data, false).err()
+    }
+
+    /// Like from_compressed, but the other players can have made more than one
+    /// turn of moves that this game missed, as they do when a game answers a
+    /// resync request
+    pub fn resync_from_compressed(&mut self, data: &[u8]) -> Option<ReceiveError> {
+        self.receive_compressed(data, true).err()
+    }
+
+    /// Replace this game with a compressed game that doesn't follow from it,
+    /// once the player has agreed to. I keep my seat, and the game still has
+    /// to be signed with the match key. Returns the reason the game was not
+    /// accepted, or undefined if it was.
+    pub fn reset_from_compressed(&mut self, data: &[u8]) -> Option<ReceiveError> {
+        match self.decode_received(data) {
+            Ok(game) => {
+                self.receive(game);
+                None
+            }
+            Err(error) => Some(error),
         }
     }
 
@@ -138,20 +206,64 @@ impl Game {
         array
     }
 
-    /// Returns the client id: 4 bytes for my hand followed by 4 bytes for
-    /// opponent's hand. Assumes that at most one combination has been played.
-    /// Cards on the table are counted as cards in the opponent's hand because
-    /// they must have been played by the opponent.
+    /// The game as text, for pasting into a chat or putting in a link
+    pub fn to_text(&self) -> String {
+        encode_text(&encode_game(self))
+    }
+
+    /// Accept a game that another player sent as text, like from_compressed.
+    /// Returns the reason the game was not accepted, or undefined if it was.
+    pub fn from_text(&mut self, text: &str) -> Option<ReceiveError> {
+        match decode_text(text) {
+            Some(data) => self.from_compressed(&data),
+            None => Some(ReceiveError::Undecodable),
+        }
+    }
+
+    /// Sign the games I send with key, and only accept games signed with it
+    pub fn set_match_key(&mut self, key: &MatchKey) {
+        self.match_key = Some(key.clone());
+    }
+
+    /// The last move, for players who already have the game from before it.
+    /// Returns undefined if no move has been played this hand.
+    pub fn to_move_message(&self) -> Option<Box<[u8]>> {
+        let history = History::from_game(self)?;
+        let last_move = history.moves.last()?;
+        let game_before = history.game_at(history.num_moves() - 1);
+        Some(encode_move(&game_before, last_move).into_boxed_slice())
+    }
+
+    /// Play the move in a move message from another player. Returns the reason
+    /// the move was not played, or undefined if it was. After StateMismatch,
+    /// send a resync request to get the whole game instead.
+    #[wasm_bindgen(js_name = apply_move_message)]
+    pub fn try_apply_move_message(&mut self, data: &[u8]) -> Option<MoveMessageError> {
+        self.apply_move_message(data).err()
+    }
+
+    /// A message asking the other player to send the whole game
+    pub fn resync_request() -> Box<[u8]> {
+        resync_request().into_boxed_slice()
+    }
+
+    /// Whether data asks for the whole game to be sent
+    pub fn is_resync_request(data: &[u8]) -> bool {
+        is_resync_request(data)
+    }
+
+    /// Returns the client id: 4 bytes for the viewer's hand followed by 4 bytes
+    /// for the opponent's hand. Assumes that at most one combination has been
+    /// played. Cards on the table are counted as cards in the opponent's hand
+    /// because they must have been played by the opponent.
     pub fn get_client_id(&self) -> js_sys::Uint8Array {
         let mut my_sorted_hand = Vec::with_capacity(INIT_HAND_SIZE_WO_WILDCARD);
         let mut opponent_sorted_hand = Vec::with_capacity(INIT_HAND_SIZE_WO_WILDCARD);
 
         for (card_id, location) in self.locations[0..NUM_NORMAL].iter().enumerate() {
             match location {
-                Location::Hand(Player::Me) => my_sorted_hand.push(card_id),
-                Location::Hand(Player::Opponent) | Location::Table { .. } => {
-                    opponent_sorted_hand.push(card_id)
-                }
+                Location::Hand(player) if *player == self.viewer => my_sorted_hand.push(card_id),
+                Location::Hand(_) | Location::Table { .. } => opponent_sorted_hand.push(card_id),
                 _ => {}
             }
         }
@@ -170,111 +282,81 @@ impl Game {
         array
     }
 
-    // card_ids can be empty
-    // Returns true on success, false on failure
-    // Assumption: current_player == Player::Me
-    pub fn can_play_cards(&mut self, card_ids: &[usize]) -> bool {
-        if card_ids.is_empty() {
-            // We can't pass before the first combination of a combination group is played
-            return self.last_combination_type.is_some();
-        }
+    /// Whether try_play_cards would play card_ids as the viewer, or pass if
+    /// card_ids is empty. It is false when it is not the viewer's turn.
+    pub fn can_play_cards(&self, card_ids: &[usize]) -> bool {
+        self.validate_play(self.viewer, card_ids, None).is_ok()
+    }
 
-        let card_values = card_ids
-            .into_iter()
-            .map(|&id| CardId(id).to_value())
-            .collect();
+    /// Play card_ids as the viewer. Returns the reason the move was rejected,
+    /// or undefined if the cards were played.
+    #[wasm_bindgen(js_name = try_play_cards)]
+    pub fn try_play_cards_as_me(&mut self, card_ids: &[usize]) -> Option<GameError> {
+        self.try_play_cards(self.viewer, card_ids).err()
+    }
 
-        let current_combination_type = if let Some(bomb_rank) = is_bomb(&card_values) {
-            Some(CombinationType::Bomb(bomb_rank))
-        } else {
-            is_valid_normal(&card_values).map(|normal_type| CombinationType::Normal(normal_type))
-        };
+    /// Play card_ids as the viewer, using the extra wildcards as shape.
+    /// Returns the reason the move was rejected, or undefined if the cards were played.
+    #[wasm_bindgen(js_name = try_play_cards_with_shape)]
+    pub fn try_play_cards_with_shape_as_me(
+        &mut self,
+        card_ids: &[usize],
+        shape: WildcardShape,
+    ) -> Option<GameError> {
+        self.try_play_cards_with_shape(self.viewer, card_ids, Some(shape))
+            .err()
+    }
 
-        use CombinationType::*;
-        match (&self.last_combination_type, &current_combination_type) {
-            (_, None) => false,
-            (Some(Bomb(last_bomb)), Some(Bomb(current_bomb))) => {
-                if current_bomb <= last_bomb {
-                    false
-                } else {
-                    true
-                }
-            }
-            (Some(Bomb(_)), Some(Normal(_))) => false,
-            (Some(Normal(last_normal)), Some(Normal(current_normal))) => {
-                if let Some(_) = current_normal.has_higher_rank_than(last_normal) {
-                    true
-                } else {
-                    false
-                }
-            }
-            _ => true,
+    /// Whether I have to declare a WildcardShape to play card_ids, because
+    /// the last combination played does not already decide the shape
+    pub fn needs_wildcard_shape(&self, card_ids: &[usize]) -> bool {
+        match self.validate_play(self.viewer, card_ids, None) {
+            Ok(Some(CombinationType::Normal(normal_type))) => normal_type.is_ambiguous(),
+            _ => false,
         }
     }
 
-    // We pass if card_ids is empty
-    pub fn play_cards(&mut self, card_ids: &[usize]) {
-        if card_ids.is_empty() {
-            self.capture_table();
-        } else {
-            let card_values = card_ids.iter().map(|&id| CardId(id).to_value()).collect();
+    /// The shape declared for the combination that was just played, if any
+    pub fn last_wildcard_shape(&self) -> Option<WildcardShape> {
+        self.locations.iter().find_map(|location| match location {
+            Location::Table {
+                captured_by: None,
+                order,
+                wildcard_shape,
+                ..
+            } if order + 1 == self.next_order => *wildcard_shape,
+            _ => None,
+        })
+    }
 
-            let current_combination_type = if let Some(bomb_rank) = is_bomb(&card_values) {
-                Some(CombinationType::Bomb(bomb_rank))
-            } else {
-                is_valid_normal(&card_values)
-                    .map(|normal_type| CombinationType::Normal(normal_type))
-            };
+    /// Declare a bet as the viewer. Returns the reason the bet was rejected,
+    /// or undefined if the bet was declared.
+    #[wasm_bindgen(js_name = declare_bet)]
+    pub fn declare_bet_as_me(&mut self, bet: Bet) -> Option<GameError> {
+        self.declare_bet(self.viewer, bet).err()
+    }
 
-            use CombinationType::*;
-            self.last_combination_type =
-                match (&self.last_combination_type, &current_combination_type) {
-                    (_, None) => panic!("play_cards: No current combination type"),
-                    (Some(Bomb(last_bomb)), Some(Bomb(current_bomb))) => {
-                        if current_bomb <= last_bomb {
-                            panic!("play_cards: Bomb rank too low");
-                        } else {
-                            current_combination_type
-                        }
-                    }
-                    (Some(Bomb(_)), Some(Normal(_))) => {
-                        panic!("play_cards: Tried to play normal combination after a bomb")
-                    }
-                    (Some(Normal(last_normal)), Some(Normal(current_normal))) => {
-                        if let Some(new_normal_type) =
-                            current_normal.has_higher_rank_than(last_normal)
-                        {
-                            Some(Normal(new_normal_type))
-                        } else {
-                            panic!(
-                                "play_cards: Tried to play {:?} after {:?}",
-                                current_normal, last_normal
-                            );
-                        }
-                    }
-                    _ => current_combination_type,
-                };
+    pub fn my_bet(&self) -> Bet {
+        self.bets[self.viewer.index()]
+    }
 
-            // move the cards to table
-            for &card_id in card_ids {
-                self.locations[card_id] = Location::Table {
-                    order: self.next_order,
-                    captured_by: None,
-                    in_last_combination_before_pass: false,
-                };
-            }
-            self.next_order += 1;
-        }
+    /// The bet of the player after me
+    pub fn opponent_bet(&self) -> Bet {
+        self.bets[self.viewer.next(self.num_players).index()]
+    }
 
-        // change the current player
-        self.current_player = self.current_player.other();
+    pub fn num_players(&self) -> usize {
+        self.num_players
     }
 
     pub fn game_stage(&self) -> GameStage {
         if self.is_game_over() {
             return GameStage::GameOver;
         }
-        if self.current_player == Player::Me {
+        if self.can_bet(self.viewer) {
+            return GameStage::Bet;
+        }
+        if self.current_player == self.viewer {
             return GameStage::Play;
         } else {
             return GameStage::Wait;
@@ -284,8 +366,8 @@ impl Game {
     pub fn card_frontend_state(&self, card_id: usize) -> CardFrontendState {
         match self.locations[card_id] {
             Location::Haggis => CardFrontendState::Haggis,
-            Location::Hand(Player::Opponent) => CardFrontendState::InOpponentHand,
-            Location::Hand(Player::Me) => CardFrontendState::InMyHand,
+            Location::Hand(player) if player == self.viewer => CardFrontendState::InMyHand,
+            Location::Hand(_) => CardFrontendState::InOpponentHand,
             Location::Table {
                 captured_by: None,
                 order,
@@ -298,99 +380,153 @@ impl Game {
                 }
             }
             Location::Table {
-                captured_by: Some(Player::Me),
+                captured_by: Some(player),
                 ..
-            } => CardFrontendState::CapturedByMe,
+            } if player == self.viewer => CardFrontendState::CapturedByMe,
             Location::Table {
-                captured_by: Some(Player::Opponent),
+                captured_by: Some(_),
                 ..
             } => CardFrontendState::CapturedByOpponent,
         }
     }
 
-    /// return (my_hand_size, opponent_hand_size)
-    pub fn hand_sizes(&mut self) -> Box<[usize]> {
-        let mut my_card_count = 0;
-        let mut opponent_card_count = 0;
+    /// return (my_hand_size, opponent_hand_size), followed by the second
+    /// opponent's hand size in a three-player game
+    pub fn hand_sizes(&self) -> Box<[usize]> {
+        let card_counts: Vec<usize> = (0..self.num_players)
+            .map(|index| self.locations.hand(Player::from_index(index)).len())
+            .collect();
 
-        for location in self.locations.iter() {
-            match location {
-                Location::Hand(Player::Me) => my_card_count += 1,
-                Location::Hand(Player::Opponent) => opponent_card_count += 1,
-                _ => {}
-            };
-        }
+        self.relative_to_viewer(&card_counts)
+    }
 
-        Box::new([my_card_count, opponent_card_count])
+    /// return (my_score, opponent_score) based on the scores so far,
+    /// followed by the second opponent's score in a three-player game.
+    /// Once the hand is over, the last combination group is scored as if
+    /// finish_hand had already captured it.
+    pub fn calculate_score(&self) -> Box<[usize]> {
+        let scores = self.calculate_score_by_seat();
+        self.relative_to_viewer(&scores)
     }
 
-    /// return (my_score, opponent_score) based on the scores so far
-    pub fn calculate_score(&mut self) -> Box<[usize]> {
-        let hand_sizes = self.hand_sizes();
-        let my_card_count = hand_sizes[0];
-        let opponent_card_count = hand_sizes[1];
+    /// Where the score of the player seats_after_me turns after me comes from
+    pub fn score_breakdown(&self, seats_after_me: usize) -> ScoreBreakdown {
+        let player = self.viewer.after(seats_after_me, self.num_players);
+        self.score_breakdowns_by_seat()[player.index()]
+    }
 
-        if my_card_count == 0 || opponent_card_count == 0 {
-            // Capture the last combination group
-            // Because the winner of the last combination group was the last
-            // person to play, self.play_cards will switch the current player
-            // to their opponent, so self.capture_table will assign cards correctly
+    /// End a hand that is over by capturing its last combination group, which
+    /// nobody passes on. Returns false if the hand is not over yet.
+    /// Finishing a hand more than once has no effect.
+    pub fn finish_hand(&mut self) -> bool {
+        if !self.is_game_over() {
+            return false;
+        }
+        if self.last_combination_type.is_some() {
             self.capture_table();
         }
-        let mut my_score = 0;
-        let mut opponent_score = 0;
+        true
+    }
 
-        let mut winner_of_hand_bonus = 0;
+    pub fn me_went_first(&self) -> bool {
+        self.viewer == Player::First
+    }
 
-        // The winner of the hand scores 5 points for each card in her opponent's hand.
-        // Remember, the wild cards count as part of the hand.
-        winner_of_hand_bonus += 5 * (my_card_count + opponent_card_count);
+    /// Take back the last move. Returns the reason the move can't be taken
+    /// back, or undefined if it was.
+    #[wasm_bindgen(js_name = undo)]
+    pub fn try_undo(&mut self) -> Option<GameError> {
+        self.undo().err()
+    }
 
-        for (i, location) in self.locations.iter().enumerate() {
-            let card_id = CardId(i);
-            match location {
-                // All point  cards (i.e., any 3, 5, 7, 9, J, Q, or K) captured
-                // are  scored by the capturing player.
-                Location::Table {
-                    captured_by: Some(Player::Me),
-                    ..
-                } => {
-                    my_score += card_id.to_value().point_value();
-                }
-                Location::Table {
-                    captured_by: Some(Player::Opponent),
-                    ..
-                } => {
-                    opponent_score += card_id.to_value().point_value();
-                }
-                // Point cards left in the opponent's hand and any point cards found in the
-                // Haggis are scored by the player who won the hand.
-                Location::Hand(..) | Location::Haggis => {
-                    winner_of_hand_bonus += card_id.to_value().point_value();
-                }
-                Location::Table {
-                    captured_by: None, ..
-                } => {
-                    // This can happen if the game is still going
-                }
-            }
-        }
+    /// Play the last move that was taken back again. Returns the reason the
+    /// move can't be redone, or undefined if it was.
+    #[wasm_bindgen(js_name = redo)]
+    pub fn try_redo(&mut self) -> Option<GameError> {
+        self.redo().err()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.undo_target().is_ok()
+    }
 
-        if my_card_count == 0 {
-            my_score += winner_of_hand_bonus;
-        } else if opponent_card_count == 0 {
-            opponent_score += winner_of_hand_bonus;
+    pub fn can_redo(&self) -> bool {
+        !self.undo.undone_moves.is_empty()
+    }
+
+    /// Call once the other players have seen the game, for example after
+    /// sending it to them
+    pub fn mark_shared(&mut self) {
+        if let Some(history) = History::from_game(self) {
+            self.undo.num_shared_moves = history.num_moves();
         }
+    }
 
-        Box::new([my_score, opponent_score])
+    /// House rule: whether a move can be taken back after the other players
+    /// have seen it. Not allowed by default.
+    pub fn set_undo_after_shared(&mut self, allowed: bool) {
+        self.undo.allowed_after_shared = allowed;
     }
+}
 
-    pub fn me_went_first(&self) -> bool {
-        self.me_went_first
+/// A random two-player deal
+impl Default for Game {
+    fn default() -> Self {
+        Game::new()
     }
 }
 
 impl Game {
+    /// Deal a hand for 2 or 3 players, shuffling with rng. The first seat
+    /// plays the first combination and uses this game.
+    pub fn new_with_rng<R: Rng + ?Sized>(num_players: usize, rng: &mut R) -> Self {
+        let mut game = Game::undealt_for_players(num_players);
+        game.init_state(rng);
+        game
+    }
+
+    /// Every card of the deck is still in the Haggis
+    pub fn undealt_for_players(num_players: usize) -> Self {
+        Game {
+            locations: vec![Location::Haggis; deck_size(num_players)].into(),
+            num_players,
+            current_player: Player::First,
+            viewer: Player::First,
+            last_combination_type: None,
+            last_player: None,
+            passes: 0,
+            first_out: None,
+            next_order: 0,
+            bets: [Bet::None; MAX_NUM_PLAYERS],
+            match_scores: [0; MAX_NUM_PLAYERS],
+            undo: UndoState::default(),
+            match_key: None,
+        }
+    }
+
+    /// Whether the game has no deal yet, and so no seat either
+    pub fn is_undealt(&self) -> bool {
+        self.locations
+            .iter()
+            .all(|location| *location == Location::Haggis)
+    }
+
+    /// Reorder values indexed by seat so that the viewer comes first,
+    /// followed by the other players in turn order
+    fn relative_to_viewer(&self, by_seat: &[usize]) -> Box<[usize]> {
+        (0..self.num_players)
+            .map(|seats| by_seat[self.viewer.after(seats, self.num_players).index()])
+            .collect()
+    }
+
+    /// The score of each seat, indexed by Player::index
+    pub fn calculate_score_by_seat(&self) -> Vec<usize> {
+        self.score_breakdowns_by_seat()
+            .iter()
+            .map(ScoreBreakdown::total)
+            .collect()
+    }
+
     pub fn read_qr_code(&mut self, image: DynamicImage) -> Result<(), &str> {
         // convert to gray scale
         let img_gray = image.into_luma();
@@ -415,15 +551,97 @@ impl Game {
             _ => return Err("Cannot decode qr code into bytes"),
         };
 
-        *self = match decode_game(&decoded.payload) {
-            Some(game) => game,
-            None => return Err("Qr data is not a valid game"),
+        // Qr codes made from a link hold the game as text
+        let payload = std::str::from_utf8(&decoded.payload)
+            .ok()
+            .and_then(decode_text)
+            .unwrap_or(decoded.payload);
+        let game = match decode_game_with_key(&payload, self.match_key.as_ref()) {
+            Ok(game) => game,
+            Err(DecodeError::UnsupportedVersion) => {
+                return Err("Qr code is from an unsupported version")
+            }
+            Err(DecodeError::ChecksumMismatch) => return Err("Qr data is damaged"),
+            Err(DecodeError::Unauthenticated) => {
+                return Err("Qr code is not signed with this match's key")
+            }
+            Err(_) => return Err("Qr data is not a valid game"),
         };
-        self.switch_perspective();
+        match self.check_successor(&game, false) {
+            Ok(()) => self.receive(game),
+            Err(ReceiveError::DifferentGame) => return Err("Qr code is from a different hand"),
+            Err(ReceiveError::OlderState) => return Err("Qr code is older than this game"),
+            Err(ReceiveError::SkippedMoves) => {
+                return Err("Qr code skips a qr code that was not scanned")
+            }
+            Err(ReceiveError::MovesInMySeat) => return Err("Qr code has moves in my seat"),
+            Err(ReceiveError::ChangedBets) => return Err("Qr code has a bet that can't be made"),
+            Err(_) => return Err("Qr code has an illegal move"),
+        }
 
         Ok(())
     }
 
+    fn receive_compressed(
+        &mut self,
+        data: &[u8],
+        allow_skipped_moves: bool,
+    ) -> Result<(), ReceiveError> {
+        let game = self.decode_received(data)?;
+        self.check_successor(&game, allow_skipped_moves)?;
+        self.receive(game);
+        Ok(())
+    }
+
+    /// Decode a game that another player sent, checking it against the match key
+    fn decode_received(&self, data: &[u8]) -> Result<Game, ReceiveError> {
+        decode_game_with_key(data, self.match_key.as_ref()).map_err(|error| match error {
+            DecodeError::Unauthenticated => ReceiveError::Unauthenticated,
+            _ => ReceiveError::Undecodable,
+        })
+    }
+
+    /// Play the move in a move message, if it was played in the same state as
+    /// this game
+    pub fn apply_move_message(&mut self, data: &[u8]) -> Result<(), MoveMessageError> {
+        let (hash, played_move) =
+            decode_move(data, self.match_key.as_ref()).map_err(|error| match error {
+                DecodeError::Unauthenticated => MoveMessageError::Unauthenticated,
+                _ => MoveMessageError::NotAMove,
+            })?;
+        // Bets aren't moves, so a bet declared with the move also mismatches
+        if hash != state_hash(self) {
+            return Err(MoveMessageError::StateMismatch);
+        }
+        if self.current_player == self.viewer {
+            return Err(MoveMessageError::MyTurn);
+        }
+        self.play_move(&played_move)
+            .map_err(|_| MoveMessageError::IllegalMove)?;
+        self.mark_shared();
+        Ok(())
+    }
+
+    /// Replace this game with a game that was sent by another player. Every
+    /// move in it has been seen by the sender, and my seat, the house rules
+    /// and the match key stay the same.
+    fn receive(&mut self, game: Game) {
+        // Without a deal, I join the seat that the game was sent to, which is
+        // the seat of the player whose turn it is
+        let viewer = if self.is_undealt() {
+            game.current_player
+        } else {
+            self.viewer
+        };
+        let allowed_after_shared = self.undo.allowed_after_shared;
+        let match_key = self.match_key.take();
+        *self = game;
+        self.match_key = match_key;
+        self.viewer = viewer;
+        self.mark_shared();
+        self.undo.allowed_after_shared = allowed_after_shared;
+    }
+
     pub fn write_qr_code(&self, width: usize, height: usize) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
         let encoded_game = encode_game(self);
 
@@ -438,97 +656,292 @@ impl Game {
             .build()
     }
 
-    /// Setup the location of each card at the beginning of a game
-    pub fn init_state(&mut self) {
-        // Even though we only loop over the first 28 indices, we still
-        // need to shuffle all 36 normal cards so that the Haggis gets randomized.
-        let mut indices: Vec<_> = (0..NUM_NORMAL).collect();
-        indices.shuffle(&mut rand::thread_rng());
+    /// Play card_ids on behalf of player, or pass if card_ids is empty.
+    /// The game is left unchanged if the move is rejected.
+    pub fn try_play_cards(&mut self, player: Player, card_ids: &[usize]) -> Result<(), GameError> {
+        self.try_play_cards_with_shape(player, card_ids, None)
+    }
 
-        // Cleaner version of the for loops:
-        for &i in &indices[0..INIT_HAND_SIZE_WO_WILDCARD] {
-            self.locations[i] = Location::Hand(Player::Me);
+    /// Play card_ids for the player whose turn it is, or pass if card_ids is
+    /// empty. Panics if the move is rejected, so only tests use it.
+    #[cfg(test)]
+    pub(crate) fn play_cards(&mut self, card_ids: &[usize]) {
+        if let Err(error) = self.try_play_cards(self.current_player, card_ids) {
+            panic!("play_cards: {}", error);
         }
-        for &i in &indices[INIT_HAND_SIZE_WO_WILDCARD..(INIT_HAND_SIZE_WO_WILDCARD * 2)] {
-            self.locations[i] = Location::Hand(Player::Opponent);
+    }
+
+    /// Like try_play_cards, but the extra wildcards of an ambiguous combination
+    /// are used as shape. If no shape is declared, the shape of an ambiguous
+    /// combination is decided by the combination played after it.
+    pub fn try_play_cards_with_shape(
+        &mut self,
+        player: Player,
+        card_ids: &[usize],
+        shape: Option<WildcardShape>,
+    ) -> Result<(), GameError> {
+        let combination_type = self.validate_play(player, card_ids, shape)?;
+        // A new move replaces the moves that were taken back
+        self.undo.undone_moves.clear();
+
+        if card_ids.is_empty() {
+            self.passes += 1;
+            let last_player = self
+                .last_player
+                .unwrap_or_else(|| self.current_player.previous(self.num_players));
+            let num_waiting_players = (0..self.num_players)
+                .map(Player::from_index)
+                .filter(|&other| other != last_player && self.has_cards(other))
+                .count();
+            if self.passes >= num_waiting_players {
+                self.capture_table();
+                // The player who played the last combination leads the next
+                // combination group, unless they went out
+                self.current_player = if self.has_cards(last_player) {
+                    last_player
+                } else {
+                    self.next_player_with_cards(last_player)
+                };
+                return Ok(());
+            }
+        } else {
+            self.last_combination_type = combination_type;
+            self.last_player = Some(player);
+
+            // move the cards to table
+            for &card_id in card_ids {
+                self.locations.set(
+                    card_id,
+                    Location::Table {
+                        order: self.next_order,
+                        captured_by: None,
+                        in_last_combination_before_pass: false,
+                        after_pass: self.passes > 0,
+                        wildcard_shape: shape,
+                    },
+                );
+            }
+            self.next_order += 1;
+            self.passes = 0;
+
+            if self.first_out.is_none() && !self.has_cards(player) {
+                self.first_out = Some(player);
+            }
         }
 
-        for i in NUM_NORMAL..(NUM_NORMAL + NUM_WILDCARDS_PER_PLAYER) {
-            self.locations[i] = Location::Hand(Player::Me);
+        // change the current player
+        self.current_player = self.next_player_with_cards(player);
+
+        Ok(())
+    }
+
+    /// Declare a bet for player, who must not have played a card yet this hand.
+    /// Declaring Bet::None is allowed and does nothing.
+    pub fn declare_bet(&mut self, player: Player, bet: Bet) -> Result<(), GameError> {
+        if player != self.current_player {
+            return Err(GameError::WrongTurn);
+        }
+        if self.has_played_a_card(player) {
+            return Err(GameError::BetAfterPlaying);
         }
-        for i in (NUM_NORMAL + NUM_WILDCARDS_PER_PLAYER)..DECK_SIZE {
-            self.locations[i] = Location::Hand(Player::Opponent);
+        if self.bets[player.index()] != Bet::None {
+            return Err(GameError::AlreadyBet);
         }
+        self.bets[player.index()] = bet;
+        Ok(())
     }
 
-    pub fn get_hand(&self, player: Player) -> Vec<CardId> {
-        let mut hand = Vec::new();
-        for (i, location) in self.locations.iter().enumerate() {
-            if let Location::Hand(owner) = location {
-                if *owner == player {
-                    hand.push(CardId(i));
+    /// Whether it is player's turn and they can still declare a bet
+    pub fn can_bet(&self, player: Player) -> bool {
+        self.current_player == player
+            && !self.is_game_over()
+            && !self.has_played_a_card(player)
+            && self.bets[player.index()] == Bet::None
+    }
+
+    fn has_cards(&self, player: Player) -> bool {
+        !self.locations.hand(player).is_empty()
+    }
+
+    /// The first player after player in turn order who still has cards.
+    /// Returns the player after player if nobody has cards.
+    fn next_player_with_cards(&self, player: Player) -> Player {
+        let mut next_player = player.next(self.num_players);
+        while !self.has_cards(next_player) && next_player != player {
+            next_player = next_player.next(self.num_players);
+        }
+        if self.has_cards(next_player) {
+            next_player
+        } else {
+            player.next(self.num_players)
+        }
+    }
+
+    /// Cards only leave a hand by being played, so a player has played a card
+    /// iff their hand is smaller than it was when it was dealt
+    fn has_played_a_card(&self, player: Player) -> bool {
+        self.locations.hand(player).len() < INIT_HAND_SIZE
+    }
+
+    /// Check whether player can play card_ids (or pass if card_ids is empty)
+    /// with the declared shape.
+    /// On success, returns the disambiguated type that the last combination
+    /// will have after the move, which is None after a pass.
+    fn validate_play(
+        &self,
+        player: Player,
+        card_ids: &[usize],
+        shape: Option<WildcardShape>,
+    ) -> Result<Option<CombinationType>, GameError> {
+        if player != self.current_player {
+            return Err(GameError::WrongTurn);
+        }
+
+        if card_ids.is_empty() {
+            if shape.is_some() {
+                return Err(GameError::UnambiguousCombination);
+            }
+            // We can't pass before the first combination of a combination group is played
+            return match self.last_combination_type {
+                Some(_) => Ok(None),
+                None => Err(GameError::PassOnEmptyTable),
+            };
+        }
+
+        let hand = self.locations.hand(player);
+        let mut cards = CardSet::EMPTY;
+        for &card_id in card_ids {
+            if card_id >= self.locations.len() {
+                return Err(GameError::InvalidCardId);
+            }
+            if cards.contains(CardId(card_id)) {
+                return Err(GameError::DuplicateCard);
+            }
+            cards.insert(CardId(card_id));
+            if !hand.contains(CardId(card_id)) {
+                return Err(GameError::CardNotInHand);
+            }
+        }
+
+        Game::combination_type_after(&self.last_combination_type, cards, shape).map(Some)
+    }
+
+    /// The type of cards played with the declared shape after a combination
+    /// of type last_combination_type, or why they can't be played after it
+    fn combination_type_after(
+        last_combination_type: &Option<CombinationType>,
+        cards: CardSet,
+        shape: Option<WildcardShape>,
+    ) -> Result<CombinationType, GameError> {
+        let current_combination_type = if let Some(bomb_rank) = is_bomb(cards) {
+            CombinationType::Bomb(bomb_rank)
+        } else if let Some(normal_type) = is_valid_normal(cards) {
+            CombinationType::Normal(normal_type)
+        } else {
+            return Err(GameError::InvalidCombination);
+        };
+
+        let current_combination_type = match (current_combination_type, shape) {
+            (combination_type, None) => combination_type,
+            (CombinationType::Normal(normal_type), Some(shape)) if normal_type.is_ambiguous() => {
+                CombinationType::Normal(normal_type.with_shape(shape))
+            }
+            (_, Some(_)) => return Err(GameError::UnambiguousCombination),
+        };
+
+        use CombinationType::*;
+        match (last_combination_type, current_combination_type) {
+            (Some(Bomb(last_bomb)), Bomb(current_bomb)) => {
+                if current_bomb <= *last_bomb {
+                    Err(GameError::BombTooLow)
+                } else {
+                    Ok(Bomb(current_bomb))
                 }
             }
+            (Some(Bomb(_)), Normal(_)) => Err(GameError::NormalAfterBomb),
+            (Some(Normal(last_normal)), Normal(current_normal)) => {
+                match current_normal.has_higher_rank_than(last_normal) {
+                    Some(new_normal_type) => Ok(Normal(new_normal_type)),
+                    None => Err(GameError::MismatchedCombination),
+                }
+            }
+            (_, current_combination_type) => Ok(current_combination_type),
+        }
+    }
+
+    /// Setup the location of each card at the beginning of a game
+    pub fn init_state<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        // Even though we only deal 14 cards to each player, we still need to
+        // shuffle all normal cards so that the Haggis gets randomized.
+        let mut indices = normal_card_ids(self.num_players);
+        indices.shuffle(rng);
+
+        for (player_index, hand) in indices
+            .chunks(INIT_HAND_SIZE_WO_WILDCARD)
+            .take(self.num_players)
+            .enumerate()
+        {
+            let player = Player::from_index(player_index);
+            for &i in hand {
+                self.locations.set(i, Location::Hand(player));
+            }
+            // The first player of the hand gets the first set of wildcards
+            for i in wildcard_ids(player_index) {
+                self.locations.set(i, Location::Hand(player));
+            }
         }
-        hand
     }
 
+    pub fn get_hand(&self, player: Player) -> Vec<CardId> {
+        self.locations.hand(player).iter().collect()
+    }
+
+    /// The hand is over once at most one player has cards left
     pub fn is_game_over(&self) -> bool {
-        let mut my_card_count = 0;
-        let mut opponent_card_count = 0;
+        (0..self.num_players)
+            .filter(|&index| self.has_cards(Player::from_index(index)))
+            .count()
+            <= 1
+    }
 
-        for location in self.locations.iter() {
-            match location {
-                Location::Hand(Player::Me) => my_card_count += 1,
-                Location::Hand(Player::Opponent) => opponent_card_count += 1,
-                _ => {}
-            };
+    /// The player who captures the cards on the table if the combination
+    /// group ends now
+    fn player_who_captures(&self) -> Player {
+        // A combination group won by a bomb goes to an opponent. With three
+        // players, the rules let the bomber pick either opponent, but the app
+        // doesn't ask: it goes to the player whose turn it is, who is the last
+        // opponent to pass, or the next player with cards if the hand is over.
+        // Letting the bomber pick would need a move for the choice, and a
+        // digit in the layout to send it.
+        match (&self.last_combination_type, self.last_player) {
+            (Some(CombinationType::Bomb(_)), _) => self.current_player,
+            (_, Some(last_player)) => last_player,
+            (_, None) => self.current_player.previous(self.num_players),
         }
-
-        my_card_count == 0 || opponent_card_count == 0
     }
 
     /// Capture the cards on the table without changing whose turn it is
     fn capture_table(&mut self) {
         // capture the cards on the table
-        let player_who_captures = if let Some(CombinationType::Bomb(_)) = self.last_combination_type
-        {
-            self.current_player
-        } else {
-            self.current_player.other()
-        };
-        for location in &mut self.locations {
+        let player_who_captures = self.player_who_captures();
+        let next_order = self.next_order;
+        self.locations.update_table(|location| {
             if let Location::Table {
-                captured_by,
+                captured_by: captured_by @ None,
                 order,
                 in_last_combination_before_pass,
+                ..
             } = location
             {
-                if captured_by.is_none() {
-                    *captured_by = Some(player_who_captures);
-                    if *order + 1 == self.next_order {
-                        *in_last_combination_before_pass = true;
-                    }
+                *captured_by = Some(player_who_captures);
+                if *order + 1 == next_order {
+                    *in_last_combination_before_pass = true;
                 }
             }
-        }
+        });
 
         self.last_combination_type = None;
-    }
-
-    // Swap Me and Opponent
-    pub fn switch_perspective(&mut self) {
-        for location in &mut self.locations {
-            match location {
-                Location::Hand(player) => *player = player.other(),
-                Location::Table {
-                    captured_by: Some(player),
-                    ..
-                } => *player = player.other(),
-                _ => {}
-            }
-        }
-        self.current_player = self.current_player.other();
-        self.me_went_first = !self.me_went_first;
+        self.last_player = None;
+        self.passes = 0;
     }
 }
@@ -0,0 +1,23 @@
+use super::constant::{BIG_BET_POINTS, LITTLE_BET_POINTS};
+use wasm_bindgen::prelude::*;
+
+/// Before playing their first card of a hand, a player may bet that they will
+/// be the first to empty their hand. If they are, they score the bet.
+/// Otherwise, their opponent scores it.
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Bet {
+    None,
+    Little,
+    Big,
+}
+
+impl Bet {
+    pub fn points(self) -> usize {
+        match self {
+            Bet::None => 0,
+            Bet::Little => LITTLE_BET_POINTS,
+            Bet::Big => BIG_BET_POINTS,
+        }
+    }
+}
@@ -1,10 +1,15 @@
-use super::constant::{NUM_NORMAL, NUM_RANKS, NUM_SUITS};
+use std::fmt;
+
+use super::constant::{
+    DECK_SIZE, DECK_SIZE_THREE_PLAYERS, MAX_NUM_SUITS, MIN_RANK, NUM_NORMAL, NUM_RANKS, NUM_SUITS,
+    NUM_WILDCARDS_PER_PLAYER,
+};
 
 pub enum CardValue {
     Normal {
         /// Rank is in the range 2..=10
         rank: usize,
-        /// Suit is in the range 0..4
+        /// Suit is in the range 0..4, or 0..5 in the three-player game
         suit: usize,
     },
     Wildcard {
@@ -33,6 +38,21 @@ impl CardValue {
     }
 }
 
+/// Cards are written the same way they are parsed, e.g. 10♠ or J
+impl fmt::Display for CardValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CardValue::Normal { rank, suit } => {
+                write!(f, "{}{}", rank, ['♠', '♥', '♦', '♣', '★'][*suit])
+            }
+            CardValue::Wildcard { rank: 11 } => f.write_str("J"),
+            CardValue::Wildcard { rank: 12 } => f.write_str("Q"),
+            CardValue::Wildcard { rank: 13 } => f.write_str("K"),
+            _ => panic!("Invalid wildcard rank"),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct CardId(pub usize);
 
@@ -40,32 +60,78 @@ impl CardId {
     /// CardIds: 0  1  2  ...   8  9  ...  35 36 37 38 39 40 41
     /// Ranks:   2  3  4  ...  10  2  ...  10  J  Q  K  J  Q  K
     /// Suits:   0  0  0  ...   0  1  ...   3
+    ///
+    /// The three-player game adds the cards after the two-player deck:
+    /// CardIds: 42 ... 50 51 52 53
+    /// Ranks:    2 ... 10  J  Q  K
+    /// Suits:    4 ...  4
     pub fn to_value(self) -> CardValue {
         if self.0 < NUM_NORMAL {
             CardValue::Normal {
                 rank: 2 + (self.0 % NUM_RANKS),
                 suit: self.0 / NUM_RANKS,
             }
+        } else if DECK_SIZE <= self.0 && self.0 < DECK_SIZE + NUM_RANKS {
+            CardValue::Normal {
+                rank: 2 + ((self.0 - DECK_SIZE) % NUM_RANKS),
+                suit: MAX_NUM_SUITS - 1,
+            }
         } else {
             CardValue::Wildcard {
                 rank: 11 + (self.0 % 3),
             }
         }
     }
-}
 
-pub struct SuitSet([usize; NUM_SUITS]);
+    /// The card with value. Wildcards with the same rank are told apart by
+    /// the seat that they are dealt to.
+    pub fn from_value(value: &CardValue, wildcard_seat: usize) -> Self {
+        match *value {
+            CardValue::Normal { rank, suit } if suit < NUM_SUITS => {
+                CardId(suit * NUM_RANKS + rank - MIN_RANK)
+            }
+            CardValue::Normal { rank, .. } => CardId(DECK_SIZE + rank - MIN_RANK),
+            CardValue::Wildcard { rank } => CardId(wildcard_ids(wildcard_seat).start + rank - 11),
+        }
+    }
+
+    /// The seat (0 for the player who went first, 1 for the next player, ...)
+    /// that this wildcard is dealt to, or None if this is not a wildcard
+    pub fn wildcard_seat(self) -> Option<usize> {
+        if NUM_NORMAL <= self.0 && self.0 < DECK_SIZE {
+            Some((self.0 - NUM_NORMAL) / NUM_WILDCARDS_PER_PLAYER)
+        } else if DECK_SIZE + NUM_RANKS <= self.0 && self.0 < DECK_SIZE_THREE_PLAYERS {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
 
-impl SuitSet {
-    pub fn new() -> Self {
-        SuitSet([0; NUM_SUITS])
+/// Number of cards in the deck of a game with num_players players
+pub fn deck_size(num_players: usize) -> usize {
+    match num_players {
+        2 => DECK_SIZE,
+        3 => DECK_SIZE_THREE_PLAYERS,
+        _ => panic!("deck_size: Haggis is played by 2 or 3 players"),
     }
+}
 
-    pub fn insert(&mut self, suit: usize) {
-        self.0[suit] = 1;
+/// Ids of the normal (non-wildcard) cards in the deck of a game with num_players players
+pub fn normal_card_ids(num_players: usize) -> Vec<usize> {
+    let mut card_ids: Vec<usize> = (0..NUM_NORMAL).collect();
+    if num_players == 3 {
+        card_ids.extend(DECK_SIZE..(DECK_SIZE + NUM_RANKS));
     }
+    card_ids
+}
 
-    pub fn len(&self) -> usize {
-        self.0.iter().sum()
+/// Ids of the wildcards dealt to seat
+pub fn wildcard_ids(seat: usize) -> std::ops::Range<usize> {
+    if seat < 2 {
+        let start = NUM_NORMAL + seat * NUM_WILDCARDS_PER_PLAYER;
+        start..(start + NUM_WILDCARDS_PER_PLAYER)
+    } else {
+        (DECK_SIZE + NUM_RANKS)..DECK_SIZE_THREE_PLAYERS
     }
 }
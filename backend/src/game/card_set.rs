@@ -0,0 +1,191 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{BitAnd, BitOr, Sub};
+
+use super::card::CardId;
+use super::constant::{
+    DECK_SIZE, MAX_NUM_PLAYERS, MAX_NUM_SUITS, MIN_RANK, NUM_NORMAL, NUM_RANKS,
+    NUM_WILDCARDS_PER_PLAYER,
+};
+
+// Each card id is a bit of a u64, so sets of cards can be combined and counted
+// without allocating. The normal cards of a suit are NUM_RANKS consecutive bits
+// in rank order, starting at SUIT_STARTS[suit], and the J, Q and K of a seat
+// are 3 consecutive bits starting at WILDCARD_STARTS[seat]. Shifting each suit
+// or seat down to the bottom bits and combining them gives the ranks of the set.
+
+/// The id of the 2 of each suit
+const SUIT_STARTS: [usize; MAX_NUM_SUITS] = [0, NUM_RANKS, 2 * NUM_RANKS, 3 * NUM_RANKS, DECK_SIZE];
+/// The id of the J of each seat
+const WILDCARD_STARTS: [usize; MAX_NUM_PLAYERS] = [
+    NUM_NORMAL,
+    NUM_NORMAL + NUM_WILDCARDS_PER_PLAYER,
+    DECK_SIZE + NUM_RANKS,
+];
+const RANKS_OF_SUIT: u64 = (1 << NUM_RANKS) - 1;
+const RANKS_OF_SEAT: u64 = (1 << NUM_WILDCARDS_PER_PLAYER) - 1;
+/// The first wildcard rank, J
+const MIN_WILDCARD_RANK: usize = 11;
+const WILDCARDS: CardSet = CardSet(
+    RANKS_OF_SEAT << WILDCARD_STARTS[0]
+        | RANKS_OF_SEAT << WILDCARD_STARTS[1]
+        | RANKS_OF_SEAT << WILDCARD_STARTS[2],
+);
+
+/// A set of cards, where the card with id x is in the set iff bit x is set
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct CardSet(pub u64);
+
+impl CardSet {
+    pub const EMPTY: CardSet = CardSet(0);
+
+    pub fn from_ids(card_ids: &[usize]) -> CardSet {
+        card_ids.iter().map(|&card_id| CardId(card_id)).collect()
+    }
+
+    pub fn contains(self, card_id: CardId) -> bool {
+        self.0 & 1 << card_id.0 != 0
+    }
+
+    pub fn insert(&mut self, card_id: CardId) {
+        self.0 |= 1 << card_id.0;
+    }
+
+    pub fn remove(&mut self, card_id: CardId) {
+        self.0 &= !(1 << card_id.0);
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_subset(self, other: CardSet) -> bool {
+        self.0 & !other.0 == 0
+    }
+
+    /// The cards in increasing order of id
+    pub fn iter(self) -> impl Iterator<Item = CardId> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let card_id = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            Some(CardId(card_id))
+        })
+    }
+
+    /// The cards that are not wildcards
+    pub fn normal_cards(self) -> CardSet {
+        self - WILDCARDS
+    }
+
+    /// A mask with bit r set iff a card of rank r is in the set, including
+    /// the wildcard ranks 11 (J) to 13 (K)
+    pub fn ranks(self) -> u32 {
+        let mut normal_ranks = 0;
+        for &start in &SUIT_STARTS {
+            normal_ranks |= self.0 >> start & RANKS_OF_SUIT;
+        }
+        let mut wildcard_ranks = 0;
+        for &start in &WILDCARD_STARTS {
+            wildcard_ranks |= self.0 >> start & RANKS_OF_SEAT;
+        }
+        (normal_ranks << MIN_RANK | wildcard_ranks << MIN_WILDCARD_RANK) as u32
+    }
+
+    /// The number of suits that the normal cards of the set have
+    pub fn suit_count(self) -> usize {
+        SUIT_STARTS
+            .iter()
+            .filter(|&&start| self.0 >> start & RANKS_OF_SUIT != 0)
+            .count()
+    }
+}
+
+impl FromIterator<CardId> for CardSet {
+    fn from_iter<I: IntoIterator<Item = CardId>>(card_ids: I) -> Self {
+        let mut set = CardSet::EMPTY;
+        for card_id in card_ids {
+            set.insert(card_id);
+        }
+        set
+    }
+}
+
+impl BitOr for CardSet {
+    type Output = CardSet;
+
+    fn bitor(self, other: CardSet) -> CardSet {
+        CardSet(self.0 | other.0)
+    }
+}
+
+impl BitAnd for CardSet {
+    type Output = CardSet;
+
+    fn bitand(self, other: CardSet) -> CardSet {
+        CardSet(self.0 & other.0)
+    }
+}
+
+/// The cards of self that are not in other
+impl Sub for CardSet {
+    type Output = CardSet;
+
+    fn sub(self, other: CardSet) -> CardSet {
+        CardSet(self.0 & !other.0)
+    }
+}
+
+/// Sets are written as their cards, e.g. {2♠, 10♥, J}
+impl fmt::Debug for CardSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self
+            .iter()
+            .map(|card_id| card_id.to_value().to_string())
+            .collect();
+        write!(f, "{{{}}}", names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::card::{normal_card_ids, wildcard_ids, CardValue};
+
+    #[test]
+    fn test_ranks_and_suits() {
+        for card_id in 0..crate::game::constant::DECK_SIZE_THREE_PLAYERS {
+            let set = CardSet::from_ids(&[card_id]);
+            assert_eq!(set.ranks(), 1 << CardId(card_id).to_value().rank());
+            match CardId(card_id).to_value() {
+                CardValue::Normal { .. } => assert_eq!(set.suit_count(), 1),
+                CardValue::Wildcard { .. } => assert_eq!(set.normal_cards(), CardSet::EMPTY),
+            }
+        }
+
+        let set = CardSet::from_ids(&normal_card_ids(3));
+        assert_eq!(set.suit_count(), MAX_NUM_SUITS);
+        assert_eq!(set.ranks(), 0b11111111100);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut set: CardSet = wildcard_ids(1).map(CardId).collect();
+        assert_eq!(set.len(), 3);
+        set.insert(CardId(4));
+        set.remove(CardId(40));
+        assert!(set.contains(CardId(4)));
+        assert!(!set.contains(CardId(40)));
+        assert_eq!(set.iter().collect::<Vec<_>>(), [4, 39, 41].map(CardId));
+        assert!(CardSet::from_ids(&[39, 41]).is_subset(set));
+        assert_eq!(set - CardSet::from_ids(&[39, 41]), CardSet::from_ids(&[4]));
+        assert_eq!(format!("{:?}", set), "{6♠, J, K}");
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use super::card::{CardId, CardValue};
+use super::card_set::CardSet;
+use super::combination_type::{CombinationType, NormalType};
+use super::location::Location;
+use super::wildcard_shape::WildcardShape;
+use super::Game;
+
+/// What kind of combination a group of cards forms
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CombinationKind {
+    Single,
+    /// Several cards of the same rank
+    Set,
+    /// Several ranks in a row in one suit
+    Sequence,
+    /// Several ranks in a row, with the same number of suits for each rank
+    MultiSuitSequence,
+    /// Extra wildcards that could either add ranks or add suits, because no
+    /// shape was declared and no other combination decided it
+    Undecided,
+    /// 3-5-7-9 in four different suits, without wildcards
+    RainbowBomb,
+    JQBomb,
+    JKBomb,
+    QKBomb,
+    JQKBomb,
+    /// 3-5-7-9 in one suit, without wildcards
+    SuitedBomb,
+}
+
+/// Bombs from lowest to highest, in the order of their bomb ranks
+const BOMBS: [CombinationKind; 6] = [
+    CombinationKind::RainbowBomb,
+    CombinationKind::JQBomb,
+    CombinationKind::JKBomb,
+    CombinationKind::QKBomb,
+    CombinationKind::JQKBomb,
+    CombinationKind::SuitedBomb,
+];
+
+/// A combination of cards and how the wildcards in it were used, e.g. three 7s
+/// or the 4-card sequence 5–8♥
+#[wasm_bindgen]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Combination {
+    pub kind: CombinationKind,
+    /// The lowest rank of the combination, counting wildcards as the ranks they
+    /// stand for. Ranks 11 to 13 are J, Q and K.
+    pub start_rank: usize,
+    /// The highest rank of the combination
+    pub end_rank: usize,
+    /// How many cards there are of each rank
+    pub suit_count: usize,
+    pub num_wildcards: usize,
+    /// The suit of a sequence or single, if it has a normal card
+    suit: Option<usize>,
+    /// The combination as it would be with each shape, if it is undecided
+    shapes: Option<Box<(Combination, Combination)>>,
+}
+
+impl Combination {
+    /// Describe cards that form combination_type
+    pub(super) fn new(cards: CardSet, combination_type: &CombinationType) -> Combination {
+        match combination_type {
+            CombinationType::Bomb(bomb_rank) => {
+                let ranks = cards.ranks();
+                Combination {
+                    kind: BOMBS[*bomb_rank],
+                    start_rank: ranks.trailing_zeros() as usize,
+                    end_rank: 31 - ranks.leading_zeros() as usize,
+                    // Every bomb has one card of each of its ranks
+                    suit_count: cards.len() / ranks.count_ones() as usize,
+                    num_wildcards: (cards - cards.normal_cards()).len(),
+                    suit: None,
+                    shapes: None,
+                }
+            }
+            CombinationType::Normal(normal_type) => Combination::new_normal(cards, normal_type),
+        }
+    }
+
+    fn new_normal(cards: CardSet, normal_type: &NormalType) -> Combination {
+        let normal_cards = cards.normal_cards();
+        let suit = normal_cards
+            .iter()
+            .next()
+            .map(|card_id| match card_id.to_value() {
+                CardValue::Normal { suit, .. } => suit,
+                CardValue::Wildcard { .. } => unreachable!(),
+            });
+        let shapes = if normal_type.is_ambiguous() {
+            Some(Box::new((
+                Combination::new_normal(cards, &normal_type.with_shape(WildcardShape::MoreSuits)),
+                Combination::new_normal(cards, &normal_type.with_shape(WildcardShape::MoreRanks)),
+            )))
+        } else {
+            None
+        };
+        let kind = match (
+            shapes.is_some(),
+            normal_type.rank_count(),
+            normal_type.suit_count,
+        ) {
+            (true, _, _) => CombinationKind::Undecided,
+            (false, 1, 1) => CombinationKind::Single,
+            (false, 1, _) => CombinationKind::Set,
+            (false, _, 1) => CombinationKind::Sequence,
+            (false, _, _) => CombinationKind::MultiSuitSequence,
+        };
+        Combination {
+            kind,
+            start_rank: normal_type.start_rank,
+            end_rank: normal_type.end_rank,
+            suit_count: normal_type.suit_count,
+            num_wildcards: (cards - normal_cards).len(),
+            suit: suit.filter(|_| normal_type.suit_count == 1),
+            shapes,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Combination {
+    /// How the combination is read out, e.g. "three 7s" or "J-Q bomb"
+    pub fn description(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Ranks are written like cards, e.g. 10 or J
+fn rank_name(rank: usize) -> String {
+    match rank {
+        11..=13 => CardValue::Wildcard { rank }.to_string(),
+        _ => rank.to_string(),
+    }
+}
+
+impl fmt::Display for Combination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let suit = match self.suit {
+            Some(suit) => ['♠', '♥', '♦', '♣', '★'][suit].to_string(),
+            None => String::new(),
+        };
+        let start = rank_name(self.start_rank);
+        let end = rank_name(self.end_rank);
+        let rank_count = self.end_rank - self.start_rank + 1;
+        match self.kind {
+            CombinationKind::Single => write!(f, "single {}{}", start, suit),
+            CombinationKind::Set => {
+                let count = ["two", "three", "four", "five", "six", "seven", "eight"]
+                    .get(self.suit_count - 2)
+                    .map_or(self.suit_count.to_string(), |word| word.to_string());
+                write!(f, "{} {}s", count, start)
+            }
+            CombinationKind::Sequence => {
+                write!(f, "{}-card sequence {}–{}{}", rank_count, start, end, suit)
+            }
+            CombinationKind::MultiSuitSequence => {
+                write!(f, "{}-suit sequence {}–{}", self.suit_count, start, end)
+            }
+            CombinationKind::Undecided => {
+                let (more_suits, more_ranks) = &**self.shapes.as_ref().unwrap();
+                write!(f, "{} or {}", more_suits, more_ranks)
+            }
+            CombinationKind::RainbowBomb => f.write_str("rainbow 3-5-7-9 bomb"),
+            CombinationKind::JQBomb => f.write_str("J-Q bomb"),
+            CombinationKind::JKBomb => f.write_str("J-K bomb"),
+            CombinationKind::QKBomb => f.write_str("Q-K bomb"),
+            CombinationKind::JQKBomb => f.write_str("J-Q-K bomb"),
+            CombinationKind::SuitedBomb => f.write_str("suited 3-5-7-9 bomb"),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Game {
+    /// The combination that was just played, or undefined if its group has
+    /// been captured
+    pub fn last_combination(&self) -> Option<Combination> {
+        let combination_type = self.last_combination_type.as_ref()?;
+        let cards = self
+            .locations
+            .iter()
+            .enumerate()
+            .filter_map(|(card_id, location)| match location {
+                Location::Table {
+                    captured_by: None,
+                    order,
+                    ..
+                } if order + 1 == self.next_order => Some(CardId(card_id)),
+                _ => None,
+            })
+            .collect();
+        Some(Combination::new(cards, combination_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::player::Player;
+    use crate::game::tests::game_with_hands;
+
+    // 7♣ J Q, which can be 7♣ 8♣ 9♣ or three 7s
+    const AMBIGUOUS: [usize; 3] = [32, 36, 37];
+
+    /// A game where I hold 5♥ 6♥ 7♥ 8♥ 7♣ and the opponent holds 5♠ 6♠ 8♠ 9♠ 10♠
+    fn new_combination_game() -> Game {
+        game_with_hands(&[12, 13, 14, 15, 32], &[3, 4, 6, 7, 8])
+    }
+
+    fn last_description(game: &Game) -> String {
+        game.last_combination().unwrap().description()
+    }
+
+    #[test]
+    fn test_normal_combinations() {
+        let mut game = new_combination_game();
+        assert_eq!(game.last_combination(), None);
+
+        game.try_play_cards_with_shape(Player::First, &AMBIGUOUS, Some(WildcardShape::MoreSuits))
+            .unwrap();
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::Set);
+        assert_eq!((combination.start_rank, combination.end_rank), (7, 7));
+        assert_eq!(combination.num_wildcards, 2);
+        assert_eq!(combination.description(), "three 7s");
+
+        let mut game = new_combination_game();
+        game.play_cards(&[12, 13, 14, 15]);
+        assert_eq!(last_description(&game), "4-card sequence 5–8♥");
+
+        let mut game = new_combination_game();
+        game.play_cards(&[12, 13, 14]);
+        game.play_cards(&[6, 7, 8]);
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::Sequence);
+        assert_eq!(combination.description(), "3-card sequence 8–10♠");
+
+        let mut game = new_combination_game();
+        game.play_cards(&[32]);
+        assert_eq!(last_description(&game), "single 7♣");
+        game.play_cards(&[8]);
+        assert_eq!(last_description(&game), "single 10♠");
+        game.play_cards(&[36]);
+        assert_eq!(last_description(&game), "single J");
+    }
+
+    #[test]
+    fn test_multi_suit_sequence() {
+        let mut game = new_combination_game();
+        game.locations.set(3, Location::Hand(Player::First));
+        game.locations.set(4, Location::Hand(Player::First));
+        game.play_cards(&[3, 4, 12, 13]);
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::MultiSuitSequence);
+        assert_eq!(combination.suit_count, 2);
+        assert_eq!(combination.description(), "2-suit sequence 5–6");
+    }
+
+    #[test]
+    fn test_undecided_combination() {
+        let mut game = new_combination_game();
+        game.play_cards(&AMBIGUOUS);
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::Undecided);
+        assert_eq!(
+            combination.description(),
+            "three 7s or 3-card sequence 7–9♣"
+        );
+
+        // Beating it with a sequence decides it, but only for the new combination
+        game.play_cards(&[6, 7, 8]);
+        assert_eq!(last_description(&game), "3-card sequence 8–10♠");
+    }
+
+    #[test]
+    fn test_bombs() {
+        let mut game = new_combination_game();
+        game.play_cards(&[32]);
+        game.play_cards(&[39, 40]);
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::JQBomb);
+        assert_eq!((combination.start_rank, combination.end_rank), (11, 12));
+        assert_eq!(combination.suit_count, 1);
+        assert_eq!(combination.num_wildcards, 2);
+        assert_eq!(combination.description(), "J-Q bomb");
+
+        // Passing captures the bomb, so there is nothing left to beat
+        game.play_cards(&[]);
+        assert_eq!(game.last_combination(), None);
+    }
+
+    #[test]
+    fn test_jqk_bomb() {
+        let mut game = new_combination_game();
+        game.play_cards(&[36, 37, 38]);
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::JQKBomb);
+        assert_eq!((combination.start_rank, combination.end_rank), (11, 13));
+        assert_eq!(combination.suit_count, 1);
+        assert_eq!(combination.num_wildcards, 3);
+        assert_eq!(combination.description(), "J-Q-K bomb");
+    }
+
+    #[test]
+    fn test_suited_bomb() {
+        let mut game = new_combination_game();
+        for &card_id in &[1, 3, 5, 7] {
+            game.locations.set(card_id, Location::Hand(Player::First));
+        }
+        game.play_cards(&[1, 3, 5, 7]);
+        let combination = game.last_combination().unwrap();
+        assert_eq!(combination.kind, CombinationKind::SuitedBomb);
+        assert_eq!((combination.start_rank, combination.end_rank), (3, 9));
+        assert_eq!(combination.suit_count, 1);
+        assert_eq!(combination.num_wildcards, 0);
+    }
+}
@@ -1,5 +1,6 @@
-use super::card::{CardValue, SuitSet};
-use super::constant::{MAX_RANK, MIN_RANK};
+use super::card::CardValue;
+use super::card_set::CardSet;
+use super::wildcard_shape::WildcardShape;
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum CombinationType {
@@ -8,14 +9,14 @@ pub enum CombinationType {
 }
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct NormalType {
-    start_rank: usize,
-    end_rank: usize,
-    suit_count: usize,
-    num_extra_wildcards: usize,
+    pub(super) start_rank: usize,
+    pub(super) end_rank: usize,
+    pub(super) suit_count: usize,
+    pub(super) num_extra_wildcards: usize,
 }
 
 impl NormalType {
-    fn rank_count(&self) -> usize {
+    pub(super) fn rank_count(&self) -> usize {
         self.end_rank - self.start_rank + 1
     }
 
@@ -23,6 +24,31 @@ impl NormalType {
         self.suit_count * self.rank_count() + self.num_extra_wildcards
     }
 
+    /// Whether the extra wildcards could add either ranks or suits
+    pub fn is_ambiguous(&self) -> bool {
+        self.num_extra_wildcards > 0
+    }
+
+    /// Resolve an ambiguous type by using the extra wildcards as declared.
+    /// An ambiguous type always has enough extra wildcards to add whole ranks
+    /// or whole suits.
+    pub fn with_shape(&self, shape: WildcardShape) -> NormalType {
+        match shape {
+            WildcardShape::MoreRanks => NormalType {
+                start_rank: self.start_rank,
+                end_rank: self.end_rank + self.num_extra_wildcards / self.suit_count,
+                suit_count: self.suit_count,
+                num_extra_wildcards: 0,
+            },
+            WildcardShape::MoreSuits => NormalType {
+                start_rank: self.start_rank,
+                end_rank: self.end_rank,
+                suit_count: self.suit_count + self.num_extra_wildcards / self.rank_count(),
+                num_extra_wildcards: 0,
+            },
+        }
+    }
+
     // Checks if self is compatible with and larger than other.
     // If that is true, returns the disambiguated type of the larger normal combination, which
     // will always be self.
@@ -68,44 +94,39 @@ impl NormalType {
     }
 }
 
-/// Preassumption: card_values does not represent a bomb
-pub fn is_valid_normal(card_values: &Vec<CardValue>) -> Option<NormalType> {
-    if card_values.is_empty() {
+/// Preassumption: cards do not form a bomb
+pub fn is_valid_normal(cards: CardSet) -> Option<NormalType> {
+    if cards.is_empty() {
         return None;
     }
 
-    if card_values.len() == 1 {
+    if cards.len() == 1 {
+        let rank = cards.ranks().trailing_zeros() as usize;
         return Some(NormalType {
-            start_rank: card_values[0].rank(),
-            end_rank: card_values[0].rank(),
+            start_rank: rank,
+            end_rank: rank,
             suit_count: 1,
             num_extra_wildcards: 0,
         });
     }
 
-    let mut smallest_rank = MAX_RANK + 1;
-    let mut largest_rank = MIN_RANK - 1;
-    let mut suits = SuitSet::new();
-    let mut num_normal_cards: usize = 0;
-    for value in card_values {
-        if let CardValue::Normal { rank, suit } = value {
-            num_normal_cards += 1;
-            smallest_rank = smallest_rank.min(*rank);
-            largest_rank = largest_rank.max(*rank);
-            suits.insert(*suit);
-        }
-    }
+    let normal_cards = cards.normal_cards();
+    let normal_ranks = normal_cards.ranks();
+    let suit_count = normal_cards.suit_count();
+    let num_normal_cards = normal_cards.len();
 
-    // smallest_rank > largest_rank will happen if all the cards were wildcards.
+    // An empty rank mask will happen if all the cards were wildcards.
     // Without this check, number_of_ranks can underflow.
-    assert!(smallest_rank <= largest_rank);
+    assert!(normal_ranks != 0);
+    let smallest_rank = normal_ranks.trailing_zeros() as usize;
+    let largest_rank = 31 - normal_ranks.leading_zeros() as usize;
 
     let number_of_ranks = largest_rank - smallest_rank + 1;
-    let min_normal_size = number_of_ranks * suits.len();
+    let min_normal_size = number_of_ranks * suit_count;
     let num_required_wildcards = min_normal_size - num_normal_cards;
-    let num_wildcards = card_values.len() - num_normal_cards;
+    let num_wildcards = cards.len() - num_normal_cards;
 
-    if card_values.len() == 2 && suits.len() == 1 {
+    if cards.len() == 2 && suit_count == 1 {
         if num_wildcards == 1 {
             return Some(NormalType {
                 start_rank: smallest_rank,
@@ -124,7 +145,7 @@ pub fn is_valid_normal(card_values: &Vec<CardValue>) -> Option<NormalType> {
         let num_extra_wildcards = num_wildcards - num_required_wildcards;
 
         match (
-            num_extra_wildcards % suits.len() == 0,
+            num_extra_wildcards % suit_count == 0,
             num_extra_wildcards % number_of_ranks == 0,
         ) {
             // The number of extra wildcards doesn't fit an edge
@@ -132,22 +153,22 @@ pub fn is_valid_normal(card_values: &Vec<CardValue>) -> Option<NormalType> {
             // If we can add a vertical line (eg make the sequence longer by adding another rank)
             (true, false) => Some(NormalType {
                 start_rank: smallest_rank,
-                end_rank: largest_rank + num_extra_wildcards / suits.len(),
-                suit_count: suits.len(),
+                end_rank: largest_rank + num_extra_wildcards / suit_count,
+                suit_count,
                 num_extra_wildcards: 0,
             }),
             // If we can add a horizontal line (eg make the set bigger by adding another suit)
             (false, true) => Some(NormalType {
                 start_rank: smallest_rank,
                 end_rank: largest_rank,
-                suit_count: suits.len() + num_extra_wildcards / number_of_ranks,
+                suit_count: suit_count + num_extra_wildcards / number_of_ranks,
                 num_extra_wildcards: 0,
             }),
             // We can add either type of line, so leave it ambiguous
             (true, true) => Some(NormalType {
                 start_rank: smallest_rank,
                 end_rank: largest_rank,
-                suit_count: suits.len(),
+                suit_count,
                 num_extra_wildcards,
             }),
         }
@@ -161,54 +182,41 @@ pub fn is_valid_normal(card_values: &Vec<CardValue>) -> Option<NormalType> {
 // 4:    J-Q-K
 // 5:    3-5-7-9 (these 4 ranks in one suit, no wild cards)
 // None: not a bomb
-pub fn is_bomb(card_values: &Vec<CardValue>) -> Option<usize> {
-    if card_values.len() == 4 {
-        let mut rank_bit_mask = 0;
-        for card_value in card_values {
-            let rank = card_value.rank();
-            rank_bit_mask |= 1 << rank;
-        }
+pub fn is_bomb(cards: CardSet) -> Option<usize> {
+    let rank_bit_mask = cards.ranks();
+    if cards.len() == 4 {
         if rank_bit_mask == 0b1010101000 {
             // is 3-5-7-9
-            let mut suit_bit_mask = 0;
-            for i in 0..4 {
-                let suit = match card_values[i] {
-                    CardValue::Normal { suit, .. } => suit,
-                    _ => panic!("Should never reach this line"),
-                };
-                suit_bit_mask |= 1 << suit;
-            }
-            if suit_bit_mask == 0b1111 {
-                return Some(0);
-            } else if suit_bit_mask == 0b1
-                || suit_bit_mask == 0b10
-                || suit_bit_mask == 0b100
-                || suit_bit_mask == 0b1000
-            {
-                return Some(5);
-            } else {
-                return None;
+            // The three-player game has five suits, so we count suits
+            // instead of comparing against a fixed mask
+            match cards.suit_count() {
+                4 => Some(0),
+                1 => Some(5),
+                _ => None,
             }
         } else {
-            return None;
+            None
         }
     } else {
-        let mut rank_bit_mask = 0;
-        for card_value in card_values.iter() {
-            let rank = card_value.rank();
-            rank_bit_mask |= 1 << rank;
-        }
-        println!("{}", rank_bit_mask);
-        return match rank_bit_mask {
+        match rank_bit_mask {
             0b01100000000000 => Some(1),
             0b10100000000000 => Some(2),
             0b11000000000000 => Some(3),
             0b11100000000000 => Some(4),
             _ => None,
-        };
+        }
     }
 }
 
+/// The cards with card_values, where wildcards belong to the first seat
+#[cfg(test)]
+fn card_set(card_values: &[CardValue]) -> CardSet {
+    card_values
+        .iter()
+        .map(|value| super::card::CardId::from_value(value, 0))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests_for_is_valid_normal {
 
@@ -218,7 +226,7 @@ mod tests_for_is_valid_normal {
     fn test_valid_normal_single() {
         let card_values: Vec<CardValue> = vec!["2♦"].iter().map(|s| s.parse().unwrap()).collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 2,
                 end_rank: 2,
@@ -232,7 +240,7 @@ mod tests_for_is_valid_normal {
     fn test_valid_wildcard_single() {
         let card_values: Vec<CardValue> = vec!["Q"].iter().map(|s| s.parse().unwrap()).collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 12,
                 end_rank: 12,
@@ -249,7 +257,7 @@ mod tests_for_is_valid_normal {
             .map(|s| s.parse().unwrap())
             .collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 10,
                 end_rank: 10,
@@ -266,7 +274,7 @@ mod tests_for_is_valid_normal {
             .map(|s| s.parse().unwrap())
             .collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 10,
                 end_rank: 10,
@@ -282,7 +290,7 @@ mod tests_for_is_valid_normal {
             .iter()
             .map(|s| s.parse().unwrap())
             .collect();
-        assert_eq!(is_valid_normal(&card_values), None);
+        assert_eq!(is_valid_normal(card_set(&card_values)), None);
     }
 
     #[test]
@@ -292,7 +300,7 @@ mod tests_for_is_valid_normal {
             .map(|s| s.parse().unwrap())
             .collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 7,
                 end_rank: 9,
@@ -309,7 +317,7 @@ mod tests_for_is_valid_normal {
             .map(|s| s.parse().unwrap())
             .collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 7,
                 end_rank: 11,
@@ -325,7 +333,7 @@ mod tests_for_is_valid_normal {
             .iter()
             .map(|s| s.parse().unwrap())
             .collect();
-        assert_eq!(is_valid_normal(&card_values), None);
+        assert_eq!(is_valid_normal(card_set(&card_values)), None);
     }
 
     #[test]
@@ -334,7 +342,7 @@ mod tests_for_is_valid_normal {
             .iter()
             .map(|s| s.parse().unwrap())
             .collect();
-        assert_eq!(is_valid_normal(&card_values), None);
+        assert_eq!(is_valid_normal(card_set(&card_values)), None);
     }
 
     #[test]
@@ -344,7 +352,7 @@ mod tests_for_is_valid_normal {
             .map(|s| s.parse().unwrap())
             .collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 7,
                 end_rank: 8,
@@ -361,7 +369,7 @@ mod tests_for_is_valid_normal {
             .map(|s| s.parse().unwrap())
             .collect();
         assert_eq!(
-            is_valid_normal(&card_values),
+            is_valid_normal(card_set(&card_values)),
             Some(NormalType {
                 start_rank: 2,
                 end_rank: 3,
@@ -377,7 +385,7 @@ mod tests_for_is_valid_normal {
             .iter()
             .map(|s| s.parse().unwrap())
             .collect();
-        assert_eq!(is_valid_normal(&card_values), None);
+        assert_eq!(is_valid_normal(card_set(&card_values)), None);
     }
 }
 
@@ -392,7 +400,7 @@ mod tests_for_is_bomb {
             .map(|s| s.parse().unwrap())
             .collect();
 
-        assert_eq!(is_bomb(&card_values), Some(0));
+        assert_eq!(is_bomb(card_set(&card_values)), Some(0));
     }
 
     #[test]
@@ -400,7 +408,7 @@ mod tests_for_is_bomb {
         let card_values: Vec<CardValue> =
             vec!["J", "Q"].iter().map(|s| s.parse().unwrap()).collect();
 
-        assert_eq!(is_bomb(&card_values), Some(1));
+        assert_eq!(is_bomb(card_set(&card_values)), Some(1));
     }
 
     #[test]
@@ -408,7 +416,7 @@ mod tests_for_is_bomb {
         let card_values: Vec<CardValue> =
             vec!["J", "K"].iter().map(|s| s.parse().unwrap()).collect();
 
-        assert_eq!(is_bomb(&card_values), Some(2));
+        assert_eq!(is_bomb(card_set(&card_values)), Some(2));
     }
 
     #[test]
@@ -416,7 +424,7 @@ mod tests_for_is_bomb {
         let card_values: Vec<CardValue> =
             vec!["Q", "K"].iter().map(|s| s.parse().unwrap()).collect();
 
-        assert_eq!(is_bomb(&card_values), Some(3));
+        assert_eq!(is_bomb(card_set(&card_values)), Some(3));
     }
 
     #[test]
@@ -426,7 +434,7 @@ mod tests_for_is_bomb {
             .map(|s| s.parse().unwrap())
             .collect();
 
-        assert_eq!(is_bomb(&card_values), Some(4));
+        assert_eq!(is_bomb(card_set(&card_values)), Some(4));
     }
 
     #[test]
@@ -436,7 +444,7 @@ mod tests_for_is_bomb {
             .map(|s| s.parse().unwrap())
             .collect();
 
-        assert_eq!(is_bomb(&card_values), Some(5));
+        assert_eq!(is_bomb(card_set(&card_values)), Some(5));
     }
 
     #[test]
@@ -446,7 +454,7 @@ mod tests_for_is_bomb {
             .map(|s| s.parse().unwrap())
             .collect();
 
-        assert_eq!(is_bomb(&card_values), None);
+        assert_eq!(is_bomb(card_set(&card_values)), None);
     }
 }
 
@@ -478,11 +486,13 @@ impl std::str::FromStr for CardValue {
             Some('♥') => 1,
             Some('♦') => 2,
             Some('♣') => 3,
-            Some(_) | None => 4,
+            // The fifth suit is only used in the three-player game
+            Some('★') => 4,
+            Some(_) | None => 5,
         };
         match (rank, suit) {
-            (rank @ 2..=10, suit @ 0..=3) => Ok(CardValue::Normal { rank, suit }),
-            (rank @ 11..=13, 4) => Ok(CardValue::Wildcard { rank }),
+            (rank @ 2..=10, suit @ 0..=4) => Ok(CardValue::Normal { rank, suit }),
+            (rank @ 11..=13, 5) => Ok(CardValue::Wildcard { rank }),
             _ => Err(()),
         }
     }
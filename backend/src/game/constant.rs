@@ -8,3 +8,19 @@ pub const NUM_SUITS: usize = 4;
 pub const NUM_NORMAL: usize = NUM_RANKS * NUM_SUITS;
 pub const DECK_SIZE: usize = (NUM_SUITS * NUM_RANKS) + (NUM_WILDCARDS_PER_PLAYER * NUM_PLAYERS);
 pub const INIT_HAND_SIZE_WO_WILDCARD: usize = (NUM_NORMAL - HAGGIS_SIZE) / 2;
+pub const INIT_HAND_SIZE: usize = INIT_HAND_SIZE_WO_WILDCARD + NUM_WILDCARDS_PER_PLAYER;
+pub const LITTLE_BET_POINTS: usize = 15;
+pub const BIG_BET_POINTS: usize = 30;
+/// A match ends after the hand in which a player reaches this score
+pub const TARGET_SCORE: usize = 250;
+
+// The three-player game adds a fifth suit and a third set of wildcards.
+// Hands have the same size, and the leftover cards are set aside.
+pub const MAX_NUM_PLAYERS: usize = 3;
+pub const MAX_NUM_SUITS: usize = 5;
+pub const NUM_NORMAL_THREE_PLAYERS: usize = NUM_RANKS * MAX_NUM_SUITS;
+pub const DECK_SIZE_THREE_PLAYERS: usize =
+    NUM_NORMAL_THREE_PLAYERS + (NUM_WILDCARDS_PER_PLAYER * MAX_NUM_PLAYERS);
+pub const SET_ASIDE_SIZE_THREE_PLAYERS: usize =
+    NUM_NORMAL_THREE_PLAYERS - INIT_HAND_SIZE_WO_WILDCARD * MAX_NUM_PLAYERS;
+pub const TARGET_SCORE_THREE_PLAYERS: usize = 350;
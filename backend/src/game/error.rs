@@ -0,0 +1,177 @@
+use std::fmt;
+
+use wasm_bindgen::prelude::*;
+
+/// The reason a combination (or a pass) was rejected
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GameError {
+    /// The cards do not form a bomb or a normal combination
+    InvalidCombination,
+    /// A bomb was played after a bomb of equal or higher rank
+    BombTooLow,
+    /// A normal combination was played after a bomb
+    NormalAfterBomb,
+    /// The combination does not match or beat the last combination played
+    MismatchedCombination,
+    /// Passing before anyone has played in this combination group
+    PassOnEmptyTable,
+    /// The player tried to move when it was not their turn
+    WrongTurn,
+    /// At least one card is not in the player's hand
+    CardNotInHand,
+    /// The same card was included more than once
+    DuplicateCard,
+    /// A card id does not refer to any card in the deck
+    InvalidCardId,
+    /// Bets must be declared before playing any cards
+    BetAfterPlaying,
+    /// The player has already declared a bet this hand
+    AlreadyBet,
+    /// A wildcard shape was declared for cards that can only be played one way
+    UnambiguousCombination,
+    /// No move has been played since the deal, or every move was undone
+    NothingToUndo,
+    /// No move has been undone since the last move was played
+    NothingToRedo,
+    /// The other players have seen the move, and the house rules don't allow
+    /// taking it back
+    UndoAfterShared,
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            GameError::InvalidCombination => "cards do not form a valid combination",
+            GameError::BombTooLow => "bomb rank too low",
+            GameError::NormalAfterBomb => "tried to play normal combination after a bomb",
+            GameError::MismatchedCombination => {
+                "combination does not beat the last combination played"
+            }
+            GameError::PassOnEmptyTable => "cannot pass before a combination has been played",
+            GameError::WrongTurn => "it is not this player's turn",
+            GameError::CardNotInHand => "card is not in the player's hand",
+            GameError::DuplicateCard => "card was played more than once",
+            GameError::InvalidCardId => "card id is out of range",
+            GameError::BetAfterPlaying => "cannot bet after playing a card",
+            GameError::AlreadyBet => "player has already declared a bet",
+            GameError::UnambiguousCombination => "combination can only be played one way",
+            GameError::NothingToUndo => "there is no move to take back",
+            GameError::NothingToRedo => "there is no move to redo",
+            GameError::UndoAfterShared => "the other players have already seen this move",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for GameError {}
+
+/// The reason a compressed game could not be decoded
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The data does not start with the header of a compressed game, so it
+    /// probably came from another app
+    NotAGame,
+    /// The game was compressed with a layout that this version doesn't know,
+    /// probably by a newer version of the app
+    UnsupportedVersion,
+    /// The data was damaged, because it doesn't match its checksum
+    ChecksumMismatch,
+    /// The game isn't signed with the match key, or it is signed but there is
+    /// no match key to check it with, so it may have been forged
+    Unauthenticated,
+    /// The data has a known layout, but it doesn't describe a game that could
+    /// have been played
+    Malformed,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            DecodeError::NotAGame => "data is not a compressed game",
+            DecodeError::UnsupportedVersion => "game was compressed by an unsupported version",
+            DecodeError::ChecksumMismatch => "data does not match its checksum",
+            DecodeError::Unauthenticated => "game is not signed with the match key",
+            DecodeError::Malformed => "data does not describe a valid game",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The reason a move message from another player was not applied
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MoveMessageError {
+    /// The data is not a move message, or it was damaged. It may be a whole
+    /// compressed game instead.
+    NotAMove,
+    /// The move was played in a different state than this game, so the whole
+    /// game has to be sent again
+    StateMismatch,
+    /// The move can't be played in this game
+    IllegalMove,
+    /// The message isn't signed with the match key
+    Unauthenticated,
+    /// It is my turn, so the move can't have come from another player
+    MyTurn,
+}
+
+impl fmt::Display for MoveMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            MoveMessageError::NotAMove => "data is not a move message",
+            MoveMessageError::StateMismatch => "move was played in a different state",
+            MoveMessageError::IllegalMove => "move is not legal in this game",
+            MoveMessageError::Unauthenticated => "move is not signed with the match key",
+            MoveMessageError::MyTurn => "move was made in this player's seat",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for MoveMessageError {}
+
+/// The reason a game sent by another player was not accepted
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReceiveError {
+    /// The data could not be decoded into a game
+    Undecodable,
+    /// The game isn't signed with the match key
+    Unauthenticated,
+    /// The game was dealt differently, so it is from another hand
+    DifferentGame,
+    /// The game has no moves that this game doesn't already have
+    OlderState,
+    /// A move in the game can't be played in this game, or the game takes
+    /// back moves that were already seen
+    IllegalMove,
+    /// The game has more moves than the other players make before my turn,
+    /// so a game in between was missed
+    SkippedMoves,
+    /// The game has moves in my seat that I didn't make
+    MovesInMySeat,
+    /// The game changes a bet, or has a new bet from a player who didn't
+    /// move or had already played a card
+    ChangedBets,
+}
+
+impl fmt::Display for ReceiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ReceiveError::Undecodable => "data is not a valid game",
+            ReceiveError::Unauthenticated => "game is not signed with the match key",
+            ReceiveError::DifferentGame => "game is from a different hand",
+            ReceiveError::OlderState => "game is older than this game",
+            ReceiveError::IllegalMove => "game has a move that is not legal from this game",
+            ReceiveError::SkippedMoves => "game skips moves that this game is missing",
+            ReceiveError::MovesInMySeat => "game has moves in this player's seat",
+            ReceiveError::ChangedBets => "game has a bet that can't have been made",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for ReceiveError {}
@@ -0,0 +1,282 @@
+use super::constant::{NUM_PLAYERS, TARGET_SCORE, TARGET_SCORE_THREE_PLAYERS};
+use super::Game;
+use crate::compression::{decode_key_share, encode_key_share, KEY_SHARE_LEN};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use rand::prelude::*;
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+
+// A match (called game in the rulebook) is a series of hands. After each hand,
+// the hand's score is added to each player's running total. The match ends
+// once a player's total reaches the target score, unless the totals are tied,
+// in which case another hand is played.
+//
+// The player with the lowest total leads the next hand. If the totals are tied,
+// the lead passes to the next of the tied players after the last leader. The
+// leader deals the next hand and sends it to the next player with their first
+// combination, just like the first hand of the match.
+//
+// Each hand is stored by seat, but my seat can change from hand to hand, so the
+// match counts players by the number of turns after me: 0 is me, 1 is the
+// player after me, and so on.
+//
+// Once the players agree on a match key, the match keeps it, so every hand it
+// deals or waits for is signed with it.
+
+#[wasm_bindgen]
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Match {
+    /// The final state of each hand played so far, in order
+    #[wasm_bindgen(skip)]
+    pub hands: Vec<Game>,
+    /// The running total of each player's score, counted in turns after me
+    #[wasm_bindgen(skip)]
+    pub scores: Vec<usize>,
+    #[wasm_bindgen(skip)]
+    pub target_score: usize,
+    #[wasm_bindgen(skip)]
+    pub match_key: Option<MatchKey>,
+}
+
+#[wasm_bindgen]
+pub enum MatchStage {
+    /// I lead the next hand, so I deal it
+    MyLead,
+    /// The opponent leads the next hand, so I wait for their QR code
+    OpponentLead,
+    MatchOver,
+}
+
+#[wasm_bindgen]
+impl Match {
+    pub fn new() -> Self {
+        Match::new_for_players(NUM_PLAYERS)
+    }
+
+    /// A match for 2 or 3 players, with the rulebook's target score
+    pub fn new_for_players(num_players: usize) -> Self {
+        Match {
+            hands: Vec::new(),
+            scores: vec![0; num_players],
+            target_score: if num_players == NUM_PLAYERS {
+                TARGET_SCORE
+            } else {
+                TARGET_SCORE_THREE_PLAYERS
+            },
+            match_key: None,
+        }
+    }
+
+    /// Sign every hand of the match with key, including the hand being played
+    /// when it is set, which the caller sets the key on as well
+    pub fn set_match_key(&mut self, key: &MatchKey) {
+        self.match_key = Some(key.clone());
+    }
+
+    pub fn has_match_key(&self) -> bool {
+        self.match_key.is_some()
+    }
+
+    /// Add the score of a finished hand to the running totals.
+    /// Returns false if the hand is not over yet. Recording the same hand
+    /// twice in a row has no effect.
+    pub fn record_hand(&mut self, game: &Game) -> bool {
+        if !game.is_game_over() {
+            return false;
+        }
+
+        // Store the finished hand so that a hand that was already finished
+        // is recognized as the same hand
+        let mut final_game = game.clone();
+        final_game.finish_hand();
+        let hand_score = final_game.calculate_score();
+        if self.hands.last() != Some(&final_game) {
+            for (score, hand_score) in self.scores.iter_mut().zip(hand_score.iter()) {
+                *score += hand_score;
+            }
+            self.hands.push(final_game);
+        }
+
+        true
+    }
+
+    /// return (my_score, opponent_score, ...) summed over every recorded hand
+    pub fn scores(&self) -> Box<[usize]> {
+        self.scores.clone().into_boxed_slice()
+    }
+
+    pub fn hands_played(&self) -> usize {
+        self.hands.len()
+    }
+
+    pub fn stage(&self) -> MatchStage {
+        if self.winner().is_some() {
+            MatchStage::MatchOver
+        } else if self.next_leader() == 0 {
+            MatchStage::MyLead
+        } else {
+            MatchStage::OpponentLead
+        }
+    }
+
+    /// Deal the next hand if I lead it. Otherwise, the next hand will arrive
+    /// from the opponent.
+    pub fn next_hand(&self) -> Option<Game> {
+        match self.stage() {
+            MatchStage::MyLead => {
+                // I deal, so I sit in the first seat
+                let mut game = Game::new_for_players(self.scores.len());
+                game.match_scores[..self.scores.len()].copy_from_slice(&self.scores);
+                game.match_key = self.match_key.clone();
+                Some(game)
+            }
+            _ => None,
+        }
+    }
+
+    /// A game without a deal, which waits for the next hand from the opponent
+    pub fn undealt_hand(&self) -> Game {
+        let mut game = Game::undealt();
+        game.match_key = self.match_key.clone();
+        game
+    }
+}
+
+impl Match {
+    /// The player who leads the next hand, counted in turns after me.
+    /// The first hand can be led by either player, so it is assigned to me.
+    pub fn next_leader(&self) -> usize {
+        let num_players = self.scores.len();
+        let lowest_score = *self.scores.iter().min().unwrap();
+        // Search for a tied player starting after the last leader, who sat
+        // in the first seat of the last hand
+        let mut leader = match self.hands.last() {
+            Some(last_hand) => (num_players - last_hand.viewer.index() + 1) % num_players,
+            None => 0,
+        };
+        while self.scores[leader] != lowest_score {
+            leader = (leader + 1) % num_players;
+        }
+        leader
+    }
+
+    /// The player who won the match, if it is over, counted in turns after me
+    pub fn winner(&self) -> Option<usize> {
+        let highest_score = *self.scores.iter().max().unwrap();
+        let num_leaders = self
+            .scores
+            .iter()
+            .filter(|&&score| score == highest_score)
+            .count();
+        if highest_score < self.target_score || num_leaders > 1 {
+            None
+        } else {
+            self.scores.iter().position(|&score| score == highest_score)
+        }
+    }
+}
+
+// Anyone can make up a game and send it, so the players of a match can agree
+// on a key at the start of the match and sign every game they send with it.
+// The key either comes from a code that every player types in, or from a
+// Diffie-Hellman exchange on ristretto255 between two players, who each send
+// a key share after their client id. Client ids can be read from any qr code
+// of the hand, so they can't be the key themselves. The shares only tell
+// anyone who reads them how the key was made, not what it is. The server that
+// passes the shares on could still swap them for its own and sit between the
+// players, which only a typed code rules out.
+
+/// A key shared by the players of a match, which signs the games they send
+#[wasm_bindgen]
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct MatchKey(pub(crate) [u8; 32]);
+
+#[wasm_bindgen]
+impl MatchKey {
+    /// The key of a code that both players typed in. Case and surrounding
+    /// whitespace don't matter.
+    pub fn from_code(code: &str) -> MatchKey {
+        MatchKey::derive(&[b"qr-haggis code", code.trim().to_lowercase().as_bytes()])
+    }
+}
+
+impl MatchKey {
+    fn derive(parts: &[&[u8]]) -> MatchKey {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        let mut key = [0; 32];
+        key.copy_from_slice(&hasher.finalize());
+        MatchKey(key)
+    }
+}
+
+/// My half of a key exchange with the other player, which gives the match key
+/// once their half arrives. The secret never leaves the client.
+#[wasm_bindgen]
+pub struct KeyExchange {
+    secret: Scalar,
+}
+
+#[wasm_bindgen]
+impl KeyExchange {
+    pub fn new() -> KeyExchange {
+        let mut bytes = [0; 64];
+        thread_rng().fill(&mut bytes[..]);
+        KeyExchange {
+            secret: Scalar::from_bytes_mod_order_wide(&bytes),
+        }
+    }
+
+    /// The message that sends my share to the other player
+    pub fn share_message(&self) -> Box<[u8]> {
+        encode_key_share(&self.share()).into_boxed_slice()
+    }
+
+    /// Whether data is a key share rather than a game or a move
+    pub fn is_share_message(data: &[u8]) -> bool {
+        decode_key_share(data).is_some()
+    }
+
+    /// The key of my secret and the share in the other player's message, which
+    /// is the same for both of us. Returns undefined if the message doesn't hold
+    /// a valid share.
+    pub fn key(&self, message: &[u8]) -> Option<MatchKey> {
+        let their_share = decode_key_share(message)?;
+        let their_point = CompressedRistretto(their_share).decompress()?;
+        if their_point.is_identity() {
+            return None;
+        }
+        let shared_point = (self.secret * their_point).compress();
+        let my_share = self.share();
+        let (first, second) = if my_share < their_share {
+            (my_share, their_share)
+        } else {
+            (their_share, my_share)
+        };
+        Some(MatchKey::derive(&[
+            b"qr-haggis exchange",
+            &first,
+            &second,
+            shared_point.as_bytes(),
+        ]))
+    }
+}
+
+impl KeyExchange {
+    fn share(&self) -> [u8; KEY_SHARE_LEN] {
+        (self.secret * RISTRETTO_BASEPOINT_POINT)
+            .compress()
+            .to_bytes()
+    }
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        KeyExchange::new()
+    }
+}
@@ -0,0 +1,1161 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use hmac::{Hmac, Mac, NewMac};
+use rand::prelude::*;
+use sha2::{Sha256, Sha512};
+
+use super::card::{normal_card_ids, wildcard_ids, CardId};
+use super::card_set::CardSet;
+use super::constant::INIT_HAND_SIZE_WO_WILDCARD;
+use super::error::{DecodeError, GameError};
+use super::game_match::MatchKey;
+use super::history::{History, Move};
+use super::location::Location;
+use super::player::Player;
+use super::Game;
+use crate::compression::{decode_game, encode_game};
+
+// A compressed game tells everyone where every card is, so anyone who scans a
+// qr code can read the hands of every player. A sealed game hides them, and
+// no seat, not even the one that starts the deal, learns a hand but its own.
+//
+// The deal is mental poker with commutative locks. Each normal card is a point
+// on ristretto255, and each seat locks points by multiplying them with a
+// scalar that comes from its secret. Locks commute, so they can be taken off
+// in any order. The deck goes around the table once: each seat locks every
+// card and shuffles the deck. Position i of the deck then belongs to seat
+// i / 14, and the positions after the hands are the Haggis. The deck goes
+// around once more, and each seat strips its lock off every hand but its
+// own. A seat reads its hand once every seat has stripped, by taking off its
+// own lock, which is the only one left on its hand.
+//
+// That takes extra messages before the first move. With two players the
+// first seat locks, the second locks and strips, and the first seat strips,
+// reads its hand and makes the first move in the same message. The second
+// seat reads its hand when that message arrives. With three players, six
+// messages go around the table before the first seat can read its hand. The
+// deck is sent until every seat has read its hand, which makes those messages
+// about 1.2 KB with two players and 1.6 KB with three: each one fits a single
+// qr code, but only one of the largest versions, far past the 34 bytes of a
+// compressed game. Later messages are about 50 bytes with two players and 75
+// with three, plus 66 bytes for each card they open.
+//
+// When a seat plays a normal card, it opens it: it sends the position of the
+// card in the deck and a proof that its own lock turns the point of that card
+// into the point at that position. The proof is a Chaum-Pedersen proof of
+// equal discrete logs, so it shows nothing about the other cards of the hand.
+// Each position can only be opened once. The receiver checks the proofs of
+// every card that was played since it last moved.
+//
+// The game that is sent puts stand-ins in the hidden hands: the unknown normal
+// cards with the lowest ids, handed out in seat order. The receiver swaps their
+// own cards back in. Once the hand is over, every seat sends its secret so the
+// hands can be scored, and the whole deck is decrypted: a seat that tampered
+// with the deck while it went around is caught then, since the deck no longer
+// holds every card exactly once.
+//
+// Every message ends with a tag of HMAC-SHA256 with the match key, cut down to
+// 16 bytes, so only the players of the match can take part in the deal.
+//
+// Sealed games are not a transport yet, and the app doesn't use them: it still
+// sends compressed games, which show every hand to anyone who scans them. The
+// deal messages don't fit a qr code that the app can show and a camera can
+// read, and a seat that tampers with the deck is only caught when the hand is
+// over. Until a deal fits the qr budget, hiding the hands is not done, so
+// sealed games are kept out of the wasm interface.
+
+const SECRET_LEN: usize = 32;
+const POINT_LEN: usize = 32;
+const PROOF_LEN: usize = 64;
+const TAG_LEN: usize = 16;
+
+/// The point of a normal card before anyone has locked it
+fn card_point(card_id: CardId) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(
+        &[b"qr-haggis card" as &[u8], &[card_id.0 as u8]].concat(),
+    )
+}
+
+/// The position in the deck of each normal card of seat
+fn hand_positions(seat: usize) -> Range<usize> {
+    (seat * INIT_HAND_SIZE_WO_WILDCARD)..((seat + 1) * INIT_HAND_SIZE_WO_WILDCARD)
+}
+
+/// The seats of a game with num_players players, as bits
+fn all_seats(num_players: usize) -> u8 {
+    (1 << num_players) - 1
+}
+
+/// The secret a player uses to lock the deck for one hand. It is never sent
+/// until the hand is over.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HandSecret([u8; SECRET_LEN]);
+
+impl HandSecret {
+    pub fn random() -> HandSecret {
+        let mut bytes = [0; SECRET_LEN];
+        thread_rng().fill(&mut bytes);
+        HandSecret(bytes)
+    }
+
+    /// Returns None if bytes has the wrong length
+    pub fn from_bytes(bytes: &[u8]) -> Option<HandSecret> {
+        let mut secret = [0; SECRET_LEN];
+        if bytes.len() != SECRET_LEN {
+            return None;
+        }
+        secret.copy_from_slice(bytes);
+        Some(HandSecret(secret))
+    }
+
+    pub fn to_bytes(&self) -> Box<[u8]> {
+        Box::new(self.0)
+    }
+}
+
+impl HandSecret {
+    /// The scalar that locks the deck
+    fn lock(&self) -> Scalar {
+        Scalar::hash_from_bytes::<Sha512>(&[b"qr-haggis lock" as &[u8], &self.0].concat())
+    }
+
+    /// The point that everyone can check the lock against
+    fn public_key(&self) -> RistrettoPoint {
+        self.lock() * RISTRETTO_BASEPOINT_POINT
+    }
+
+    /// Prove that my lock turns point into locked, without giving the lock away
+    fn prove(&self, point: RistrettoPoint, locked: RistrettoPoint) -> Proof {
+        let lock = self.lock();
+        let nonce = Scalar::hash_from_bytes::<Sha512>(
+            &[
+                b"qr-haggis nonce" as &[u8],
+                &self.0,
+                point.compress().as_bytes(),
+                locked.compress().as_bytes(),
+            ]
+            .concat(),
+        );
+        let challenge = Proof::challenge(
+            self.public_key(),
+            point,
+            locked,
+            nonce * RISTRETTO_BASEPOINT_POINT,
+            nonce * point,
+        );
+        Proof {
+            challenge,
+            response: nonce - challenge * lock,
+        }
+    }
+}
+
+/// A proof that the lock of public_key turns a point into a locked point
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Proof {
+    challenge: Scalar,
+    response: Scalar,
+}
+
+impl Proof {
+    fn challenge(
+        public_key: RistrettoPoint,
+        point: RistrettoPoint,
+        locked: RistrettoPoint,
+        base_commitment: RistrettoPoint,
+        point_commitment: RistrettoPoint,
+    ) -> Scalar {
+        let mut bytes = b"qr-haggis proof".to_vec();
+        for part in &[public_key, point, locked, base_commitment, point_commitment] {
+            bytes.extend_from_slice(part.compress().as_bytes());
+        }
+        Scalar::hash_from_bytes::<Sha512>(&bytes)
+    }
+
+    fn verify(
+        &self,
+        public_key: RistrettoPoint,
+        point: RistrettoPoint,
+        locked: RistrettoPoint,
+    ) -> bool {
+        let base_commitment =
+            self.response * RISTRETTO_BASEPOINT_POINT + self.challenge * public_key;
+        let point_commitment = self.response * point + self.challenge * locked;
+        self.challenge
+            == Proof::challenge(public_key, point, locked, base_commitment, point_commitment)
+    }
+
+    fn to_bytes(self) -> [u8; PROOF_LEN] {
+        let mut bytes = [0; PROOF_LEN];
+        bytes[..32].copy_from_slice(self.challenge.as_bytes());
+        bytes[32..].copy_from_slice(self.response.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Proof> {
+        let mut challenge = [0; 32];
+        let mut response = [0; 32];
+        challenge.copy_from_slice(&bytes[..32]);
+        response.copy_from_slice(&bytes[32..]);
+        Some(Proof {
+            challenge: Scalar::from_canonical_bytes(challenge)?,
+            response: Scalar::from_canonical_bytes(response)?,
+        })
+    }
+}
+
+/// Where a played card was in the deck, with the proof that it was there
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Opening {
+    position: usize,
+    proof: Proof,
+}
+
+/// The reason a sealed game could not be opened
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HiddenError {
+    /// The game inside could not be decoded
+    Decode(DecodeError),
+    /// The deal, the game or the openings are cut short or too long
+    Malformed,
+    /// The message was not signed with the match key
+    Unauthenticated,
+    /// The message is not the next step of the deal for this seat
+    OutOfOrder,
+    /// The game doesn't continue mine, or has new moves in my seat
+    NotASuccessor,
+    /// A card on the table was not in the hand of its player
+    CardNotCommitted(CardId),
+    /// A revealed secret doesn't unlock what its seat locked
+    WrongSecret,
+    /// A hand doesn't decrypt to cards, or doesn't fit the game
+    InconsistentHand(Player),
+    /// The deck doesn't decrypt to every card exactly once
+    InvalidDeal,
+}
+
+impl fmt::Display for HiddenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HiddenError::Decode(error) => error.fmt(f),
+            HiddenError::Malformed => f.write_str("hidden hands are malformed"),
+            HiddenError::Unauthenticated => f.write_str("hidden hands are not from this match"),
+            HiddenError::OutOfOrder => f.write_str("deal is out of order"),
+            HiddenError::NotASuccessor => f.write_str("game does not follow from mine"),
+            HiddenError::CardNotCommitted(card_id) => {
+                write!(f, "card {} was not in its player's hand", card_id.0)
+            }
+            HiddenError::WrongSecret => f.write_str("secret does not open its player's hand"),
+            HiddenError::InconsistentHand(player) => {
+                write!(f, "hand of seat {} does not fit the game", player.index())
+            }
+            HiddenError::InvalidDeal => f.write_str("deck does not hold every card once"),
+        }
+    }
+}
+
+impl std::error::Error for HiddenError {}
+
+/// A game in which the hands of the other players are hidden. The game holds
+/// my real hand once I have read it, and stand-ins for every hand that has not
+/// been revealed.
+#[derive(Debug, Clone)]
+pub struct SealedGame {
+    game: Game,
+    /// The public key of each seat that has locked the deck
+    public_keys: Vec<RistrettoPoint>,
+    /// The deck, locked by every seat that has locked it, with the locks of the
+    /// seats that have stripped taken off the hands of the others
+    deck: Vec<RistrettoPoint>,
+    /// The seats that have stripped their lock off the other hands, as bits
+    stripped: u8,
+    /// The seats that have read their hand, as bits
+    read: u8,
+    /// The opening of each normal card that another seat played, by card id
+    openings: BTreeMap<usize, Opening>,
+    /// The card at each of my positions in the deck, once I have read my hand
+    my_hand: Vec<CardId>,
+    /// The secrets that were revealed after the hand was over
+    revealed: Vec<Option<HandSecret>>,
+    secret: HandSecret,
+    key: MatchKey,
+}
+
+impl SealedGame {
+    /// Start the deal of a hand in the first seat, locking the deck with secret
+    pub fn deal(num_players: usize, secret: HandSecret, key: &MatchKey) -> SealedGame {
+        let mut sealed_game = SealedGame::undealt(num_players, Player::First, secret, key);
+        sealed_game.lock_deck();
+        sealed_game
+    }
+
+    /// The game as I can see it
+    pub fn game(&self) -> Game {
+        self.game.clone()
+    }
+
+    /// Whether I have read my hand. Until then the deal has to go around.
+    pub fn has_hand(&self) -> bool {
+        self.read & 1 << self.game.viewer.index() != 0
+    }
+
+    /// Play card_ids as the viewer
+    pub fn try_play_cards(&mut self, card_ids: &[usize]) -> Result<(), GameError> {
+        if !self.has_hand() {
+            // My hand is still only stand-ins
+            return Err(GameError::CardNotInHand);
+        }
+        self.game.try_play_cards(self.game.viewer, card_ids)
+    }
+
+    /// Whether every hand is known, which is needed to score a hand that is over
+    pub fn is_fully_revealed(&self) -> bool {
+        self.has_hand()
+            && (0..self.game.num_players)
+                .all(|seat| seat == self.game.viewer.index() || self.revealed[seat].is_some())
+    }
+}
+
+/// A message of a sealed game, once its tag has been checked
+struct Message {
+    num_players: usize,
+    num_locked: usize,
+    stripped: u8,
+    read: u8,
+    /// The public keys and the deck, which are only sent until every seat has
+    /// read its hand
+    deal: Option<(Vec<RistrettoPoint>, Vec<RistrettoPoint>)>,
+    game: Game,
+    openings: BTreeMap<usize, Opening>,
+    revealed: Vec<(usize, HandSecret)>,
+}
+
+impl SealedGame {
+    /// Take the next seat of a deal that was sent to me, locking the deck
+    /// with secret
+    pub fn join(
+        bytes: &[u8],
+        secret: HandSecret,
+        key: &MatchKey,
+    ) -> Result<SealedGame, HiddenError> {
+        let message = Message::from_bytes(bytes, key)?;
+        if message.deal.is_none() || message.num_locked >= message.num_players {
+            return Err(HiddenError::OutOfOrder);
+        }
+        let me = Player::from_index(message.num_locked);
+        let mut sealed_game = SealedGame::undealt(message.num_players, me, secret, key);
+        sealed_game.apply(message)?;
+        Ok(sealed_game)
+    }
+
+    /// Take in the game that was sent to me and do my part of the deal. Checks
+    /// that the game continues mine and that every card on the table was in the
+    /// hand of its player. If the game is rejected, mine is unchanged.
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<(), HiddenError> {
+        let message = Message::from_bytes(bytes, &self.key)?;
+        let mut sealed_game = self.clone();
+        sealed_game.apply(message)?;
+        *self = sealed_game;
+        Ok(())
+    }
+
+    /// The deal so far, the game with the hidden hands replaced by stand-ins,
+    /// and the openings of the cards that a seat may not have seen played.
+    /// Once the hand is over, my secret is sent as well.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let num_players = self.game.num_players;
+        let mut bytes = vec![
+            num_players as u8,
+            self.public_keys.len() as u8,
+            self.stripped,
+            self.read,
+        ];
+        if self.read != all_seats(num_players) {
+            for point in self.public_keys.iter().chain(&self.deck) {
+                bytes.extend_from_slice(point.compress().as_bytes());
+            }
+        }
+
+        let encoded_game = encode_game(&self.public_game());
+        bytes.push(encoded_game.len() as u8);
+        bytes.extend(encoded_game);
+
+        let openings = self.openings_to_send();
+        bytes.push(openings.len() as u8);
+        for (card_id, opening) in openings {
+            bytes.push(card_id.0 as u8);
+            bytes.push(opening.position as u8);
+            bytes.extend_from_slice(&opening.proof.to_bytes());
+        }
+
+        let revealed = self.revealed_secrets();
+        bytes.push(revealed.len() as u8);
+        for (seat, secret) in revealed {
+            bytes.push(seat as u8);
+            bytes.extend_from_slice(&secret.0);
+        }
+
+        let tag = tag(&self.key, &bytes);
+        bytes.extend_from_slice(&tag);
+        bytes
+    }
+
+    /// A seat that hasn't locked the deck yet, holding stand-ins
+    fn undealt(num_players: usize, me: Player, secret: HandSecret, key: &MatchKey) -> SealedGame {
+        let mut game = Game::undealt_for_players(num_players);
+        game.viewer = me;
+        for seat in 0..num_players {
+            let player = Player::from_index(seat);
+            for card_id in wildcard_ids(seat) {
+                game.locations.set(card_id, Location::Hand(player));
+            }
+        }
+        let normal_card_ids = normal_card_ids(num_players);
+        for (index, &card_id) in normal_card_ids.iter().enumerate() {
+            let seat = index / INIT_HAND_SIZE_WO_WILDCARD;
+            if seat < num_players {
+                game.locations
+                    .set(card_id, Location::Hand(Player::from_index(seat)));
+            }
+        }
+        SealedGame {
+            game,
+            public_keys: Vec::new(),
+            deck: normal_card_ids
+                .into_iter()
+                .map(|card_id| card_point(CardId(card_id)))
+                .collect(),
+            stripped: 0,
+            read: 0,
+            openings: BTreeMap::new(),
+            my_hand: Vec::new(),
+            revealed: vec![None; num_players],
+            secret,
+            key: key.clone(),
+        }
+    }
+
+    /// Lock every card of the deck and shuffle it
+    fn lock_deck(&mut self) {
+        let lock = self.secret.lock();
+        for point in &mut self.deck {
+            *point = lock * *point;
+        }
+        self.deck.shuffle(&mut thread_rng());
+        self.public_keys.push(self.secret.public_key());
+    }
+
+    /// Whether every seat has stripped, so each hand only has its own lock left
+    fn is_deck_final(&self) -> bool {
+        self.stripped == all_seats(self.game.num_players)
+    }
+
+    /// Lock, strip and read, as far as the deal has come
+    fn advance_deal(&mut self) -> Result<(), HiddenError> {
+        let num_players = self.game.num_players;
+        let me = self.game.viewer.index();
+        if self.public_keys.len() == me {
+            self.lock_deck();
+        }
+        if self.public_keys.len() == num_players && self.stripped & 1 << me == 0 {
+            let unlock = self.secret.lock().invert();
+            for seat in (0..num_players).filter(|&seat| seat != me) {
+                for position in hand_positions(seat) {
+                    self.deck[position] = unlock * self.deck[position];
+                }
+            }
+            self.stripped |= 1 << me;
+        }
+        if self.is_deck_final() && !self.has_hand() {
+            self.my_hand = self
+                .open_hand(me, &self.secret)
+                .ok_or(HiddenError::InconsistentHand(self.game.viewer))?;
+            self.read |= 1 << me;
+        }
+        Ok(())
+    }
+
+    /// The normal cards that seat was dealt, in the order of its positions, or
+    /// None if secret doesn't turn them into distinct cards
+    fn open_hand(&self, seat: usize, secret: &HandSecret) -> Option<Vec<CardId>> {
+        let unlock = secret.lock().invert();
+        let points: Vec<RistrettoPoint> = hand_positions(seat)
+            .map(|position| unlock * self.deck[position])
+            .collect();
+        self.find_cards(&points)
+    }
+
+    /// The card of each point, or None if a point isn't a card or a card
+    /// appears twice
+    fn find_cards(&self, points: &[RistrettoPoint]) -> Option<Vec<CardId>> {
+        let card_points: Vec<(CardId, RistrettoPoint)> = normal_card_ids(self.game.num_players)
+            .into_iter()
+            .map(|card_id| (CardId(card_id), card_point(CardId(card_id))))
+            .collect();
+        let mut cards = Vec::new();
+        for point in points {
+            let &(card_id, _) = card_points.iter().find(|(_, card)| card == point)?;
+            if cards.contains(&card_id) {
+                return None;
+            }
+            cards.push(card_id);
+        }
+        Some(cards)
+    }
+
+    /// Check the rest of the deck once every secret is known: the Haggis still
+    /// has every lock on it, and together with the hands it has to hold every
+    /// normal card once
+    fn check_deal(&self, hands: &[Vec<CardId>]) -> Result<(), HiddenError> {
+        let num_players = self.game.num_players;
+        let mut unlock = self.secret.lock().invert();
+        for secret in self.revealed.iter().flatten() {
+            unlock *= secret.lock().invert();
+        }
+        let haggis: Vec<RistrettoPoint> = self.deck[hand_positions(num_players).start..]
+            .iter()
+            .map(|point| unlock * point)
+            .collect();
+        let mut dealt = self.find_cards(&haggis).ok_or(HiddenError::InvalidDeal)?;
+        dealt.extend(hands.iter().flatten());
+        dealt.sort_by_key(|card_id| card_id.0);
+        let normal_card_ids: Vec<CardId> = normal_card_ids(num_players)
+            .into_iter()
+            .map(CardId)
+            .collect();
+        if dealt != normal_card_ids {
+            return Err(HiddenError::InvalidDeal);
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, message: Message) -> Result<(), HiddenError> {
+        let num_players = self.game.num_players;
+        let me = self.game.viewer.index();
+        if message.num_players != num_players {
+            return Err(HiddenError::OutOfOrder);
+        }
+        match message.deal {
+            Some((public_keys, deck)) => {
+                // The deal only moves forward, and my own part of it stays
+                let num_known = self.public_keys.len();
+                if public_keys.len() < num_known
+                    || public_keys[..num_known] != self.public_keys[..]
+                    || message.stripped & self.stripped != self.stripped
+                    || (self.is_deck_final() && deck != self.deck)
+                {
+                    return Err(HiddenError::OutOfOrder);
+                }
+                self.public_keys = public_keys;
+                self.deck = deck;
+                self.stripped = message.stripped;
+                self.read |= message.read;
+            }
+            // Only sent once every seat, me included, has read its hand
+            None if self.has_hand() => {}
+            None => return Err(HiddenError::OutOfOrder),
+        }
+        self.advance_deal()?;
+
+        let game = message.game;
+        self.check_moves(&game, &message.openings)?;
+        for (seat, secret) in message.revealed {
+            if self.public_keys.get(seat) != Some(&secret.public_key()) {
+                return Err(HiddenError::WrongSecret);
+            }
+            // My own secret comes back once it has gone around the table
+            if seat != me {
+                self.revealed[seat] = Some(secret);
+            }
+        }
+        self.update_game(game)
+    }
+
+    /// Check that game continues mine without new moves in my seat, and that
+    /// every normal card the other seats played was opened from their hand
+    fn check_moves(
+        &mut self,
+        game: &Game,
+        openings: &BTreeMap<usize, Opening>,
+    ) -> Result<(), HiddenError> {
+        let me = self.game.viewer;
+        let history = History::from_game(game).ok_or(HiddenError::Malformed)?;
+        let my_history = History::from_game(&self.game).ok_or(HiddenError::Malformed)?;
+        let num_known = my_history.moves.len();
+        let movers = movers(&history);
+        if history.moves.len() < num_known
+            || history.moves[..num_known] != my_history.moves[..]
+            || movers[num_known..].contains(&me)
+        {
+            return Err(HiddenError::NotASuccessor);
+        }
+
+        // Every seat was dealt a full hand, whatever its stand-ins are
+        for seat in 0..game.num_players {
+            let player = Player::from_index(seat);
+            let dealt = history.deal.locations.hand(player).normal_cards();
+            if dealt.len() != INIT_HAND_SIZE_WO_WILDCARD {
+                return Err(HiddenError::InconsistentHand(player));
+            }
+        }
+
+        let mut checked = BTreeMap::new();
+        let mut is_opened = vec![false; self.deck.len()];
+        for (&mover, played_move) in movers.iter().zip(&history.moves) {
+            if mover == me {
+                continue;
+            }
+            for &card_id in move_cards(played_move) {
+                if card_id.wildcard_seat().is_some() {
+                    continue;
+                }
+                let not_committed = HiddenError::CardNotCommitted(card_id);
+                let opening = match self.openings.get(&card_id.0) {
+                    Some(opening) => *opening,
+                    None => {
+                        let opening = *openings.get(&card_id.0).ok_or(not_committed)?;
+                        let seat = mover.index();
+                        let is_valid = self.is_deck_final()
+                            && hand_positions(seat).contains(&opening.position)
+                            && opening.proof.verify(
+                                self.public_keys[seat],
+                                card_point(card_id),
+                                self.deck[opening.position],
+                            );
+                        if !is_valid {
+                            return Err(not_committed);
+                        }
+                        opening
+                    }
+                };
+                // A position holds one card, so it can only be opened once
+                if is_opened[opening.position] {
+                    return Err(not_committed);
+                }
+                is_opened[opening.position] = true;
+                checked.insert(card_id.0, opening);
+            }
+        }
+        self.openings = checked;
+        Ok(())
+    }
+
+    /// Take game as mine, swapping in every hand I know for its stand-ins
+    fn update_game(&mut self, mut game: Game) -> Result<(), HiddenError> {
+        let num_players = game.num_players;
+        let me = self.game.viewer.index();
+        game.viewer = self.game.viewer;
+        game.match_key = None;
+        game.mark_shared();
+        self.game = game;
+
+        let on_table: CardSet = self.normal_cards_on_table().into_iter().collect();
+        let mut hands = Vec::new();
+        for seat in 0..num_players {
+            let player = Player::from_index(seat);
+            let dealt = if seat == me {
+                if !self.has_hand() {
+                    continue;
+                }
+                self.my_hand.clone()
+            } else {
+                match &self.revealed[seat] {
+                    Some(secret) => self
+                        .open_hand(seat, secret)
+                        .ok_or(HiddenError::InconsistentHand(player))?,
+                    None => continue,
+                }
+            };
+            let hand = dealt.iter().copied().collect::<CardSet>() - on_table;
+            hands.push((seat, dealt, hand));
+        }
+
+        let mut is_known = vec![false; num_players];
+        for (seat, _, hand) in &hands {
+            self.place_hand(Player::from_index(*seat), *hand, &is_known)?;
+            is_known[*seat] = true;
+        }
+        // Hand the stand-ins out again, so that they only depend on what I know
+        let is_hidden: Vec<bool> = is_known.iter().map(|is_known| !is_known).collect();
+        self.game = with_stand_ins(&self.game, &is_hidden);
+        if hands.len() == num_players {
+            let dealt: Vec<Vec<CardId>> = hands.into_iter().map(|(_, dealt, _)| dealt).collect();
+            self.check_deal(&dealt)?;
+        }
+        Ok(())
+    }
+
+    /// The normal cards on the table, in increasing order of card id
+    fn normal_cards_on_table(&self) -> Vec<CardId> {
+        self.game
+            .locations
+            .iter()
+            .enumerate()
+            .filter(|(card_id, location)| {
+                matches!(location, Location::Table { .. })
+                    && CardId(*card_id).wildcard_seat().is_none()
+            })
+            .map(|(card_id, _)| CardId(card_id))
+            .collect()
+    }
+
+    /// My secret once the hand is over, and the secrets I was sent
+    fn revealed_secrets(&self) -> Vec<(usize, &HandSecret)> {
+        let me = self.game.viewer.index();
+        (0..self.game.num_players)
+            .filter_map(|seat| {
+                if seat == me {
+                    Some(&self.secret).filter(|_| self.has_hand() && self.game.is_game_over())
+                } else {
+                    self.revealed[seat].as_ref()
+                }
+                .map(|secret| (seat, secret))
+            })
+            .collect()
+    }
+
+    /// The openings of the normal cards that were played since the seat that
+    /// moved least recently last moved, since that seat has seen the rest
+    fn openings_to_send(&self) -> Vec<(CardId, Opening)> {
+        let me = self.game.viewer;
+        let history = match History::from_game(&self.game) {
+            Some(history) => history,
+            None => return Vec::new(),
+        };
+        let movers = movers(&history);
+        let since = (0..self.game.num_players)
+            .map(Player::from_index)
+            .filter(|&seat| seat != me)
+            .map(|seat| {
+                movers
+                    .iter()
+                    .rposition(|&mover| mover == seat)
+                    .map_or(0, |index| index + 1)
+            })
+            .min()
+            .unwrap_or(0);
+
+        let mut openings = Vec::new();
+        for (&mover, played_move) in movers.iter().zip(&history.moves).skip(since) {
+            for &card_id in move_cards(played_move) {
+                if card_id.wildcard_seat().is_some() {
+                    continue;
+                }
+                let opening = if mover == me {
+                    let start = hand_positions(me.index()).start;
+                    let index = self.my_hand.iter().position(|&mine| mine == card_id);
+                    match index {
+                        Some(index) => Opening {
+                            position: start + index,
+                            proof: self
+                                .secret
+                                .prove(card_point(card_id), self.deck[start + index]),
+                        },
+                        None => continue,
+                    }
+                } else {
+                    match self.openings.get(&card_id.0) {
+                        Some(opening) => *opening,
+                        None => continue,
+                    }
+                };
+                openings.push((card_id, opening));
+            }
+        }
+        openings
+    }
+
+    /// The game with the normal cards of each hand that hasn't been revealed
+    /// replaced by stand-ins
+    fn public_game(&self) -> Game {
+        let revealed = self.revealed_secrets();
+        let is_hidden: Vec<bool> = (0..self.game.num_players)
+            .map(|seat| revealed.iter().all(|&(revealed, _)| revealed != seat))
+            .collect();
+        with_stand_ins(&self.game, &is_hidden)
+    }
+
+    /// Swap the real normal cards of player into their hand, in place of the
+    /// stand-ins. The cards can only come from hands that are not known yet and
+    /// from the cards that were not dealt.
+    fn place_hand(
+        &mut self,
+        player: Player,
+        hand: CardSet,
+        is_known: &[bool],
+    ) -> Result<(), HiddenError> {
+        let locations = &mut self.game.locations;
+        let stand_ins = locations.hand(player).normal_cards();
+        if stand_ins.len() != hand.len() {
+            return Err(HiddenError::InconsistentHand(player));
+        }
+        for (card_id, stand_in) in (hand - stand_ins).iter().zip((stand_ins - hand).iter()) {
+            match locations[card_id.0] {
+                Location::Haggis => {}
+                Location::Hand(other) if !is_known[other.index()] => {}
+                _ => return Err(HiddenError::InconsistentHand(player)),
+            }
+            locations.swap(card_id.0, stand_in.0);
+        }
+        Ok(())
+    }
+}
+
+impl Message {
+    /// Check the tag of bytes and split them into the parts of a message
+    fn from_bytes(bytes: &[u8], key: &MatchKey) -> Result<Message, HiddenError> {
+        if bytes.len() < TAG_LEN {
+            return Err(HiddenError::Malformed);
+        }
+        let (mut bytes, received_tag) = bytes.split_at(bytes.len() - TAG_LEN);
+        if tag(key, bytes)[..] != *received_tag {
+            return Err(HiddenError::Unauthenticated);
+        }
+
+        let header = take(&mut bytes, 4)?;
+        let (num_players, num_locked) = (header[0] as usize, header[1] as usize);
+        let (stripped, read) = (header[2], header[3]);
+        if !(2..=3).contains(&num_players)
+            || num_locked > num_players
+            || stripped & !all_seats(num_players) != 0
+            || read & !all_seats(num_players) != 0
+        {
+            return Err(HiddenError::Malformed);
+        }
+        let deal = if read == all_seats(num_players) {
+            if num_locked != num_players {
+                return Err(HiddenError::Malformed);
+            }
+            None
+        } else {
+            let mut points = || -> Result<RistrettoPoint, HiddenError> {
+                CompressedRistretto::from_slice(take(&mut bytes, POINT_LEN)?)
+                    .decompress()
+                    .ok_or(HiddenError::Malformed)
+            };
+            let public_keys = (0..num_locked)
+                .map(|_| points())
+                .collect::<Result<_, _>>()?;
+            let deck = (0..normal_card_ids(num_players).len())
+                .map(|_| points())
+                .collect::<Result<_, _>>()?;
+            Some((public_keys, deck))
+        };
+
+        let game_len = take(&mut bytes, 1)?[0] as usize;
+        let game = decode_game(take(&mut bytes, game_len)?).map_err(HiddenError::Decode)?;
+        if game.num_players != num_players {
+            return Err(HiddenError::Malformed);
+        }
+
+        let mut openings = BTreeMap::new();
+        for _ in 0..take(&mut bytes, 1)?[0] {
+            let opened = take(&mut bytes, 2)?;
+            let proof = Proof::from_bytes(take(&mut bytes, PROOF_LEN)?);
+            let opening = Opening {
+                position: opened[1] as usize,
+                proof: proof.ok_or(HiddenError::Malformed)?,
+            };
+            openings.insert(opened[0] as usize, opening);
+        }
+
+        let mut revealed = Vec::new();
+        for _ in 0..take(&mut bytes, 1)?[0] {
+            let seat = take(&mut bytes, 1)?[0] as usize;
+            let secret = HandSecret::from_bytes(take(&mut bytes, SECRET_LEN)?);
+            revealed.push((seat, secret.ok_or(HiddenError::Malformed)?));
+        }
+        if !bytes.is_empty() {
+            return Err(HiddenError::Malformed);
+        }
+
+        Ok(Message {
+            num_players,
+            num_locked,
+            stripped,
+            read,
+            deal,
+            game,
+            openings,
+            revealed,
+        })
+    }
+}
+
+/// The game with the normal cards of each hidden seat replaced by stand-ins:
+/// the unknown normal cards with the lowest ids, handed out in seat order
+fn with_stand_ins(game: &Game, is_hidden: &[bool]) -> Game {
+    let mut game = game.clone();
+    let mut unknown_cards = normal_card_ids(game.num_players)
+        .into_iter()
+        .filter(|&card_id| match game.locations[card_id] {
+            Location::Haggis => true,
+            Location::Hand(player) => is_hidden[player.index()],
+            _ => false,
+        })
+        .collect::<Vec<_>>()
+        .into_iter();
+    let hand_sizes: Vec<usize> = (0..game.num_players)
+        .map(|seat| {
+            let hand = game.locations.hand(Player::from_index(seat));
+            if is_hidden[seat] {
+                hand.normal_cards().len()
+            } else {
+                0
+            }
+        })
+        .collect();
+    for (seat, &hand_size) in hand_sizes.iter().enumerate() {
+        for card_id in unknown_cards.by_ref().take(hand_size) {
+            game.locations
+                .set(card_id, Location::Hand(Player::from_index(seat)));
+        }
+    }
+    for card_id in unknown_cards {
+        game.locations.set(card_id, Location::Haggis);
+    }
+    game
+}
+
+/// HMAC-SHA256 of a message with the match key, cut down to TAG_LEN bytes
+fn tag(key: &MatchKey, data: &[u8]) -> [u8; TAG_LEN] {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(&key.0).expect("HMAC takes keys of any length");
+    hmac.update(b"qr-haggis sealed");
+    hmac.update(data);
+    let mut tag = [0; TAG_LEN];
+    tag.copy_from_slice(&hmac.finalize().into_bytes()[..TAG_LEN]);
+    tag
+}
+
+/// The seat that made each move of history
+fn movers(history: &History) -> Vec<Player> {
+    let mut game = history.deal.clone();
+    history
+        .moves
+        .iter()
+        .map(|played_move| {
+            let mover = game.current_player;
+            game.play_move(played_move)
+                .expect("movers: every move in the history is legal");
+            mover
+        })
+        .collect()
+}
+
+/// The cards of a move, which are none for a pass
+fn move_cards(played_move: &Move) -> &[CardId] {
+    match played_move {
+        Move::Play(card_ids) | Move::PlayWithShape(card_ids, _) => card_ids,
+        Move::Pass => &[],
+    }
+}
+
+/// Split off the first len bytes
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], HiddenError> {
+    if bytes.len() < len {
+        return Err(HiddenError::Malformed);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qrcode::QrCode;
+
+    fn key() -> MatchKey {
+        MatchKey::from_code("haggis")
+    }
+
+    /// Send the deal around the table until the first seat has read its hand,
+    /// returning every seat and the messages that were sent
+    fn deal(num_players: usize) -> (Vec<SealedGame>, Vec<Vec<u8>>) {
+        let mut seats = vec![SealedGame::deal(num_players, HandSecret::random(), &key())];
+        let mut messages = vec![seats[0].to_bytes()];
+        for _ in 1..num_players {
+            let bytes = messages.last().unwrap();
+            let joined = SealedGame::join(bytes, HandSecret::random(), &key()).unwrap();
+            messages.push(joined.to_bytes());
+            seats.push(joined);
+        }
+        let mut seat = 0;
+        loop {
+            seats[seat].receive(messages.last().unwrap()).unwrap();
+            if seats[0].has_hand() {
+                return (seats, messages);
+            }
+            messages.push(seats[seat].to_bytes());
+            seat = (seat + 1) % num_players;
+        }
+    }
+
+    /// Replace the tag of a message that was changed
+    fn retag(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - TAG_LEN);
+        let tag = tag(&key(), bytes);
+        bytes.extend_from_slice(&tag);
+    }
+
+    #[test]
+    fn test_deal_is_hidden() {
+        for &(num_players, num_messages) in &[(2, 2), (3, 6)] {
+            let (seats, messages) = deal(num_players);
+            assert_eq!(messages.len(), num_messages);
+            for bytes in &messages {
+                assert!(QrCode::new(bytes).is_ok());
+            }
+
+            // Every seat that has read its hand sees only stand-ins for the
+            // others: the lowest card ids that it doesn't hold, in seat order
+            let mut dealt = CardSet::EMPTY;
+            for sealed in seats.iter().filter(|sealed| sealed.has_hand()) {
+                let game = sealed.game();
+                let hand = game.locations.hand(game.viewer).normal_cards();
+                assert_eq!(hand.len(), INIT_HAND_SIZE_WO_WILDCARD);
+                assert_eq!(dealt & hand, CardSet::EMPTY);
+                dealt = dealt | hand;
+
+                let mut unknown = normal_card_ids(num_players)
+                    .into_iter()
+                    .map(CardId)
+                    .filter(|&card_id| !hand.contains(card_id));
+                for seat in (0..num_players).filter(|&seat| seat != game.viewer.index()) {
+                    let stand_ins: CardSet = unknown.by_ref().take(hand.len()).collect();
+                    let player = Player::from_index(seat);
+                    assert_eq!(game.locations.hand(player).normal_cards(), stand_ins);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_moves_before_hand_is_read() {
+        let mut sealed = SealedGame::deal(2, HandSecret::random(), &key());
+        assert!(!sealed.has_hand());
+        let card_id = sealed.game().legal_moves().last().unwrap().clone();
+        assert_eq!(
+            sealed.try_play_cards(&card_id),
+            Err(GameError::CardNotInHand)
+        );
+    }
+
+    #[test]
+    fn test_reject_unauthenticated() {
+        let bytes = SealedGame::deal(2, HandSecret::random(), &key()).to_bytes();
+        let other_key = MatchKey::from_code("battery staple");
+        assert_eq!(
+            SealedGame::join(&bytes, HandSecret::random(), &other_key).unwrap_err(),
+            HiddenError::Unauthenticated
+        );
+        let mut damaged = bytes.clone();
+        damaged[10] ^= 1;
+        assert_eq!(
+            SealedGame::join(&damaged, HandSecret::random(), &key()).unwrap_err(),
+            HiddenError::Unauthenticated
+        );
+        assert!(SealedGame::join(&bytes, HandSecret::random(), &key()).is_ok());
+    }
+
+    #[test]
+    fn test_reject_join_when_seats_are_taken() {
+        let (seats, _) = deal(2);
+        assert_eq!(
+            SealedGame::join(&seats[1].to_bytes(), HandSecret::random(), &key()).unwrap_err(),
+            HiddenError::OutOfOrder
+        );
+    }
+
+    /// A normal card played as the first move of a two-player hand, which
+    /// still carries the deck
+    fn first_move() -> (Vec<SealedGame>, Vec<u8>) {
+        let (mut seats, _) = deal(2);
+        let hand = seats[0].game().locations.hand(Player::First);
+        let card_id = hand.normal_cards().iter().next().unwrap();
+        assert_eq!(seats[0].try_play_cards(&[card_id.0]), Ok(()));
+        let bytes = seats[0].to_bytes();
+        (seats, bytes)
+    }
+
+    #[test]
+    fn test_reject_card_not_committed() {
+        let (seats, bytes) = first_move();
+        let mut second = seats[1].clone();
+        assert_eq!(second.receive(&bytes), Ok(()));
+
+        // Damage the proof of the last card that was opened
+        let mut damaged = bytes.clone();
+        let proof_start = bytes.len() - TAG_LEN - 1 - PROOF_LEN;
+        damaged[proof_start] ^= 1;
+        retag(&mut damaged);
+        let mut second = seats[1].clone();
+        assert!(matches!(
+            second.receive(&damaged),
+            Err(HiddenError::CardNotCommitted(_))
+        ));
+        // The game is unchanged
+        assert!(!second.has_hand());
+
+        // Leave the opening out
+        let mut unopened = bytes[..(proof_start - 2)].to_vec();
+        *unopened.last_mut().unwrap() -= 1;
+        unopened.extend_from_slice(&bytes[(proof_start + PROOF_LEN)..]);
+        retag(&mut unopened);
+        assert!(matches!(
+            seats[1].clone().receive(&unopened),
+            Err(HiddenError::CardNotCommitted(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_tampered_deck() {
+        let (seats, mut bytes) = first_move();
+        // Swap a card of the second hand for the base point, which is no card
+        let position = hand_positions(1).start;
+        let offset = 4 + 2 * POINT_LEN + position * POINT_LEN;
+        bytes[offset..(offset + POINT_LEN)]
+            .copy_from_slice(RISTRETTO_BASEPOINT_POINT.compress().as_bytes());
+        retag(&mut bytes);
+        assert_eq!(
+            seats[1].clone().receive(&bytes),
+            Err(HiddenError::InconsistentHand(Player::Second))
+        );
+    }
+
+    #[test]
+    fn test_reject_wrong_secret() {
+        let (seats, bytes) = first_move();
+        // Claim that the first seat revealed a secret it doesn't hold
+        let mut revealed = bytes[..(bytes.len() - TAG_LEN)].to_vec();
+        *revealed.last_mut().unwrap() = 1;
+        revealed.push(0);
+        revealed.extend_from_slice(&HandSecret::random().0);
+        revealed.extend_from_slice(&[0; TAG_LEN]);
+        retag(&mut revealed);
+        assert_eq!(
+            seats[1].clone().receive(&revealed),
+            Err(HiddenError::WrongSecret)
+        );
+    }
+
+    #[test]
+    fn test_reject_moves_in_my_seat() {
+        let (seats, _) = deal(2);
+        let mut first = seats[0].clone();
+        let card_ids = first.game().legal_moves().last().unwrap().clone();
+        assert_eq!(first.try_play_cards(&card_ids), Ok(()));
+
+        // Only I make the moves of my seat
+        assert_eq!(
+            seats[0].clone().receive(&first.to_bytes()),
+            Err(HiddenError::NotASuccessor)
+        );
+        assert_eq!(first.clone().receive(&first.to_bytes()), Ok(()));
+    }
+}
@@ -0,0 +1,424 @@
+use super::bet::Bet;
+use super::card::CardId;
+use super::constant::{INIT_HAND_SIZE, MAX_NUM_PLAYERS, NUM_PLAYERS};
+use super::error::{GameError, ReceiveError};
+use super::location::Location;
+use super::player::Player;
+use super::wildcard_shape::WildcardShape;
+use super::Game;
+use wasm_bindgen::prelude::*;
+
+// A game only stores where each card ended up, but the order of the cards on
+// the table, the passes between them and the captures at the end of each
+// combination group are enough to replay every move of the hand from its deal.
+// The owner of a normal card on the table is whoever's turn it was when it was
+// played, and each set of wildcards belongs to a fixed seat.
+//
+// Whose turn it is depends on who still has cards, so a replay can't wait until
+// a card is played to give it an owner. Since every player is dealt the same
+// number of cards, we know how many of the cards on the table each player was
+// dealt. The replay hands them out in any order, then swaps owners whenever a
+// player plays a card that was given to someone else.
+//
+// Bets are not moves. They are declared before a player's first card, so the
+// deal of a history already includes every bet of the hand.
+//
+// Undoing a move rebuilds the game from the history without that move, so the
+// captures and turns that the move caused are undone along with it.
+
+/// A move as it was played by the player whose turn it was
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Move {
+    Play(Vec<CardId>),
+    /// A combination whose extra wildcards were declared to have shape
+    PlayWithShape(Vec<CardId>, WildcardShape),
+    Pass,
+}
+
+/// What Game::undo and Game::redo need to know besides the history
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct UndoState {
+    /// Moves that were taken back, with the most recently taken back move last
+    pub undone_moves: Vec<Move>,
+    /// The number of moves that the other players have seen
+    pub num_shared_moves: usize,
+    /// House rule: moves can be taken back after the other players have seen them
+    pub allowed_after_shared: bool,
+}
+
+/// The moves of a hand in the order they happened, along with a position
+/// that can step through them
+#[wasm_bindgen]
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct History {
+    /// The game before the first move
+    #[wasm_bindgen(skip)]
+    pub deal: Game,
+    #[wasm_bindgen(skip)]
+    pub moves: Vec<Move>,
+    /// The number of moves played to reach the current position
+    #[wasm_bindgen(skip)]
+    pub position: usize,
+}
+
+#[wasm_bindgen]
+impl History {
+    /// Recover the history of game, for example after it was decoded from a
+    /// qr code. The position starts at the end of the history.
+    /// Returns undefined if the cards on the table could not have been played.
+    pub fn from_game(game: &Game) -> Option<History> {
+        let mut combinations = vec![Vec::new(); game.next_order];
+        for (card_id, location) in game.locations.iter().enumerate() {
+            if let Location::Table { order, .. } = location {
+                combinations.get_mut(*order)?.push(CardId(card_id));
+            }
+        }
+
+        let mut replay = Replay::new(game, &combinations.concat())?;
+        for combination in combinations {
+            match game.locations[combination.first()?.0] {
+                Location::Table {
+                    after_pass,
+                    wildcard_shape,
+                    in_last_combination_before_pass,
+                    ..
+                } => {
+                    if after_pass {
+                        replay.pass()?;
+                    }
+                    replay.play(combination, wildcard_shape)?;
+                    if in_last_combination_before_pass {
+                        replay.end_group()?;
+                    }
+                }
+                _ => return None,
+            }
+        }
+        for _ in 0..game.passes {
+            replay.pass()?;
+        }
+
+        // Moves that were undone are not part of the history
+        replay.game.undo = game.undo.clone();
+        if replay.game != *game {
+            return None;
+        }
+        let position = replay.moves.len();
+        Some(History {
+            deal: replay.deal,
+            moves: replay.moves,
+            position,
+        })
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn num_moves(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Go to the next move. Returns false if we are already at the end.
+    pub fn step_forward(&mut self) -> bool {
+        self.go_to(self.position + 1)
+    }
+
+    /// Go to the previous move. Returns false if we are already at the deal.
+    pub fn step_back(&mut self) -> bool {
+        self.position > 0 && self.go_to(self.position - 1)
+    }
+
+    /// Go to the position after the first position moves.
+    /// Returns false if the history has fewer moves.
+    pub fn go_to(&mut self, position: usize) -> bool {
+        if position > self.moves.len() {
+            return false;
+        }
+        self.position = position;
+        true
+    }
+
+    /// The game at the current position
+    pub fn game(&self) -> Game {
+        self.game_at(self.position)
+    }
+}
+
+impl History {
+    /// A history that starts from deal, with no moves yet
+    pub fn new(deal: Game) -> Self {
+        History {
+            deal,
+            moves: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Rebuild the game after the first position moves.
+    /// Panics if position is past the end of the history.
+    pub fn game_at(&self, position: usize) -> Game {
+        let mut game = self.deal.clone();
+        for played_move in &self.moves[..position] {
+            game.play_move(played_move)
+                .expect("game_at: every move in the history is legal");
+        }
+        game
+    }
+
+    /// Play a move at the current position. Any moves after the current
+    /// position are replaced by the new move.
+    pub fn push(&mut self, played_move: Move) -> Result<(), GameError> {
+        self.game().play_move(&played_move)?;
+        self.moves.truncate(self.position);
+        self.moves.push(played_move);
+        self.position += 1;
+        Ok(())
+    }
+}
+
+impl Game {
+    /// Play a move on behalf of the current player
+    pub fn play_move(&mut self, played_move: &Move) -> Result<(), GameError> {
+        let (card_ids, shape) = match played_move {
+            Move::Play(cards) => (cards, None),
+            Move::PlayWithShape(cards, shape) => (cards, Some(*shape)),
+            Move::Pass => return self.try_play_cards(self.current_player, &[]),
+        };
+        let card_ids: Vec<usize> = card_ids.iter().map(|card_id| card_id.0).collect();
+        self.try_play_cards_with_shape(self.current_player, &card_ids, shape)
+    }
+
+    /// Take back the last move, restoring the game to exactly how it was
+    /// before the move
+    pub fn undo(&mut self) -> Result<(), GameError> {
+        let mut history = self.undo_target()?;
+        let undone_move = history.moves.pop().ok_or(GameError::NothingToUndo)?;
+
+        let mut undo = self.undo.clone();
+        undo.undone_moves.push(undone_move);
+        undo.num_shared_moves = undo.num_shared_moves.min(history.num_moves());
+        *self = history.game_at(history.num_moves());
+        self.undo = undo;
+        Ok(())
+    }
+
+    /// Play the last move that was taken back again
+    pub fn redo(&mut self) -> Result<(), GameError> {
+        let mut undo = self.undo.clone();
+        let redone_move = undo.undone_moves.pop().ok_or(GameError::NothingToRedo)?;
+        self.play_move(&redone_move)?;
+        self.undo = undo;
+        Ok(())
+    }
+
+    /// Check that game follows from this game by moves of the other players,
+    /// as it does when they send it to me. A game without a deal is waiting
+    /// for the first game of a hand, so it accepts any game. Every move I
+    /// made is already in this game, so game can't have new moves in my seat.
+    /// Each other player moves at most once before it is my turn again, unless
+    /// allow_skipped_moves is set, which is how a player catches up after
+    /// missing a game. A bet can only be added, by a player who moves in game
+    /// and hadn't played a card before that move.
+    pub(super) fn check_successor(
+        &self,
+        game: &Game,
+        allow_skipped_moves: bool,
+    ) -> Result<(), ReceiveError> {
+        if self.is_undealt() {
+            return Ok(());
+        }
+        let ours = History::from_game(self).ok_or(ReceiveError::DifferentGame)?;
+        let theirs = History::from_game(game).ok_or(ReceiveError::IllegalMove)?;
+        if theirs.deal.num_players != ours.deal.num_players
+            || theirs.deal.locations != ours.deal.locations
+            || theirs.deal.match_scores != ours.deal.match_scores
+        {
+            return Err(ReceiveError::DifferentGame);
+        }
+
+        let num_common_moves = ours
+            .moves
+            .iter()
+            .zip(&theirs.moves)
+            .take_while(|(our_move, their_move)| our_move == their_move)
+            .count();
+        if num_common_moves == theirs.num_moves() {
+            return Err(ReceiveError::OlderState);
+        }
+        // Only the house rules let a player take back a move that was seen
+        if num_common_moves < ours.num_moves() && !self.undo.allowed_after_shared {
+            return Err(ReceiveError::IllegalMove);
+        }
+
+        let mut game = ours.game_at(num_common_moves);
+        let new_moves = &theirs.moves[num_common_moves..];
+        let mut movers = Vec::new();
+        let mut can_bet = [false; MAX_NUM_PLAYERS];
+        for played_move in new_moves {
+            if game.current_player == self.viewer {
+                return Err(ReceiveError::MovesInMySeat);
+            }
+            if !movers.contains(&game.current_player) {
+                can_bet[game.current_player.index()] = !game.has_played_a_card(game.current_player);
+            }
+            movers.push(game.current_player);
+            game.play_move(played_move)
+                .map_err(|_| ReceiveError::IllegalMove)?;
+        }
+        let bets = ours.deal.bets.iter().zip(&theirs.deal.bets).zip(&can_bet);
+        for ((our_bet, their_bet), can_bet) in bets {
+            if their_bet != our_bet && (*our_bet != Bet::None || !can_bet) {
+                return Err(ReceiveError::ChangedBets);
+            }
+        }
+
+        // With two players, the other player's move can be followed by a
+        // pass. With three, each of the other players moves at most once.
+        let is_one_turn = if self.num_players == NUM_PLAYERS {
+            let num_plays = new_moves
+                .iter()
+                .filter(|&played_move| *played_move != Move::Pass)
+                .count();
+            new_moves.len() <= 2 && num_plays <= 1
+        } else {
+            (0..movers.len()).all(|index| !movers[..index].contains(&movers[index]))
+        };
+        if !is_one_turn && !allow_skipped_moves {
+            return Err(ReceiveError::SkippedMoves);
+        }
+        Ok(())
+    }
+
+    /// The history of the game if its last move can be taken back
+    pub(super) fn undo_target(&self) -> Result<History, GameError> {
+        let history = History::from_game(self).ok_or(GameError::NothingToUndo)?;
+        if history.num_moves() == 0 {
+            return Err(GameError::NothingToUndo);
+        }
+        if history.num_moves() <= self.undo.num_shared_moves && !self.undo.allowed_after_shared {
+            return Err(GameError::UndoAfterShared);
+        }
+        Ok(history)
+    }
+}
+
+/// Replays the combinations on the table of a game, one move at a time
+pub(crate) struct Replay {
+    /// The game before the first move, including the owners of the cards on
+    /// the table that have been found so far
+    pub deal: Game,
+    /// The game after the moves so far
+    pub game: Game,
+    pub moves: Vec<Move>,
+    /// The cards on the table that have not been played yet by the replay
+    unplayed: Vec<CardId>,
+}
+
+impl Replay {
+    /// Start replaying cards_on_table from the hands of game.
+    /// Returns None if the hand sizes don't add up to a deal.
+    pub fn new(game: &Game, cards_on_table: &[CardId]) -> Option<Self> {
+        let mut deal = Game {
+            current_player: Player::First,
+            last_combination_type: None,
+            last_player: None,
+            passes: 0,
+            first_out: None,
+            next_order: 0,
+            undo: UndoState::default(),
+            ..game.clone()
+        };
+        for card_id in 0..deal.locations.len() {
+            if let Location::Table { .. } = deal.locations[card_id] {
+                deal.locations.set(card_id, Location::Haggis);
+            }
+        }
+
+        let mut num_missing = vec![INIT_HAND_SIZE; game.num_players];
+        for location in &deal.locations {
+            if let Location::Hand(player) = location {
+                num_missing[player.index()] = num_missing[player.index()].checked_sub(1)?;
+            }
+        }
+        let (wildcards, normal_cards): (Vec<CardId>, Vec<CardId>) = cards_on_table
+            .iter()
+            .partition(|card_id| card_id.wildcard_seat().is_some());
+        for card_id in wildcards {
+            let owner = Player::from_index(card_id.wildcard_seat()?);
+            if owner.index() >= game.num_players {
+                return None;
+            }
+            num_missing[owner.index()] = num_missing[owner.index()].checked_sub(1)?;
+            deal.locations.set(card_id.0, Location::Hand(owner));
+        }
+        if normal_cards.len() != num_missing.iter().sum::<usize>() {
+            return None;
+        }
+        let mut normal_cards = normal_cards.into_iter();
+        for (player_index, &num_cards) in num_missing.iter().enumerate() {
+            for card_id in normal_cards.by_ref().take(num_cards) {
+                deal.locations
+                    .set(card_id.0, Location::Hand(Player::from_index(player_index)));
+            }
+        }
+
+        Some(Replay {
+            game: deal.clone(),
+            deal,
+            moves: Vec::new(),
+            unplayed: cards_on_table.to_vec(),
+        })
+    }
+
+    pub fn pass(&mut self) -> Option<()> {
+        self.game.play_move(&Move::Pass).ok()?;
+        self.moves.push(Move::Pass);
+        Some(())
+    }
+
+    /// Play combination as the current player, with the declared shape
+    pub fn play(&mut self, combination: Vec<CardId>, shape: Option<WildcardShape>) -> Option<()> {
+        let player = self.game.current_player;
+        self.unplayed
+            .retain(|card_id| !combination.contains(card_id));
+        for card_id in &combination {
+            let owner = match self.game.locations[card_id.0] {
+                Location::Hand(owner) => owner,
+                _ => return None,
+            };
+            if owner == player || card_id.wildcard_seat().is_some() {
+                continue;
+            }
+            // Swap with an unplayed normal card that was given to player
+            let swapped = *self.unplayed.iter().find(|other| {
+                other.wildcard_seat().is_none()
+                    && self.game.locations[other.0] == Location::Hand(player)
+            })?;
+            for game in [&mut self.deal, &mut self.game].iter_mut() {
+                game.locations.set(card_id.0, Location::Hand(player));
+                game.locations.set(swapped.0, Location::Hand(owner));
+            }
+        }
+
+        let played_move = match shape {
+            Some(shape) => Move::PlayWithShape(combination, shape),
+            None => Move::Play(combination),
+        };
+        self.game.play_move(&played_move).ok()?;
+        self.moves.push(played_move);
+        Some(())
+    }
+
+    /// End the combination group after the last combination was played
+    pub fn end_group(&mut self) -> Option<()> {
+        // The last combination group is captured when the hand is
+        // finished, without anyone passing
+        self.game.finish_hand();
+        // With three players, everyone else has to pass to end the group
+        while self.game.last_combination_type.is_some() {
+            self.pass()?;
+        }
+        Some(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeSet;
+
+use super::card::CardValue;
+use super::constant::{MAX_NUM_SUITS, MIN_RANK, NUM_PLAYERS, NUM_RANKS, NUM_SUITS};
+use super::Game;
+
+// Every normal combination fills a rectangle of ranks and suits, where the
+// missing cards are filled in by wildcards. Extra wildcards can then make the
+// rectangle longer or wider. Since a player has at most 3 wildcards, we only
+// need to look at rectangles that are missing at most 3 cards, and choose at
+// most 3 of the cards in the rectangle to replace with wildcards. Bombs and
+// combinations made only of wildcards are listed separately.
+//
+// Every candidate is checked with validate_play, so the rules for what makes a
+// valid combination stay in one place.
+
+impl Game {
+    /// Every move that the current player can legally make, with the card ids
+    /// of each move in increasing order. The empty move means passing.
+    /// Moves that need a declared WildcardShape are only listed once.
+    pub fn legal_moves(&self) -> Vec<Vec<usize>> {
+        if self.is_game_over() {
+            return Vec::new();
+        }
+
+        let player = self.current_player;
+        let num_suits = if self.num_players == NUM_PLAYERS {
+            NUM_SUITS
+        } else {
+            MAX_NUM_SUITS
+        };
+
+        // The card in my hand with each rank and suit, and my wildcards
+        let mut grid = [[None; MAX_NUM_SUITS]; NUM_RANKS];
+        let mut wildcards = Vec::new();
+        for card_id in self.get_hand(player) {
+            match card_id.to_value() {
+                CardValue::Normal { rank, suit } => grid[rank - MIN_RANK][suit] = Some(card_id.0),
+                CardValue::Wildcard { .. } => wildcards.push(card_id.0),
+            }
+        }
+        let wildcard_subsets = subsets(&wildcards, wildcards.len());
+
+        let mut candidates = BTreeSet::new();
+
+        // Passing
+        candidates.insert(Vec::new());
+
+        // Single wildcards and wildcard bombs
+        for wildcard_subset in &wildcard_subsets {
+            if !wildcard_subset.is_empty() {
+                candidates.insert(wildcard_subset.clone());
+            }
+        }
+
+        // 3-5-7-9 bombs
+        let bomb_ranks = [3, 5, 7, 9];
+        let bomb_cards: Vec<Vec<usize>> = bomb_ranks
+            .iter()
+            .map(|&rank| grid[rank - MIN_RANK].iter().flatten().copied().collect())
+            .collect();
+        for &three in &bomb_cards[0] {
+            for &five in &bomb_cards[1] {
+                for &seven in &bomb_cards[2] {
+                    for &nine in &bomb_cards[3] {
+                        let mut candidate = vec![three, five, seven, nine];
+                        candidate.sort_unstable();
+                        candidates.insert(candidate);
+                    }
+                }
+            }
+        }
+
+        // Normal combinations with at least one normal card
+        for suit_mask in 1..(1_usize << num_suits) {
+            for start_rank in 0..NUM_RANKS {
+                for end_rank in start_rank..NUM_RANKS {
+                    let mut cards_in_rectangle = Vec::new();
+                    let mut num_missing = 0;
+                    for ranked_cards in &grid[start_rank..=end_rank] {
+                        for (suit, card) in ranked_cards[0..num_suits].iter().enumerate() {
+                            if suit_mask & 1 << suit != 0 {
+                                match card {
+                                    Some(card_id) => cards_in_rectangle.push(*card_id),
+                                    None => num_missing += 1,
+                                }
+                            }
+                        }
+                    }
+                    if num_missing > wildcards.len() {
+                        // Longer rectangles with these suits can only miss more cards
+                        break;
+                    }
+
+                    for replaced in subsets(&cards_in_rectangle, wildcards.len() - num_missing) {
+                        if replaced.len() == cards_in_rectangle.len() {
+                            continue;
+                        }
+                        let num_required_wildcards = num_missing + replaced.len();
+                        for wildcard_subset in &wildcard_subsets {
+                            if wildcard_subset.len() < num_required_wildcards {
+                                continue;
+                            }
+                            let mut candidate: Vec<usize> = cards_in_rectangle
+                                .iter()
+                                .filter(|card_id| !replaced.contains(card_id))
+                                .chain(wildcard_subset)
+                                .copied()
+                                .collect();
+                            candidate.sort_unstable();
+                            candidates.insert(candidate);
+                        }
+                    }
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .filter(|card_ids| self.validate_play(player, card_ids, None).is_ok())
+            .collect()
+    }
+}
+
+/// Every subset of cards with at most max_len cards, keeping the order of cards
+fn subsets(cards: &[usize], max_len: usize) -> Vec<Vec<usize>> {
+    let mut result = vec![Vec::new()];
+    for &card in cards {
+        for i in 0..result.len() {
+            if result[i].len() < max_len {
+                let mut subset = result[i].clone();
+                subset.push(card);
+                result.push(subset);
+            }
+        }
+    }
+    result
+}
@@ -1,7 +1,15 @@
+use std::ops::Deref;
+
+use super::card::CardId;
+use super::card_set::CardSet;
+use super::constant::MAX_NUM_PLAYERS;
 use super::player::Player;
+use super::wildcard_shape::WildcardShape;
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum Location {
+    /// Cards that were not dealt. In the three-player game, these cards are
+    /// set aside instead of forming a Haggis.
     Haggis,
     Hand(Player),
     /// Table is the location of all cards that players have played.
@@ -11,6 +19,13 @@ pub enum Location {
         captured_by: Option<Player>,
         order: usize,
         in_last_combination_before_pass: bool,
+        /// Whether a player passed right before this combination was played
+        /// without ending the combination group, which can only happen with
+        /// three players.
+        after_pass: bool,
+        /// The shape that the player declared for this combination, if its
+        /// wildcards could be used in more than one way.
+        wildcard_shape: Option<WildcardShape>,
     },
 }
 
@@ -22,3 +37,78 @@ impl Location {
         }
     }
 }
+
+/// Where every card is, indexed by card id. The cards in each hand are also
+/// kept as a CardSet, so that hands can be read without looking at every
+/// location. Locations can be read like a slice, but are only changed with
+/// set and update_table, which keep the hands up to date.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Locations {
+    locations: Vec<Location>,
+    hands: [CardSet; MAX_NUM_PLAYERS],
+}
+
+impl Locations {
+    pub fn set(&mut self, card_id: usize, location: Location) {
+        if let Location::Hand(player) = self.locations[card_id] {
+            self.hands[player.index()].remove(CardId(card_id));
+        }
+        if let Location::Hand(player) = location {
+            self.hands[player.index()].insert(CardId(card_id));
+        }
+        self.locations[card_id] = location;
+    }
+
+    /// Exchange the locations of two cards
+    pub fn swap(&mut self, card_id: usize, other_card_id: usize) {
+        let location = self.locations[card_id].clone();
+        let other_location = self.locations[other_card_id].clone();
+        self.set(card_id, other_location);
+        self.set(other_card_id, location);
+    }
+
+    /// The cards in player's hand
+    pub fn hand(&self, player: Player) -> CardSet {
+        self.hands[player.index()]
+    }
+
+    /// Change each location on the table in place. Cards on the table stay
+    /// on the table.
+    pub fn update_table(&mut self, mut update: impl FnMut(&mut Location)) {
+        for location in &mut self.locations {
+            if let Location::Table { .. } = location {
+                update(location);
+                debug_assert!(matches!(location, Location::Table { .. }));
+            }
+        }
+    }
+}
+
+impl From<Vec<Location>> for Locations {
+    fn from(locations: Vec<Location>) -> Self {
+        let mut hands = [CardSet::EMPTY; MAX_NUM_PLAYERS];
+        for (card_id, location) in locations.iter().enumerate() {
+            if let Location::Hand(player) = location {
+                hands[player.index()].insert(CardId(card_id));
+            }
+        }
+        Locations { locations, hands }
+    }
+}
+
+impl Deref for Locations {
+    type Target = [Location];
+
+    fn deref(&self) -> &[Location] {
+        &self.locations
+    }
+}
+
+impl<'a> IntoIterator for &'a Locations {
+    type Item = &'a Location;
+    type IntoIter = std::slice::Iter<'a, Location>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.locations.iter()
+    }
+}
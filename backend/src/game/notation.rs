@@ -0,0 +1,360 @@
+use super::bet::Bet;
+use super::card::{deck_size, CardId, CardValue};
+use super::constant::{
+    HAGGIS_SIZE, INIT_HAND_SIZE, MAX_NUM_PLAYERS, NUM_PLAYERS, SET_ASIDE_SIZE_THREE_PLAYERS,
+};
+use super::history::{History, Move, UndoState};
+use super::location::Location;
+use super::player::Player;
+use super::wildcard_shape::WildcardShape;
+use super::Game;
+
+// A game is written as its deal followed by one move per line:
+//
+//     First: 2♠ 3♠ 4♠ ... J Q K
+//     Second: 7♥ 8♥ ... J Q K
+//     Haggis: 3♣ 4♣ ...
+//     Bets: little none
+//     2♠ 3♠ 4♠
+//     pass
+//     7♣ J Q (more suits)
+//
+// A three-player game adds a Third line, and its Haggis line lists the cards
+// that were set aside. The Bets line is left out if nobody bet. Wildcards in a
+// move belong to the player who plays it, and a declared wildcard shape is
+// written after the cards. Blank lines are ignored.
+
+const SEAT_NAMES: [&str; MAX_NUM_PLAYERS] = ["First", "Second", "Third"];
+const HAGGIS_NAME: &str = "Haggis";
+const BETS_NAME: &str = "Bets";
+const PASS: &str = "pass";
+const MORE_RANKS: &str = "(more ranks)";
+const MORE_SUITS: &str = "(more suits)";
+
+impl Game {
+    /// Write the deal and the moves of this game.
+    /// Returns None if the moves can't be recovered from the cards on the table.
+    pub fn to_notation(&self) -> Option<String> {
+        let history = History::from_game(self)?;
+        let deal = &history.deal;
+
+        let mut lines = Vec::new();
+        for (seat, name) in SEAT_NAMES[..self.num_players].iter().enumerate() {
+            let hand = deal.get_hand(Player::from_index(seat));
+            lines.push(format!("{}: {}", name, write_cards(&hand)));
+        }
+        let haggis: Vec<CardId> = (0..deal.locations.len())
+            .filter(|&card_id| deal.locations[card_id] == Location::Haggis)
+            .map(CardId)
+            .collect();
+        lines.push(format!("{}: {}", HAGGIS_NAME, write_cards(&haggis)));
+        let bets = &deal.bets[..self.num_players];
+        if bets.iter().any(|&bet| bet != Bet::None) {
+            let bets: Vec<&str> = bets.iter().map(|&bet| write_bet(bet)).collect();
+            lines.push(format!("{}: {}", BETS_NAME, bets.join(" ")));
+        }
+
+        for played_move in &history.moves {
+            lines.push(match played_move {
+                Move::Play(cards) => write_cards(cards),
+                Move::PlayWithShape(cards, WildcardShape::MoreRanks) => {
+                    format!("{} {}", write_cards(cards), MORE_RANKS)
+                }
+                Move::PlayWithShape(cards, WildcardShape::MoreSuits) => {
+                    format!("{} {}", write_cards(cards), MORE_SUITS)
+                }
+                Move::Pass => PASS.to_string(),
+            });
+        }
+
+        let mut notation = lines.join("\n");
+        notation.push('\n');
+        Some(notation)
+    }
+
+    /// Deal the hands of notation and replay its moves. The first seat uses
+    /// the game. Returns a description of the first problem, with its line
+    /// number, if the notation is not a legal game, or the invariants that the
+    /// replayed game breaks.
+    pub fn from_notation(notation: &str) -> Result<Game, String> {
+        let mut lines = notation
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .peekable();
+
+        // The deal
+        let mut hands = Vec::new();
+        let mut haggis = None;
+        let mut bets = [Bet::None; MAX_NUM_PLAYERS];
+        while let Some(&(line_number, line)) = lines.peek() {
+            let (name, rest) = match line.split_once(':') {
+                Some((name, rest)) => (name.trim(), rest),
+                None => break,
+            };
+            let error = |message: &str| format!("line {}: {}", line_number, message);
+            if name == HAGGIS_NAME {
+                let cards = read_cards(rest, None).map_err(|message| error(&message))?;
+                haggis = Some((line_number, cards));
+            } else if name == BETS_NAME {
+                let words: Vec<&str> = rest.split_whitespace().collect();
+                if words.len() != hands.len() {
+                    return Err(error("expected one bet per player"));
+                }
+                for (bet, word) in bets.iter_mut().zip(words) {
+                    *bet = read_bet(word).ok_or_else(|| error(&format!("unknown bet {}", word)))?;
+                }
+            } else if hands.len() < MAX_NUM_PLAYERS && name == SEAT_NAMES[hands.len()] {
+                let seat = hands.len();
+                let hand = read_cards(rest, Some(seat)).map_err(|message| error(&message))?;
+                if hand.len() != INIT_HAND_SIZE {
+                    return Err(error(&format!("expected {} cards", INIT_HAND_SIZE)));
+                }
+                hands.push(hand);
+            } else {
+                return Err(error(&format!("unexpected {}", name)));
+            }
+            lines.next();
+        }
+
+        let num_players = hands.len();
+        if num_players < 2 {
+            return Err("the deal needs a hand for each of 2 or 3 players".to_string());
+        }
+        let (haggis_line_number, haggis) = haggis.ok_or("the deal needs a Haggis")?;
+        let haggis_size = if num_players == NUM_PLAYERS {
+            HAGGIS_SIZE
+        } else {
+            SET_ASIDE_SIZE_THREE_PLAYERS
+        };
+        if haggis.len() != haggis_size {
+            return Err(format!(
+                "line {}: expected {} cards",
+                haggis_line_number, haggis_size
+            ));
+        }
+
+        let mut game = Game {
+            locations: vec![Location::Haggis; deck_size(num_players)].into(),
+            num_players,
+            current_player: Player::First,
+            viewer: Player::First,
+            last_combination_type: None,
+            last_player: None,
+            passes: 0,
+            first_out: None,
+            next_order: 0,
+            bets,
+            match_scores: [0; MAX_NUM_PLAYERS],
+            undo: UndoState::default(),
+            match_key: None,
+        };
+        // With the right hand sizes, every card is dealt once unless one is
+        // dealt twice
+        let mut dealt = vec![false; game.locations.len()];
+        let dealt_cards = hands
+            .iter()
+            .enumerate()
+            .flat_map(|(seat, hand)| hand.iter().map(move |&card_id| (card_id, Some(seat))))
+            .chain(haggis.iter().map(|&card_id| (card_id, None)));
+        for (card_id, seat) in dealt_cards {
+            match dealt.get_mut(card_id.0) {
+                Some(true) => return Err(format!("{} is dealt twice", card_id.to_value())),
+                Some(is_dealt) => *is_dealt = true,
+                None => {
+                    return Err(format!(
+                        "{} is not in a {}-player deck",
+                        card_id.to_value(),
+                        num_players
+                    ))
+                }
+            }
+            if let Some(seat) = seat {
+                game.locations
+                    .set(card_id.0, Location::Hand(Player::from_index(seat)));
+            }
+        }
+
+        // The moves
+        for (line_number, line) in lines {
+            let error = |message: &str| format!("line {}: {}", line_number, message);
+            let seat = Some(game.current_player.index());
+            let played_move = if line == PASS {
+                Move::Pass
+            } else if let Some(cards) = line.strip_suffix(MORE_RANKS) {
+                let cards = read_cards(cards, seat).map_err(|message| error(&message))?;
+                Move::PlayWithShape(cards, WildcardShape::MoreRanks)
+            } else if let Some(cards) = line.strip_suffix(MORE_SUITS) {
+                let cards = read_cards(cards, seat).map_err(|message| error(&message))?;
+                Move::PlayWithShape(cards, WildcardShape::MoreSuits)
+            } else {
+                Move::Play(read_cards(line, seat).map_err(|message| error(&message))?)
+            };
+            game.play_move(&played_move)
+                .map_err(|game_error| error(&game_error.to_string()))?;
+        }
+
+        game.validate().map_err(|violations| {
+            let messages: Vec<String> = violations.iter().map(ToString::to_string).collect();
+            messages.join(", ")
+        })?;
+        Ok(game)
+    }
+}
+
+fn write_cards(cards: &[CardId]) -> String {
+    let names: Vec<String> = cards
+        .iter()
+        .map(|card_id| card_id.to_value().to_string())
+        .collect();
+    names.join(" ")
+}
+
+/// Read space separated cards. Wildcards belong to wildcard_seat, and are
+/// not allowed if it is None.
+fn read_cards(cards: &str, wildcard_seat: Option<usize>) -> Result<Vec<CardId>, String> {
+    cards
+        .split_whitespace()
+        .map(|name| {
+            let value: CardValue = name
+                .parse()
+                .map_err(|_| format!("{} is not a card", name))?;
+            match (&value, wildcard_seat) {
+                (CardValue::Wildcard { .. }, None) => {
+                    Err(format!("wildcard {} does not belong to a player", name))
+                }
+                (_, seat) => Ok(CardId::from_value(&value, seat.unwrap_or(0))),
+            }
+        })
+        .collect()
+}
+
+fn write_bet(bet: Bet) -> &'static str {
+    match bet {
+        Bet::None => "none",
+        Bet::Little => "little",
+        Bet::Big => "big",
+    }
+}
+
+fn read_bet(bet: &str) -> Option<Bet> {
+    match bet {
+        "none" => Some(Bet::None),
+        "little" => Some(Bet::Little),
+        "big" => Some(Bet::Big),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORT_GAME: &str = "
+        First: 2♠ 3♠ 4♠ 5♠ 6♠ 7♠ 8♠ 9♠ 10♠ 2♥ 3♥ 4♥ 5♥ 6♥ J Q K
+        Second: 7♥ 8♥ 9♥ 10♥ 2♦ 3♦ 4♦ 5♦ 6♦ 7♦ 8♦ 9♦ 10♦ 2♣ J Q K
+        Haggis: 3♣ 4♣ 5♣ 6♣ 7♣ 8♣ 9♣ 10♣
+
+        2♠ 3♠ 4♠
+        3♦ 4♦ 5♦
+        pass
+        7♥ J 9♥
+    ";
+
+    #[test]
+    fn test_read_notation() {
+        let game = Game::from_notation(SHORT_GAME).unwrap();
+        assert_eq!(game.locations[8], Location::Hand(Player::First));
+        assert_eq!(game.locations[34], Location::Haggis);
+        // The second player captured 2♠ 3♠ 4♠ and then led 7♥ J 9♥
+        assert_eq!(game.locations[1].captured_by(), Some(Player::Second));
+        assert!(matches!(
+            game.locations[39],
+            Location::Table {
+                captured_by: None,
+                ..
+            }
+        ));
+        assert_eq!(game.current_player, Player::First);
+    }
+
+    #[test]
+    fn test_write_notation() {
+        let game = Game::from_notation(SHORT_GAME).unwrap();
+        let notation = game.to_notation().unwrap();
+        assert!(notation.starts_with("First: 2♠ 3♠ 4♠ 5♠ 6♠ 7♠ 8♠ 9♠ 10♠ 2♥ 3♥ 4♥ 5♥ 6♥ J Q K\n"));
+        assert!(notation.ends_with("\n2♠ 3♠ 4♠\n3♦ 4♦ 5♦\npass\n7♥ 9♥ J\n"));
+        assert!(!notation.contains("Bets"));
+        assert_eq!(Game::from_notation(&notation), Ok(game));
+    }
+
+    #[test]
+    fn test_notation_round_trip() {
+        for &num_players in &[2, 3] {
+            let mut game = Game::new_for_players_with_seed(num_players, 4);
+            game.declare_bet(Player::First, Bet::Big).unwrap();
+            assert_eq!(
+                Game::from_notation(&game.to_notation().unwrap()),
+                Ok(game.clone())
+            );
+            while !game.is_game_over() {
+                let legal_moves = game.legal_moves();
+                let card_ids = legal_moves
+                    .iter()
+                    .max_by_key(|card_ids| card_ids.len())
+                    .unwrap();
+                game.play_cards(card_ids);
+                let notation = game.to_notation().unwrap();
+                assert!(notation.contains("Bets: big none"));
+                assert_eq!(Game::from_notation(&notation), Ok(game.clone()));
+            }
+            let history = History::from_game(&game).unwrap();
+            let notation = game.to_notation().unwrap();
+            let num_deal_lines = num_players + 2;
+            assert_eq!(
+                notation.lines().count(),
+                num_deal_lines + history.num_moves()
+            );
+        }
+    }
+
+    #[test]
+    fn test_notation_with_wildcard_shape() {
+        let notation = SHORT_GAME.replace("7♥ J 9♥", "2♣ J Q (more suits)");
+        let game = Game::from_notation(&notation).unwrap();
+        assert_eq!(game.last_wildcard_shape(), Some(WildcardShape::MoreSuits));
+        assert!(game
+            .to_notation()
+            .unwrap()
+            .ends_with("2♣ J Q (more suits)\n"));
+    }
+
+    #[test]
+    fn test_reject_bad_notation() {
+        assert_eq!(
+            Game::from_notation(&SHORT_GAME.replace("3♦ 4♦ 5♦\n", "3♦ 4♦ 5♠\n")),
+            Err("line 7: card is not in the player's hand".to_string())
+        );
+        assert_eq!(
+            Game::from_notation(&SHORT_GAME.replace("3♦ 4♦ 5♦\n", "3♦ 4♦ 5x\n")),
+            Err("line 7: 5x is not a card".to_string())
+        );
+        assert_eq!(
+            Game::from_notation(&SHORT_GAME.replace("Haggis: 3♣", "Haggis: 2♣")),
+            Err("2♣ is dealt twice".to_string())
+        );
+        assert_eq!(
+            Game::from_notation(&SHORT_GAME.replace("Haggis: 3♣", "Haggis: J")),
+            Err("line 4: wildcard J does not belong to a player".to_string())
+        );
+        assert_eq!(
+            Game::from_notation(&SHORT_GAME.replace(" 10♣", "")),
+            Err("line 4: expected 8 cards".to_string())
+        );
+        assert_eq!(
+            Game::from_notation(&SHORT_GAME.replace("6♥ J", "6♥ 10♣ J")),
+            Err("line 2: expected 17 cards".to_string())
+        );
+    }
+}
@@ -1,14 +1,53 @@
+/// Players are named by their seat. The First player deals the hand and plays
+/// its first combination. Turns go from First to Second to Third (who only
+/// exists in three-player games) and back to First.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Player {
-    Me,
-    Opponent,
+    First,
+    Second,
+    Third,
 }
 
 impl Player {
+    /// The other player of a two-player game
     pub fn other(self) -> Self {
         match self {
-            Player::Me => Player::Opponent,
-            Player::Opponent => Player::Me,
+            Player::First => Player::Second,
+            Player::Second => Player::First,
+            Player::Third => panic!("other: two-player games have no Third player"),
+        }
+    }
+
+    /// The player whose turn comes after this player's
+    pub fn next(self, num_players: usize) -> Self {
+        Player::from_index((self.index() + 1) % num_players)
+    }
+
+    /// The player whose turn comes before this player's
+    pub fn previous(self, num_players: usize) -> Self {
+        Player::from_index((self.index() + num_players - 1) % num_players)
+    }
+
+    /// The player num_seats turns after this player
+    pub fn after(self, num_seats: usize, num_players: usize) -> Self {
+        Player::from_index((self.index() + num_seats) % num_players)
+    }
+
+    /// Index of this player in per-player arrays
+    pub fn index(self) -> usize {
+        match self {
+            Player::First => 0,
+            Player::Second => 1,
+            Player::Third => 2,
+        }
+    }
+
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            0 => Player::First,
+            1 => Player::Second,
+            2 => Player::Third,
+            _ => panic!("from_index: no player with index {}", index),
         }
     }
 }
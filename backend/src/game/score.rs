@@ -0,0 +1,103 @@
+use super::card::CardId;
+use super::constant::NUM_PLAYERS;
+use super::location::Location;
+use super::Game;
+use wasm_bindgen::prelude::*;
+
+/// Where the points of one player's score for a hand come from, like the
+/// columns of a scoresheet
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ScoreBreakdown {
+    /// Point cards captured from the table
+    pub captured: usize,
+    /// 5 points for each card left in the other hands, for going out first
+    pub cards_left: usize,
+    /// Point cards left in the other hands, for going out first
+    pub points_left: usize,
+    /// Point cards in the Haggis, for going out first in a two-player game
+    pub haggis: usize,
+    /// Points from bets, whether won by the player or lost by someone else
+    pub bets: usize,
+}
+
+#[wasm_bindgen]
+impl ScoreBreakdown {
+    pub fn total(&self) -> usize {
+        self.captured + self.cards_left + self.points_left + self.haggis + self.bets
+    }
+}
+
+impl Game {
+    /// The score breakdown of each seat, indexed by Player::index
+    pub fn score_breakdowns_by_seat(&self) -> Vec<ScoreBreakdown> {
+        let is_game_over = self.is_game_over();
+        // The last combination group of a hand that is over goes to the
+        // player who captures it when the hand is finished
+        let player_who_captures = self.player_who_captures();
+
+        let mut breakdowns = vec![ScoreBreakdown::default(); self.num_players];
+
+        // What the winner of the hand scores
+        let mut bonus = ScoreBreakdown::default();
+
+        for (i, location) in self.locations.iter().enumerate() {
+            let point_value = CardId(i).to_value().point_value();
+            match location {
+                // All point  cards (i.e., any 3, 5, 7, 9, J, Q, or K) captured
+                // are  scored by the capturing player.
+                Location::Table {
+                    captured_by: Some(player),
+                    ..
+                } => {
+                    breakdowns[player.index()].captured += point_value;
+                }
+                // The winner of the hand scores 5 points for each card in the other hands.
+                // Remember, the wild cards count as part of the hand.
+                // Point cards left in the other hands are also scored by the winner.
+                Location::Hand(..) => {
+                    bonus.cards_left += 5;
+                    bonus.points_left += point_value;
+                }
+                // Any point cards found in the Haggis are scored by the player
+                // who won the hand. Cards set aside with three players are not scored.
+                Location::Haggis => {
+                    if self.num_players == NUM_PLAYERS {
+                        bonus.haggis += point_value;
+                    }
+                }
+                Location::Table {
+                    captured_by: None, ..
+                } => {
+                    if is_game_over {
+                        breakdowns[player_who_captures.index()].captured += point_value;
+                    }
+                }
+            }
+        }
+
+        if let (true, Some(winner)) = (is_game_over, self.first_out) {
+            let winner_breakdown = &mut breakdowns[winner.index()];
+            winner_breakdown.cards_left = bonus.cards_left;
+            winner_breakdown.points_left = bonus.points_left;
+            winner_breakdown.haggis = bonus.haggis;
+
+            // A bet is won from each opponent if the bettor went out first.
+            // Otherwise, each opponent scores the bet.
+            for bettor_index in 0..self.num_players {
+                let bet_points = self.bets[bettor_index].points();
+                if bettor_index == winner.index() {
+                    breakdowns[bettor_index].bets += bet_points * (self.num_players - 1);
+                } else {
+                    for (player_index, breakdown) in breakdowns.iter_mut().enumerate() {
+                        if player_index != bettor_index {
+                            breakdown.bets += bet_points;
+                        }
+                    }
+                }
+            }
+        }
+
+        breakdowns
+    }
+}
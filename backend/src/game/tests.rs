@@ -1,11 +1,160 @@
+use super::bet::Bet;
+use super::card::CardId;
+use super::constant::MAX_NUM_PLAYERS;
+use super::history::{History, Move, UndoState};
 use super::location::Location;
 use super::player::Player;
 use super::Game;
 
+/// A hand-dealt two-player game, in which the first seat holds the second
+/// seat's wildcards and the other way around
+fn new_test_game() -> Game {
+    use Location::*;
+    use Player::*;
+    Game {
+        locations: vec![
+            Hand(Second),
+            Haggis,
+            Hand(Second),
+            Hand(Second),
+            Haggis,
+            Haggis,
+            Hand(Second),
+            Hand(First),
+            Hand(First),
+            Hand(Second),
+            Hand(First),
+            Hand(First),
+            Hand(First),
+            Hand(First),
+            Hand(Second),
+            Hand(First),
+            Haggis,
+            Hand(Second),
+            Hand(First),
+            Hand(Second),
+            Haggis,
+            Hand(Second),
+            Hand(First),
+            Hand(First),
+            Hand(Second),
+            Haggis,
+            Hand(First),
+            Hand(First),
+            Hand(Second),
+            Haggis,
+            Hand(First),
+            Hand(Second),
+            Hand(Second),
+            Haggis,
+            Hand(Second),
+            Hand(First),
+            Hand(Second),
+            Hand(Second),
+            Hand(Second),
+            Hand(First),
+            Hand(First),
+            Hand(First),
+        ]
+        .into(),
+        current_player: First,
+        num_players: 2,
+        viewer: First,
+        last_combination_type: None,
+        last_player: None,
+        passes: 0,
+        first_out: None,
+        next_order: 0,
+        bets: [Bet::None; MAX_NUM_PLAYERS],
+        match_scores: [0; MAX_NUM_PLAYERS],
+        undo: UndoState::default(),
+        match_key: None,
+    }
+}
+
+/// A random two-player deal in which the first seat also holds first and the
+/// second seat also holds second
+pub(super) fn game_with_hands(first: &[usize], second: &[usize]) -> Game {
+    let mut game = Game::new();
+    for &card_id in first {
+        game.locations.set(card_id, Location::Hand(Player::First));
+    }
+    for &card_id in second {
+        game.locations.set(card_id, Location::Hand(Player::Second));
+    }
+    game
+}
+
+/// Which legal move a seeded hand plays every turn
+#[derive(Copy, Clone, Debug)]
+enum Pick {
+    FirstLegalMove,
+    LastLegalMove,
+}
+
+impl Pick {
+    fn card_ids(self, game: &Game) -> Vec<usize> {
+        let legal_moves = game.legal_moves();
+        match self {
+            Pick::FirstLegalMove => legal_moves.first(),
+            Pick::LastLegalMove => legal_moves.last(),
+        }
+        .unwrap()
+        .clone()
+    }
+}
+
+/// A seeded deal, played to the end by always picking the same legal move
+struct SeededHand {
+    deal: Game,
+    pick: Pick,
+    /// The cards of every move, which are none for a pass
+    moves: Vec<Vec<usize>>,
+}
+
+impl SeededHand {
+    /// The history of the first num_moves moves
+    fn history(&self, num_moves: usize) -> History {
+        let mut history = History::new(self.deal.clone());
+        for card_ids in &self.moves[..num_moves] {
+            let played_move = if card_ids.is_empty() {
+                Move::Pass
+            } else {
+                Move::Play(card_ids.iter().map(|&card_id| CardId(card_id)).collect())
+            };
+            history.push(played_move).unwrap();
+        }
+        history
+    }
+}
+
+/// The hands that tests play to the end: seeds 0 to 2, with two and three
+/// players, picking either the first or the last legal move every turn.
+fn seeded_hands() -> Vec<SeededHand> {
+    let mut hands = Vec::new();
+    for seed in 0..3 {
+        for &num_players in &[2, 3] {
+            for &pick in &[Pick::FirstLegalMove, Pick::LastLegalMove] {
+                let deal = Game::new_for_players_with_seed(num_players, seed);
+                let mut game = deal.clone();
+                let mut moves = Vec::new();
+                while !game.is_game_over() {
+                    let card_ids = pick.card_ids(&game);
+                    game.play_cards(&card_ids);
+                    moves.push(card_ids);
+                }
+                hands.push(SeededHand { deal, pick, moves });
+            }
+        }
+    }
+    hands
+}
+
 mod tests_for_qr_code {
     use image::DynamicImage;
 
     use super::*;
+    use crate::game::{CardFrontendState, GameStage};
 
     #[test]
     fn test_write_and_read_qr_code() {
@@ -13,135 +162,1885 @@ mod tests_for_qr_code {
         use Player::*;
         let mut game = Game {
             locations: vec![
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Opponent),
-                Haggis,
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-            ],
-            current_player: Me,
-            me_went_first: true,
+                Hand(Second),
+                Haggis,
+                Hand(Second),
+                Hand(Second),
+                Haggis,
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(First),
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(Second),
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Haggis,
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Haggis,
+                Hand(First),
+                Hand(Second),
+                Hand(Second),
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(Second),
+                Hand(Second),
+            ]
+            .into(),
+            current_player: First,
+            num_players: 2,
+            viewer: First,
             last_combination_type: None,
+            last_player: None,
+            passes: 0,
+            first_out: None,
             next_order: 0,
+            bets: [Bet::None; MAX_NUM_PLAYERS],
+            match_scores: [0; MAX_NUM_PLAYERS],
+            undo: UndoState::default(),
+            match_key: None,
         };
 
         game.play_cards(&vec![11, 12, 13]);
 
-        let mut game_from_qr_code = Game::new();
+        let mut game_from_qr_code = Game::undealt();
 
         let qr_code = game.write_qr_code(200, 200);
         let dynamic_qr_code = DynamicImage::ImageRgba8(qr_code);
         game_from_qr_code.read_qr_code(dynamic_qr_code).unwrap();
-        game_from_qr_code.switch_perspective();
 
+        // A game without a deal joins the seat whose turn it is
+        assert_eq!(game_from_qr_code.viewer, Second);
+        game.viewer = Second;
+        // The sender has seen every move of a game read from a qr code
+        game.undo.num_shared_moves = 1;
         assert_eq!(game, game_from_qr_code);
     }
 
     #[test]
-    fn test_switch_perspective() {
+    fn test_viewer_only_changes_queries() {
         use Location::*;
         use Player::*;
         let mut game = Game {
             locations: vec![
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Opponent),
-                Haggis,
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Me),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Me),
-                Hand(Me),
-                Hand(Opponent),
-                Haggis,
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Haggis,
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Opponent),
-                Hand(Me),
-                Hand(Me),
-                Hand(Me),
-            ],
-            current_player: Me,
-            me_went_first: true,
+                Hand(Second),
+                Haggis,
+                Hand(Second),
+                Hand(Second),
+                Haggis,
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Hand(First),
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(Second),
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Haggis,
+                Hand(First),
+                Hand(First),
+                Hand(Second),
+                Haggis,
+                Hand(First),
+                Hand(Second),
+                Hand(Second),
+                Haggis,
+                Hand(Second),
+                Hand(First),
+                Hand(Second),
+                Hand(Second),
+                Hand(Second),
+                Hand(First),
+                Hand(First),
+                Hand(First),
+            ]
+            .into(),
+            current_player: First,
+            num_players: 2,
+            viewer: First,
             last_combination_type: None,
+            last_player: None,
+            passes: 0,
+            first_out: None,
             next_order: 0,
+            bets: [Bet::None; MAX_NUM_PLAYERS],
+            match_scores: [0; MAX_NUM_PLAYERS],
+            undo: UndoState::default(),
+            match_key: None,
         };
 
         game.play_cards(&vec![11, 12, 13]);
-        let game_copy = game.clone();
+        let mut second_game = game.clone();
+        second_game.viewer = Second;
+
+        assert_eq!(game.current_player, Second);
+        assert_eq!(second_game.current_player, Second);
+        assert_eq!(game.locations, second_game.locations);
+        assert_eq!(game.hand_sizes(), vec![14, 17].into_boxed_slice());
+        assert_eq!(second_game.hand_sizes(), vec![17, 14].into_boxed_slice());
+        assert!(matches!(
+            game.card_frontend_state(0),
+            CardFrontendState::InOpponentHand
+        ));
+        assert!(matches!(
+            second_game.card_frontend_state(0),
+            CardFrontendState::InMyHand
+        ));
+        assert!(matches!(game.game_stage(), GameStage::Wait));
+        assert!(matches!(second_game.game_stage(), GameStage::Bet));
+        assert!(game.me_went_first());
+        assert!(!second_game.me_went_first());
+    }
+}
+
+mod tests_for_play_cards {
+    use super::*;
+    use crate::game::error::GameError;
+
+    #[test]
+    fn test_pass_on_empty_table() {
+        let mut game = new_test_game();
+        assert_eq!(
+            game.try_play_cards(Player::First, &[]),
+            Err(GameError::PassOnEmptyTable)
+        );
+    }
+
+    #[test]
+    fn test_wrong_turn() {
+        let mut game = new_test_game();
+        assert_eq!(
+            game.try_play_cards(Player::Second, &[0]),
+            Err(GameError::WrongTurn)
+        );
+    }
+
+    #[test]
+    fn test_invalid_combination() {
+        let mut game = new_test_game();
+        assert_eq!(
+            game.try_play_cards(Player::First, &[10, 12]),
+            Err(GameError::InvalidCombination)
+        );
+    }
+
+    #[test]
+    fn test_mismatched_combination() {
+        let mut game = new_test_game();
+        game.try_play_cards(Player::First, &[11, 12, 13]).unwrap();
+        assert_eq!(
+            game.try_play_cards(Player::Second, &[0]),
+            Err(GameError::MismatchedCombination)
+        );
+    }
+
+    #[test]
+    fn test_bomb_too_low() {
+        let mut game = new_test_game();
+        game.try_play_cards(Player::First, &[40, 41]).unwrap();
+        assert_eq!(
+            game.try_play_cards(Player::Second, &[36, 37]),
+            Err(GameError::BombTooLow)
+        );
+    }
 
-        println!(
-            "game size: {}, location size: {}",
-            std::mem::size_of::<Game>(),
-            std::mem::size_of::<Location>()
+    #[test]
+    fn test_normal_after_bomb() {
+        let mut game = new_test_game();
+        game.try_play_cards(Player::First, &[39, 40]).unwrap();
+        assert_eq!(
+            game.try_play_cards(Player::Second, &[0]),
+            Err(GameError::NormalAfterBomb)
         );
+        assert_eq!(game.try_play_cards(Player::Second, &[36, 38]), Ok(()));
+    }
 
-        assert_eq!(game.current_player, Player::Opponent);
-        game.switch_perspective();
-        assert_eq!(game.current_player, Player::Me);
-        game.switch_perspective();
+    #[test]
+    fn test_rejected_move_leaves_game_unchanged() {
+        let mut game = new_test_game();
+        game.try_play_cards(Player::First, &[11, 12, 13]).unwrap();
+        let game_copy = game.clone();
+        assert!(game.try_play_cards(Player::Second, &[0]).is_err());
         assert_eq!(game, game_copy);
     }
 }
+
+mod tests_for_card_ownership {
+    use super::*;
+    use crate::game::error::GameError;
+
+    #[test]
+    fn test_card_in_opponent_hand() {
+        let mut game = Game::new();
+        let opponent_card = game.get_hand(Player::Second)[0].0;
+        assert_eq!(
+            game.try_play_cards(Player::First, &[opponent_card]),
+            Err(GameError::CardNotInHand)
+        );
+    }
+
+    #[test]
+    fn test_opponent_wildcard() {
+        let mut game = Game::new();
+        assert_eq!(
+            game.try_play_cards(Player::First, &[39]),
+            Err(GameError::CardNotInHand)
+        );
+    }
+
+    #[test]
+    fn test_card_in_haggis() {
+        let mut game = Game::new();
+        let haggis_card = game
+            .locations
+            .iter()
+            .position(|location| *location == Location::Haggis)
+            .unwrap();
+        assert_eq!(
+            game.try_play_cards(Player::First, &[haggis_card]),
+            Err(GameError::CardNotInHand)
+        );
+    }
+
+    #[test]
+    fn test_card_on_table() {
+        let mut game = Game::new();
+        game.try_play_cards(Player::First, &[36]).unwrap();
+        game.try_play_cards(Player::Second, &[]).unwrap();
+        assert_eq!(
+            game.try_play_cards(Player::First, &[36]),
+            Err(GameError::CardNotInHand)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_card() {
+        let mut game = Game::new();
+        let my_card = game.get_hand(Player::First)[0].0;
+        assert_eq!(
+            game.try_play_cards(Player::First, &[my_card, my_card]),
+            Err(GameError::DuplicateCard)
+        );
+        assert_eq!(
+            game.try_play_cards(Player::First, &[36, 36]),
+            Err(GameError::DuplicateCard)
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_card() {
+        let mut game = Game::new();
+        assert_eq!(
+            game.try_play_cards(Player::First, &[42]),
+            Err(GameError::InvalidCardId)
+        );
+        assert_eq!(
+            game.try_play_cards(Player::First, &[36, usize::MAX]),
+            Err(GameError::InvalidCardId)
+        );
+    }
+
+    #[test]
+    fn test_can_play_cards_rejects_foreign_cards() {
+        let game = Game::new();
+        let opponent_card = game.get_hand(Player::Second)[0].0;
+        assert!(game.can_play_cards(&[36]));
+        assert!(!game.can_play_cards(&[opponent_card]));
+        assert!(!game.can_play_cards(&[36, 36]));
+        assert!(!game.can_play_cards(&[42]));
+    }
+
+    #[test]
+    fn test_can_play_cards_agrees_with_try_play_cards() {
+        // The viewer can't play when it is the other player's turn
+        let mut game = Game::new_with_seed(7);
+        game.viewer = Player::Second;
+        let card_ids = game.legal_moves().pop().unwrap();
+        assert!(!game.can_play_cards(&card_ids));
+        assert_eq!(
+            game.try_play_cards_as_me(&card_ids),
+            Some(GameError::WrongTurn)
+        );
+
+        game.viewer = Player::First;
+        assert!(game.can_play_cards(&card_ids));
+        assert_eq!(game.try_play_cards_as_me(&card_ids), None);
+    }
+}
+
+mod tests_for_match {
+    use super::*;
+    use crate::game::game_match::{Match, MatchStage};
+
+    /// A hand seen by viewer where the winner captured every card from their
+    /// own hand except one wildcard, then went out by playing the wildcard
+    pub(super) fn finished_hand(winner: Player, viewer: Player) -> Game {
+        let mut game = Game::new();
+        let wildcard = game.get_hand(winner).last().unwrap().0;
+        for card_id in game.get_hand(winner) {
+            game.locations.set(
+                card_id.0,
+                Location::Table {
+                    captured_by: Some(winner),
+                    order: 0,
+                    in_last_combination_before_pass: true,
+                    after_pass: false,
+                    wildcard_shape: None,
+                },
+            );
+        }
+        game.locations.set(wildcard, Location::Hand(winner));
+        game.next_order = 1;
+        game.current_player = winner;
+        game.viewer = viewer;
+        game.play_cards(&[wildcard]);
+        assert!(game.is_game_over());
+        game
+    }
+
+    #[test]
+    fn test_record_unfinished_hand() {
+        let mut haggis_match = Match::new();
+        assert!(!haggis_match.record_hand(&Game::new()));
+        assert_eq!(haggis_match.hands_played(), 0);
+    }
+
+    #[test]
+    fn test_scores_accumulate() {
+        let mut haggis_match = Match::new();
+
+        let first_hand = finished_hand(Player::First, Player::First);
+        assert!(haggis_match.record_hand(&first_hand));
+        let first_score = first_hand.calculate_score();
+        assert_eq!(&*haggis_match.scores(), &*first_score);
+
+        // Recording the same hand again does nothing
+        assert!(haggis_match.record_hand(&first_hand));
+        assert_eq!(&*haggis_match.scores(), &*first_score);
+
+        // The opponent led and won the second hand
+        let second_hand = finished_hand(Player::First, Player::Second);
+        haggis_match.record_hand(&second_hand);
+        let second_score = second_hand.calculate_score();
+        assert_eq!(haggis_match.hands_played(), 2);
+        assert_eq!(
+            &*haggis_match.scores(),
+            &[
+                first_score[0] + second_score[0],
+                first_score[1] + second_score[1]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_score_leads() {
+        let mut haggis_match = Match::new();
+        assert_eq!(haggis_match.next_leader(), 0);
+        assert!(haggis_match.next_hand().is_some());
+
+        haggis_match.record_hand(&finished_hand(Player::First, Player::First));
+        assert_eq!(haggis_match.next_leader(), 1);
+        assert!(haggis_match.next_hand().is_none());
+
+        haggis_match.scores = vec![100, 100];
+        assert_eq!(haggis_match.next_leader(), 1);
+        // If the opponent led the last hand, I sat in the second seat
+        haggis_match.hands.last_mut().unwrap().viewer = Player::Second;
+        assert_eq!(haggis_match.next_leader(), 0);
+    }
+
+    #[test]
+    fn test_match_over() {
+        let mut haggis_match = Match::new();
+        haggis_match.scores = vec![240, 200];
+        haggis_match.record_hand(&finished_hand(Player::First, Player::First));
+        assert_eq!(haggis_match.winner(), Some(0));
+        assert!(matches!(haggis_match.stage(), MatchStage::MatchOver));
+        assert!(haggis_match.next_hand().is_none());
+    }
+
+    #[test]
+    fn test_tied_match_continues() {
+        let mut haggis_match = Match::new();
+        haggis_match.scores = vec![260, 260];
+        assert_eq!(haggis_match.winner(), None);
+        assert!(!matches!(haggis_match.stage(), MatchStage::MatchOver));
+    }
+}
+
+mod tests_for_bets {
+    use super::*;
+    use crate::game::error::GameError;
+    use crate::game::GameStage;
+
+    #[test]
+    fn test_bet_before_first_play() {
+        let mut game = Game::new();
+        assert!(matches!(game.game_stage(), GameStage::Bet));
+        assert_eq!(
+            game.declare_bet(Player::Second, Bet::Big),
+            Err(GameError::WrongTurn)
+        );
+        assert_eq!(game.declare_bet(Player::First, Bet::Little), Ok(()));
+        assert!(matches!(game.game_stage(), GameStage::Play));
+        assert_eq!(
+            game.declare_bet(Player::First, Bet::Big),
+            Err(GameError::AlreadyBet)
+        );
+        assert_eq!(game.my_bet(), Bet::Little);
+
+        game.play_cards(&[36]);
+        assert!(matches!(game.game_stage(), GameStage::Wait));
+        assert_eq!(game.declare_bet(Player::Second, Bet::Big), Ok(()));
+        assert_eq!(game.opponent_bet(), Bet::Big);
+    }
+
+    #[test]
+    fn test_bet_after_playing() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        assert!(!game.can_bet(Player::First));
+        assert_eq!(
+            game.declare_bet(Player::First, Bet::Little),
+            Err(GameError::BetAfterPlaying)
+        );
+    }
+
+    #[test]
+    fn test_passing_keeps_bet_open() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        game.play_cards(&[37]);
+        assert!(game.can_bet(Player::Second));
+    }
+
+    #[test]
+    fn test_bets_scored_by_player_who_went_out() {
+        let mut game = Game::new();
+        for card_id in game.get_hand(Player::First) {
+            game.locations.set(card_id.0, Location::Haggis);
+        }
+        game.locations.set(36, Location::Hand(Player::First));
+        game.play_cards(&[36]);
+        assert!(game.is_game_over());
+
+        let scores_without_bets = game.clone().calculate_score();
+
+        let mut won_bet = game.clone();
+        won_bet.bets = [Bet::Big, Bet::None, Bet::None];
+        let scores = won_bet.calculate_score();
+        assert_eq!(scores[0], scores_without_bets[0] + 30);
+        assert_eq!(scores[1], scores_without_bets[1]);
+
+        let mut lost_bet = game.clone();
+        lost_bet.bets = [Bet::None, Bet::Little, Bet::None];
+        let scores = lost_bet.calculate_score();
+        assert_eq!(scores[0], scores_without_bets[0] + 15);
+        assert_eq!(scores[1], scores_without_bets[1]);
+    }
+
+    #[test]
+    fn test_bets_follow_viewer() {
+        let mut game = Game::new();
+        game.declare_bet(Player::First, Bet::Big).unwrap();
+        game.viewer = Player::Second;
+        assert_eq!(game.opponent_bet(), Bet::Big);
+        assert_eq!(game.my_bet(), Bet::None);
+    }
+}
+
+mod tests_for_three_players {
+    use super::*;
+    use crate::game::card::{wildcard_ids, CardId};
+    use crate::game::constant::*;
+    use crate::game::game_match::Match;
+    use crate::game::{CardFrontendState, GameStage};
+
+    /// Remove every card from player's hand except keep
+    fn leave_in_hand(game: &mut Game, player: Player, keep: usize) {
+        for card_id in game.get_hand(player) {
+            if card_id.0 != keep {
+                game.locations.set(card_id.0, Location::Haggis);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deal() {
+        let game = Game::new_for_players(3);
+        assert_eq!(game.locations.len(), DECK_SIZE_THREE_PLAYERS);
+        assert_eq!(&*game.hand_sizes(), &[INIT_HAND_SIZE; 3]);
+        let num_set_aside = game
+            .locations
+            .iter()
+            .filter(|&location| *location == Location::Haggis)
+            .count();
+        assert_eq!(num_set_aside, SET_ASIDE_SIZE_THREE_PLAYERS);
+        for player_index in 0..3 {
+            for card_id in wildcard_ids(player_index) {
+                assert_eq!(
+                    game.locations[card_id],
+                    Location::Hand(Player::from_index(player_index))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_group_ends_after_everyone_else_passes() {
+        let mut game = Game::new_for_players(3);
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        assert_eq!(game.current_player, Player::Third);
+        assert!(game.last_combination_type.is_some());
+
+        game.play_cards(&[]);
+        assert_eq!(game.current_player, Player::First);
+        assert!(game.last_combination_type.is_none());
+        assert_eq!(game.locations[36].captured_by(), Some(Player::First));
+    }
+
+    #[test]
+    fn test_play_after_pass() {
+        let mut game = Game::new_for_players(3);
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        game.play_cards(&[53]);
+        assert!(matches!(
+            game.locations[53],
+            Location::Table {
+                after_pass: true,
+                ..
+            }
+        ));
+
+        game.play_cards(&[]);
+        game.play_cards(&[]);
+        assert_eq!(game.current_player, Player::Third);
+        assert_eq!(game.locations[36].captured_by(), Some(Player::Third));
+    }
+
+    #[test]
+    fn test_bomb_group_goes_to_last_opponent_to_pass() {
+        let mut game = Game::new_for_players(3);
+        // My jack and queen make a bomb
+        game.play_cards(&[36, 37]);
+        game.play_cards(&[]);
+        game.play_cards(&[]);
+        assert_eq!(game.locations[36].captured_by(), Some(Player::Third));
+        assert_eq!(game.locations[37].captured_by(), Some(Player::Third));
+        // The bomber still leads the next combination group
+        assert_eq!(game.current_player, Player::First);
+    }
+
+    #[test]
+    fn test_hand_continues_after_first_player_goes_out() {
+        let mut game = Game::new_for_players(3);
+        leave_in_hand(&mut game, Player::First, 36);
+        leave_in_hand(&mut game, Player::Second, 39);
+
+        game.play_cards(&[36]);
+        assert_eq!(game.first_out, Some(Player::First));
+        assert!(!game.is_game_over());
+        game.play_cards(&[]);
+        game.play_cards(&[]);
+        // I went out, so the next player leads
+        assert_eq!(game.current_player, Player::Second);
+
+        game.play_cards(&[39]);
+        assert!(game.is_game_over());
+
+        let points_in_hand: usize = game
+            .get_hand(Player::Third)
+            .iter()
+            .map(|card_id| card_id.to_value().point_value())
+            .sum();
+        let scores = game.calculate_score();
+        assert_eq!(
+            scores[0],
+            CardId(36).to_value().point_value() + 5 * INIT_HAND_SIZE + points_in_hand
+        );
+        assert_eq!(scores[1], CardId(39).to_value().point_value());
+        assert_eq!(scores[2], 0);
+    }
+
+    #[test]
+    fn test_queries_start_from_viewer() {
+        let mut game = Game::new_for_players(3);
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        game.viewer = Player::Third;
+
+        assert_eq!(
+            &*game.hand_sizes(),
+            &[INIT_HAND_SIZE, INIT_HAND_SIZE - 1, INIT_HAND_SIZE]
+        );
+        assert!(!game.me_went_first());
+        assert!(matches!(game.game_stage(), GameStage::Bet));
+        assert!(matches!(
+            game.card_frontend_state(36),
+            CardFrontendState::JustPlayed
+        ));
+        assert!(matches!(
+            game.card_frontend_state(53),
+            CardFrontendState::InMyHand
+        ));
+    }
+
+    #[test]
+    fn test_three_player_match() {
+        let mut haggis_match = Match::new_for_players(3);
+        assert_eq!(haggis_match.target_score, TARGET_SCORE_THREE_PLAYERS);
+        haggis_match.scores = vec![300, 360, 360];
+        assert_eq!(haggis_match.winner(), None);
+        assert_eq!(haggis_match.next_leader(), 0);
+        haggis_match.scores = vec![300, 370, 360];
+        assert_eq!(haggis_match.winner(), Some(1));
+    }
+}
+
+mod tests_for_legal_moves {
+    use super::*;
+    use crate::game::card::CardValue;
+    use rand::seq::SliceRandom;
+
+    /// Keep num_normal random normal cards and the wildcards in player's hand,
+    /// so that every subset of the hand can be checked by brute force
+    fn shrink_hand(game: &mut Game, player: Player, num_normal: usize) {
+        let mut normal_cards: Vec<usize> = game
+            .get_hand(player)
+            .iter()
+            .filter(|card_id| matches!(card_id.to_value(), CardValue::Normal { .. }))
+            .map(|card_id| card_id.0)
+            .collect();
+        normal_cards.shuffle(&mut rand::thread_rng());
+        for &card_id in &normal_cards[num_normal..] {
+            game.locations.set(card_id, Location::Haggis);
+        }
+    }
+
+    fn brute_force_legal_moves(game: &Game) -> Vec<Vec<usize>> {
+        let hand: Vec<usize> = game
+            .get_hand(game.current_player)
+            .iter()
+            .map(|card_id| card_id.0)
+            .collect();
+        let mut moves = Vec::new();
+        for mask in 0..(1 << hand.len()) {
+            let card_ids: Vec<usize> = (0..hand.len())
+                .filter(|i| mask & 1 << i != 0)
+                .map(|i| hand[i])
+                .collect();
+            if game
+                .clone()
+                .try_play_cards(game.current_player, &card_ids)
+                .is_ok()
+            {
+                moves.push(card_ids);
+            }
+        }
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn test_pass_only_after_a_combination() {
+        let mut game = Game::new();
+        assert!(!game.legal_moves().contains(&vec![]));
+        game.play_cards(&[36]);
+        assert!(game.legal_moves().contains(&vec![]));
+    }
+
+    #[test]
+    fn test_no_moves_after_game_over() {
+        let mut game = Game::new();
+        for card_id in game.get_hand(Player::First) {
+            game.locations.set(card_id.0, Location::Haggis);
+        }
+        assert!(game.legal_moves().is_empty());
+    }
+
+    #[test]
+    fn test_includes_bombs_and_sequences() {
+        let mut game = Game::new();
+        shrink_hand(&mut game, Player::First, 0);
+        // 3-5-7-9 in one suit, and 7-8-9 in that suit
+        for &card_id in &[1, 3, 5, 6, 7] {
+            game.locations.set(card_id, Location::Hand(Player::First));
+        }
+        let legal_moves = game.legal_moves();
+        assert!(legal_moves.contains(&vec![1, 3, 5, 7]));
+        assert!(legal_moves.contains(&vec![5, 6, 7]));
+        assert!(legal_moves.contains(&vec![5, 6, 7, 36]));
+        assert!(legal_moves.contains(&vec![36, 37]));
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        for _ in 0..20 {
+            let mut game = Game::new();
+            shrink_hand(&mut game, Player::First, 9);
+            shrink_hand(&mut game, Player::Second, 9);
+            assert_eq!(game.legal_moves(), brute_force_legal_moves(&game));
+
+            // Respond to a random combination
+            let legal_moves = game.legal_moves();
+            let combination = legal_moves.choose(&mut rand::thread_rng()).unwrap();
+            game.play_cards(combination);
+            assert_eq!(game.legal_moves(), brute_force_legal_moves(&game));
+        }
+    }
+
+    #[test]
+    fn test_three_player_matches_brute_force() {
+        for _ in 0..5 {
+            let mut game = Game::new_for_players(3);
+            shrink_hand(&mut game, Player::First, 9);
+            assert_eq!(game.legal_moves(), brute_force_legal_moves(&game));
+        }
+    }
+}
+
+mod tests_for_wildcard_shape {
+    use super::*;
+    use crate::game::error::GameError;
+    use crate::game::wildcard_shape::WildcardShape;
+
+    // 7♣ J Q, which can be 7♣ 8♣ 9♣ or three 7s
+    const AMBIGUOUS: [usize; 3] = [32, 36, 37];
+    // 8♠ 9♠ 10♠
+    const SEQUENCE: [usize; 3] = [6, 7, 8];
+    // 8♠ 8♥ 8♦
+    const SET: [usize; 3] = [6, 15, 24];
+
+    fn new_shape_game() -> Game {
+        game_with_hands(&[32], &[6, 7, 8, 15, 24])
+    }
+
+    #[test]
+    fn test_declared_sequence() {
+        let mut game = new_shape_game();
+        assert!(game.needs_wildcard_shape(&AMBIGUOUS));
+        game.try_play_cards_with_shape(Player::First, &AMBIGUOUS, Some(WildcardShape::MoreRanks))
+            .unwrap();
+        assert_eq!(game.last_wildcard_shape(), Some(WildcardShape::MoreRanks));
+
+        assert_eq!(
+            game.clone().try_play_cards(Player::Second, &SET),
+            Err(GameError::MismatchedCombination)
+        );
+        assert_eq!(game.try_play_cards(Player::Second, &SEQUENCE), Ok(()));
+    }
+
+    #[test]
+    fn test_declared_set() {
+        let mut game = new_shape_game();
+        game.try_play_cards_with_shape(Player::First, &AMBIGUOUS, Some(WildcardShape::MoreSuits))
+            .unwrap();
+        assert_eq!(game.last_wildcard_shape(), Some(WildcardShape::MoreSuits));
+
+        assert_eq!(
+            game.clone().try_play_cards(Player::Second, &SEQUENCE),
+            Err(GameError::MismatchedCombination)
+        );
+        assert_eq!(game.try_play_cards(Player::Second, &SET), Ok(()));
+    }
+
+    #[test]
+    fn test_undeclared_shape_stays_ambiguous() {
+        let mut game = new_shape_game();
+        game.play_cards(&AMBIGUOUS);
+        assert_eq!(game.last_wildcard_shape(), None);
+        assert!(game.clone().try_play_cards(Player::Second, &SET).is_ok());
+        assert!(game.try_play_cards(Player::Second, &SEQUENCE).is_ok());
+    }
+
+    #[test]
+    fn test_shape_of_unambiguous_combination() {
+        let mut game = new_shape_game();
+        assert!(!game.needs_wildcard_shape(&[32]));
+        assert_eq!(
+            game.try_play_cards_with_shape(Player::First, &[32], Some(WildcardShape::MoreRanks)),
+            Err(GameError::UnambiguousCombination)
+        );
+    }
+}
+
+mod tests_for_seeded_deal {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_same_seed_same_deal() {
+        assert_eq!(Game::new_with_seed(42), Game::new_with_seed(42));
+        assert_eq!(
+            Game::new_for_players_with_seed(3, 42),
+            Game::new_for_players_with_seed(3, 42)
+        );
+    }
+
+    #[test]
+    fn test_different_seed_different_deal() {
+        assert_ne!(Game::new_with_seed(1), Game::new_with_seed(2));
+    }
+
+    #[test]
+    fn test_any_rng() {
+        let game = Game::new_with_rng(2, &mut StdRng::seed_from_u64(7));
+        assert_eq!(game, Game::new_with_rng(2, &mut StdRng::seed_from_u64(7)));
+        assert_eq!(game.get_hand(Player::First).len(), 17);
+    }
+
+    #[test]
+    fn test_seeded_game_replays() {
+        let mut game = Game::new_with_seed(3);
+        let mut replayed_game = Game::new_with_seed(3);
+        while !game.is_game_over() {
+            let legal_moves = game.legal_moves();
+            // Always play the largest legal move so that the game ends
+            let card_ids = legal_moves
+                .iter()
+                .max_by_key(|card_ids| card_ids.len())
+                .unwrap();
+            game.play_cards(card_ids);
+            replayed_game.play_cards(card_ids);
+        }
+        assert_eq!(game, replayed_game);
+    }
+}
+
+mod tests_for_history {
+    use super::*;
+    use crate::compression::{decode_game, encode_game};
+    use crate::game::wildcard_shape::WildcardShape;
+
+    #[test]
+    fn test_history_from_game() {
+        for hand in &seeded_hands() {
+            for num_moves in 0..hand.moves.len().min(20) {
+                let history = hand.history(num_moves);
+                assert_eq!(History::from_game(&history.game()), Some(history));
+            }
+        }
+    }
+
+    #[test]
+    fn test_history_of_finished_hand() {
+        for hand in &seeded_hands() {
+            let history = hand.history(hand.moves.len());
+            let mut game = history.game();
+            assert!(game.is_game_over());
+            assert_eq!(History::from_game(&game).as_ref(), Some(&history));
+
+            // Finishing the hand captures the last combination group without a move
+            game.finish_hand();
+            let scored_history = History::from_game(&game).unwrap();
+            assert_eq!(scored_history, history);
+        }
+    }
+
+    #[test]
+    fn test_history_from_qr_state() {
+        for hand in &seeded_hands() {
+            let history = hand.history(hand.moves.len().min(15));
+            let decoded_game = decode_game(&encode_game(&history.game())).unwrap();
+            assert_eq!(
+                History::from_game(&decoded_game).unwrap().moves,
+                history.moves
+            );
+        }
+    }
+
+    #[test]
+    fn test_step_through_history() {
+        let mut history = seeded_hands()[0].history(6);
+        let games: Vec<Game> = (0..=6).map(|position| history.game_at(position)).collect();
+        assert_eq!(history.position(), 6);
+        assert!(!history.step_forward());
+
+        for position in (0..6).rev() {
+            assert!(history.step_back());
+            assert_eq!(history.game(), games[position]);
+        }
+        assert!(!history.step_back());
+        assert_eq!(history.game(), history.deal);
+
+        assert!(history.go_to(4));
+        assert!(history.step_forward());
+        assert_eq!(history.game(), games[5]);
+        assert!(!history.go_to(7));
+    }
+
+    #[test]
+    fn test_push_replaces_later_moves() {
+        let mut history = seeded_hands()[0].history(6);
+        history.go_to(1);
+        // A move that is not legal at the current position is rejected
+        assert!(history
+            .push(Move::Play(vec![CardId(36), CardId(39)]))
+            .is_err());
+        assert_eq!(history.num_moves(), 6);
+
+        history.push(Move::Pass).unwrap();
+        assert_eq!(history.num_moves(), 2);
+        assert_eq!(history.moves[1], Move::Pass);
+    }
+
+    #[test]
+    fn test_history_keeps_wildcard_shape() {
+        let mut game = Game::new();
+        // Give me 7♣
+        let my_card = game.get_hand(Player::First)[0].0;
+        game.locations.swap(32, my_card);
+        game.try_play_cards_with_shape(
+            Player::First,
+            &[32, 36, 37],
+            Some(WildcardShape::MoreSuits),
+        )
+        .unwrap();
+
+        let history = History::from_game(&game).unwrap();
+        assert_eq!(
+            history.moves,
+            vec![Move::PlayWithShape(
+                vec![CardId(32), CardId(36), CardId(37)],
+                WildcardShape::MoreSuits
+            )]
+        );
+        assert_eq!(history.game(), game);
+    }
+
+    #[test]
+    fn test_reject_impossible_table() {
+        // The second player's wildcard can't have been played first
+        let mut game = Game::new();
+        game.locations.set(
+            39,
+            Location::Table {
+                captured_by: None,
+                order: 0,
+                in_last_combination_before_pass: false,
+                after_pass: false,
+                wildcard_shape: None,
+            },
+        );
+        game.next_order = 1;
+        game.current_player = Player::Second;
+        assert_eq!(History::from_game(&game), None);
+    }
+}
+
+mod tests_for_undo {
+    use super::*;
+    use crate::compression::encode_game;
+    use crate::game::error::GameError;
+
+    #[test]
+    fn test_undo_play() {
+        let mut game = Game::new();
+        let dealt_game = game.clone();
+        assert_eq!(game.undo(), Err(GameError::NothingToUndo));
+
+        game.play_cards(&[36]);
+        let played_game = game.clone();
+        game.undo().unwrap();
+        assert_eq!(game.locations, dealt_game.locations);
+        assert_eq!(game.current_player, Player::First);
+        assert_eq!(game.last_combination_type, None);
+        assert_eq!(game.next_order, 0);
+
+        game.redo().unwrap();
+        assert_eq!(game, played_game);
+        assert_eq!(game.redo(), Err(GameError::NothingToRedo));
+    }
+
+    #[test]
+    fn test_undo_capture() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        game.play_cards(&[40]);
+        let before_pass = game.clone();
+        game.play_cards(&[]);
+        assert!(game.locations[36].captured_by().is_some());
+
+        game.undo().unwrap();
+        assert!(game.locations[36].captured_by().is_none());
+        assert_eq!(game.current_player, before_pass.current_player);
+        assert_eq!(
+            game.last_combination_type,
+            before_pass.last_combination_type
+        );
+        assert_eq!(game.locations, before_pass.locations);
+        assert_eq!(game.next_order, before_pass.next_order);
+
+        game.undo().unwrap();
+        game.undo().unwrap();
+        assert_eq!(game.undo.undone_moves.len(), 3);
+        for _ in 0..3 {
+            game.redo().unwrap();
+        }
+        assert!(game.locations[36].captured_by().is_some());
+    }
+
+    #[test]
+    fn test_new_move_clears_redo() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        game.undo().unwrap();
+        assert!(game.can_redo());
+
+        game.play_cards(&[37]);
+        assert!(!game.can_redo());
+        assert_eq!(game.redo(), Err(GameError::NothingToRedo));
+    }
+
+    #[test]
+    fn test_three_player_undo() {
+        let mut game = Game::new_for_players(3);
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        let before_pass = game.clone();
+        game.play_cards(&[]);
+        assert_eq!(game.last_combination_type, None);
+
+        game.undo().unwrap();
+        game.undo.undone_moves.clear();
+        assert_eq!(game, before_pass);
+    }
+
+    #[test]
+    fn test_undo_after_shared() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        game.mark_shared();
+        assert!(!game.can_undo());
+        assert_eq!(game.undo(), Err(GameError::UndoAfterShared));
+
+        game.set_undo_after_shared(true);
+        assert!(game.can_undo());
+        game.undo().unwrap();
+        assert_eq!(game.undo.num_shared_moves, 0);
+    }
+
+    #[test]
+    fn test_received_game_is_shared() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+
+        let mut received_game = Game::undealt();
+        assert_eq!(received_game.from_compressed(&encode_game(&game)), None);
+        assert_eq!(received_game.undo(), Err(GameError::UndoAfterShared));
+
+        // My own moves can be taken back until I share them
+        received_game.play_cards(&[40]);
+        received_game.undo().unwrap();
+        assert_eq!(received_game.locations, game.locations);
+        assert_eq!(received_game.current_player, game.current_player);
+    }
+}
+
+mod tests_for_score_breakdown {
+    use super::*;
+    use crate::game::card::CardId;
+    use crate::game::constant::INIT_HAND_SIZE;
+    use crate::game::score::ScoreBreakdown;
+
+    fn points(game: &Game, location: Location) -> usize {
+        (0..game.locations.len())
+            .filter(|&card_id| game.locations[card_id] == location)
+            .map(|card_id| CardId(card_id).to_value().point_value())
+            .sum()
+    }
+
+    /// A hand where I captured my own cards and then went out with a J
+    fn captured_by_me() -> Location {
+        Location::Table {
+            captured_by: Some(Player::First),
+            order: 0,
+            in_last_combination_before_pass: true,
+            after_pass: false,
+            wildcard_shape: None,
+        }
+    }
+
+    fn finished_hand() -> Game {
+        let mut game = Game::new();
+        for card_id in game.get_hand(Player::First) {
+            game.locations.set(card_id.0, captured_by_me());
+        }
+        game.locations.set(36, Location::Hand(Player::First));
+        game.next_order = 1;
+        game
+    }
+
+    #[test]
+    fn test_breakdown_of_finished_hand() {
+        let mut game = finished_hand();
+        let captured = points(&game, captured_by_me()) + 2;
+        let points_left = points(&game, Location::Hand(Player::Second));
+        let haggis = points(&game, Location::Haggis);
+        game.play_cards(&[36]);
+
+        assert_eq!(
+            game.score_breakdown(0),
+            ScoreBreakdown {
+                captured,
+                cards_left: 5 * INIT_HAND_SIZE,
+                points_left,
+                haggis,
+                bets: 0,
+            }
+        );
+        assert_eq!(game.score_breakdown(1), ScoreBreakdown::default());
+        let totals: Vec<usize> = game
+            .score_breakdowns_by_seat()
+            .iter()
+            .map(ScoreBreakdown::total)
+            .collect();
+        assert_eq!(&*game.calculate_score(), &totals[..]);
+    }
+
+    #[test]
+    fn test_breakdown_of_bets() {
+        let mut game = finished_hand();
+        game.bets[Player::Second.index()] = Bet::Big;
+        game.play_cards(&[36]);
+        assert_eq!(game.score_breakdown(0).bets, 30);
+        assert_eq!(game.score_breakdown(1).bets, 0);
+
+        // The breakdown is relative to the viewer
+        game.viewer = Player::Second;
+        assert_eq!(game.score_breakdown(1).bets, 30);
+    }
+
+    #[test]
+    fn test_breakdown_before_hand_is_over() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        game.play_cards(&[40]);
+        game.play_cards(&[]);
+        // Only the J and Q captured so far are scored
+        let breakdown = game.score_breakdown(1);
+        assert_eq!(breakdown.captured, 5);
+        assert_eq!(breakdown.total(), breakdown.captured);
+    }
+}
+
+mod tests_for_finish_hand {
+    use super::*;
+
+    /// A hand that is over, with my last combination still on the table
+    fn hand_over() -> Game {
+        let mut game = Game::new();
+        for card_id in 0..game.locations.len() {
+            if game.locations[card_id] == Location::Hand(Player::First) && card_id != 36 {
+                game.locations.set(
+                    card_id,
+                    Location::Table {
+                        captured_by: Some(Player::First),
+                        order: 0,
+                        in_last_combination_before_pass: true,
+                        after_pass: false,
+                        wildcard_shape: None,
+                    },
+                );
+            }
+        }
+        game.next_order = 1;
+        game.play_cards(&[36]);
+        game
+    }
+
+    #[test]
+    fn test_scoring_does_not_change_game() {
+        let game = hand_over();
+        let before = game.clone();
+        let scores = game.calculate_score();
+        for _ in 0..3 {
+            assert_eq!(game.calculate_score(), scores);
+            game.score_breakdown(0);
+            game.hand_sizes();
+        }
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn test_finish_hand() {
+        let mut game = hand_over();
+        let scores = game.calculate_score();
+        assert!(game.finish_hand());
+        assert!(matches!(
+            game.locations[36],
+            Location::Table {
+                captured_by: Some(Player::First),
+                in_last_combination_before_pass: true,
+                ..
+            }
+        ));
+        assert_eq!(game.calculate_score(), scores);
+
+        // Finishing again has no effect
+        let finished = game.clone();
+        assert!(game.finish_hand());
+        assert_eq!(game, finished);
+    }
+
+    #[test]
+    fn test_finish_hand_before_hand_is_over() {
+        let mut game = Game::new();
+        game.play_cards(&[36]);
+        let before = game.clone();
+        assert!(!game.finish_hand());
+        assert_eq!(game, before);
+    }
+}
+
+mod tests_for_validate {
+    use super::*;
+
+    #[test]
+    fn test_played_hands_are_valid() {
+        for hand in &seeded_hands() {
+            let mut game = hand.deal.clone();
+            assert_eq!(game.validate(), Ok(()));
+            for card_ids in &hand.moves {
+                game.play_cards(card_ids);
+                assert_eq!(game.validate(), Ok(()));
+            }
+            game.finish_hand();
+            assert_eq!(game.validate(), Ok(()));
+        }
+    }
+}
+
+mod tests_for_hidden {
+    use super::*;
+    use crate::game::card::normal_card_ids;
+    use crate::game::game_match::MatchKey;
+    use crate::game::hidden::{HandSecret, SealedGame};
+
+    /// The game that no seat can see on its own, once every seat has read its
+    /// hand
+    fn real_game(seats: &[SealedGame], moves: &[Vec<usize>]) -> Game {
+        let mut real = History::from_game(&seats[0].game()).unwrap().deal;
+        for card_id in normal_card_ids(real.num_players) {
+            real.locations.set(card_id, Location::Haggis);
+        }
+        for sealed in seats {
+            let deal = History::from_game(&sealed.game()).unwrap().deal;
+            for card_id in deal.locations.hand(deal.viewer).normal_cards().iter() {
+                real.locations.set(card_id.0, Location::Hand(deal.viewer));
+            }
+        }
+        for card_ids in moves {
+            real.play_cards(card_ids);
+        }
+        real
+    }
+
+    /// Deal a sealed hand and play it to the end, sending the game to the next
+    /// player after every move. Then the secrets go around until every seat
+    /// has every hand. The seats shuffle the deal themselves, so only the
+    /// number of players and the pick come from the seeded hand.
+    fn assert_hidden_hand(hand: &SeededHand) {
+        let num_players = hand.deal.num_players;
+        let key = MatchKey::from_code("haggis");
+        let mut seats = vec![SealedGame::deal(num_players, HandSecret::random(), &key)];
+        let mut bytes = seats[0].to_bytes();
+        for _ in 1..num_players {
+            let joined = SealedGame::join(&bytes, HandSecret::random(), &key).unwrap();
+            bytes = joined.to_bytes();
+            seats.push(joined);
+        }
+        let mut seat = 0;
+        while !seats[0].has_hand() {
+            seats[seat].receive(&bytes).unwrap();
+            bytes = seats[seat].to_bytes();
+            seat = (seat + 1) % num_players;
+        }
+
+        let mut moves = Vec::new();
+        let mut sender = 0;
+        let real = loop {
+            let card_ids = hand.pick.card_ids(&seats[sender].game());
+            assert_eq!(seats[sender].try_play_cards(&card_ids), Ok(()));
+            moves.push(card_ids);
+            let bytes = seats[sender].to_bytes();
+            if seats[sender].game().is_game_over() {
+                break real_game(&seats, &moves);
+            }
+
+            let receiver = seats[sender].game().current_player;
+            seats[receiver.index()].receive(&bytes).unwrap();
+            let real = real_game(&seats, &moves);
+            let game = seats[receiver.index()].game();
+            assert_eq!(game.locations.hand(receiver), real.locations.hand(receiver));
+            for seat in 0..num_players {
+                let player = Player::from_index(seat);
+                assert_eq!(
+                    game.locations.hand(player).len(),
+                    real.locations.hand(player).len()
+                );
+            }
+            for (card_id, location) in real.locations.iter().enumerate() {
+                if let Location::Table { .. } = location {
+                    assert_eq!(game.locations[card_id], *location);
+                }
+            }
+            sender = receiver.index();
+        };
+
+        let mut seat = sender;
+        for _ in 0..(2 * num_players) {
+            let next = (seat + 1) % num_players;
+            let bytes = seats[seat].to_bytes();
+            seats[next].receive(&bytes).unwrap();
+            seat = next;
+        }
+        for sealed in &seats {
+            assert!(sealed.is_fully_revealed());
+            assert_eq!(sealed.game().locations, real.locations);
+        }
+    }
+
+    #[test]
+    fn test_hidden_hands() {
+        for hand in &seeded_hands() {
+            assert_hidden_hand(hand);
+        }
+    }
+}
+
+mod tests_for_move_message {
+    use super::*;
+    use crate::compression::encode_game;
+    use crate::game::error::MoveMessageError;
+
+    #[test]
+    fn test_moves_apply() {
+        // Each move is sent to the copies of the game that the other players hold
+        for hand in &seeded_hands() {
+            let mut game = hand.deal.clone();
+            let mut receivers: Vec<Game> = (0..game.num_players)
+                .map(|index| Game {
+                    viewer: Player::from_index(index),
+                    ..game.clone()
+                })
+                .collect();
+            for card_ids in &hand.moves {
+                let player = game.current_player;
+                game.try_play_cards(player, card_ids).unwrap();
+                let message = game.to_move_message().unwrap();
+                for receiver in &mut receivers {
+                    if receiver.viewer == player {
+                        receiver.try_play_cards(player, card_ids).unwrap();
+                        continue;
+                    }
+                    assert_eq!(receiver.apply_move_message(&message), Ok(()));
+                    assert_eq!(receiver.locations, game.locations);
+                    assert_eq!(receiver.current_player, game.current_player);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reject_move_in_my_seat() {
+        let mut game = Game::new_with_seed(4);
+        let mut receiver = game.clone();
+        game.play_cards(&[36]);
+        let message = game.to_move_message().unwrap();
+
+        // Only I make the moves of my seat
+        assert_eq!(
+            receiver.apply_move_message(&message),
+            Err(MoveMessageError::MyTurn)
+        );
+        receiver.viewer = Player::Second;
+        assert_eq!(receiver.apply_move_message(&message), Ok(()));
+    }
+
+    #[test]
+    fn test_no_move_message_before_first_move() {
+        assert_eq!(Game::new().to_move_message(), None);
+    }
+
+    #[test]
+    fn test_state_mismatch() {
+        let mut game = Game::new_for_players_with_seed(3, 4);
+        let stale = Game {
+            viewer: Player::Third,
+            ..game.clone()
+        };
+        game.play_cards(&[36]);
+        let mut receiver = Game {
+            viewer: Player::Third,
+            ..game.clone()
+        };
+        game.play_cards(&[40]);
+        let message = game.to_move_message().unwrap();
+
+        // The receiver missed the move before, so it needs the whole game
+        let mut missed = stale.clone();
+        assert_eq!(
+            missed.apply_move_message(&message),
+            Err(MoveMessageError::StateMismatch)
+        );
+        assert_eq!(missed, stale);
+        assert!(Game::is_resync_request(&Game::resync_request()));
+        assert_eq!(missed.resync_from_compressed(&encode_game(&game)), None);
+        assert_eq!(missed.locations, game.locations);
+
+        assert_eq!(receiver.apply_move_message(&message), Ok(()));
+        // Applying it twice fails, since the state has moved on
+        assert_eq!(
+            receiver.apply_move_message(&message),
+            Err(MoveMessageError::StateMismatch)
+        );
+        assert_eq!(
+            receiver.apply_move_message(&encode_game(&game)),
+            Err(MoveMessageError::NotAMove)
+        );
+    }
+}
+
+mod tests_for_receive {
+    use super::*;
+    use crate::compression::encode_game;
+    use crate::game::error::ReceiveError;
+
+    /// Play the first legal move for the current player
+    fn play_first_move(game: &mut Game) {
+        let card_ids = game.legal_moves()[0].clone();
+        game.try_play_cards(game.current_player, &card_ids).unwrap();
+    }
+
+    #[test]
+    fn test_hand_is_received() {
+        // The game is sent to the next player after every move
+        for hand in &seeded_hands() {
+            let mut games: Vec<Game> = (0..hand.deal.num_players)
+                .map(|_| Game::undealt())
+                .collect();
+            games[0] = hand.deal.clone();
+            let mut seat = 0;
+            for card_ids in &hand.moves {
+                games[seat].play_cards(card_ids);
+                let data = encode_game(&games[seat]);
+                seat = games[seat].current_player.index();
+                assert_eq!(games[seat].from_compressed(&data), None);
+                // Every player keeps the seat they joined
+                for (index, game) in games.iter().enumerate() {
+                    if !game.is_undealt() {
+                        assert_eq!(game.viewer, Player::from_index(index));
+                    }
+                }
+            }
+        }
+    }
+
+    /// A hand where the first player has played, and the second player has
+    /// received it and played as well
+    fn hand_after_two_moves() -> (Game, Game) {
+        let mut first = Game::new_with_seed(8);
+        play_first_move(&mut first);
+        let mut second = Game::undealt();
+        assert_eq!(second.from_compressed(&encode_game(&first)), None);
+        assert_eq!(second.viewer, Player::Second);
+        play_first_move(&mut second);
+        (first, second)
+    }
+
+    #[test]
+    fn test_reject_different_game() {
+        let (mut first, _) = hand_after_two_moves();
+        let mut other = Game::new_with_seed(9);
+        play_first_move(&mut other);
+        let before = first.clone();
+        assert_eq!(
+            first.from_compressed(&encode_game(&other)),
+            Some(ReceiveError::DifferentGame)
+        );
+        assert_eq!(first, before);
+
+        // A dealt game is a hand of its own even before its first move
+        let mut dealer = Game::new_with_seed(8);
+        assert_eq!(
+            dealer.from_compressed(&encode_game(&other)),
+            Some(ReceiveError::DifferentGame)
+        );
+    }
+
+    #[test]
+    fn test_keep_my_seat() {
+        let mut game = Game::new_for_players_with_seed(3, 8);
+        play_first_move(&mut game);
+        play_first_move(&mut game);
+        let mut third = Game::undealt();
+        assert_eq!(third.from_compressed(&encode_game(&game)), None);
+        assert_eq!(third.viewer, Player::Third);
+
+        // The first player sends their move to everyone, on the second
+        // player's turn
+        play_first_move(&mut third);
+        let mut first = third.clone();
+        play_first_move(&mut first);
+        assert_eq!(first.current_player, Player::Second);
+        assert_eq!(third.from_compressed(&encode_game(&first)), None);
+        assert_eq!(third.viewer, Player::Third);
+    }
+
+    #[test]
+    fn test_reject_older_state() {
+        let (mut first, second) = hand_after_two_moves();
+        let older = encode_game(&first);
+        assert_eq!(
+            first.from_compressed(&older),
+            Some(ReceiveError::OlderState)
+        );
+        assert_eq!(first.from_compressed(&encode_game(&second)), None);
+        assert_eq!(
+            first.from_compressed(&older),
+            Some(ReceiveError::OlderState)
+        );
+    }
+
+    #[test]
+    fn test_reject_moves_in_my_seat() {
+        let (first, mut second) = hand_after_two_moves();
+        let mut stale = first.clone();
+        play_first_move(&mut second);
+        play_first_move(&mut second);
+        let data = encode_game(&second);
+
+        // The second player made a move in my seat, which even a resync or a
+        // game of mine can't explain
+        assert_eq!(
+            stale.from_compressed(&data),
+            Some(ReceiveError::MovesInMySeat)
+        );
+        assert_eq!(
+            stale.resync_from_compressed(&data),
+            Some(ReceiveError::MovesInMySeat)
+        );
+        assert_eq!(stale, first);
+
+        // The player can still choose to take the game as it is
+        assert_eq!(stale.reset_from_compressed(&data), None);
+        assert_eq!(stale.locations, second.locations);
+        assert_eq!(stale.viewer, Player::First);
+    }
+
+    #[test]
+    fn test_reject_changed_bets() {
+        let (mut first, mut second) = hand_after_two_moves();
+        assert_eq!(first.from_compressed(&encode_game(&second)), None);
+        play_first_move(&mut first);
+        assert_eq!(second.from_compressed(&encode_game(&first)), None);
+        let card_ids = second.legal_moves().pop().unwrap();
+        second.try_play_cards(Player::Second, &card_ids).unwrap();
+        assert!(second.has_played_a_card(Player::Second));
+        assert_eq!(first.from_compressed(&encode_game(&second)), None);
+        play_first_move(&mut first);
+        assert_eq!(second.from_compressed(&encode_game(&first)), None);
+        play_first_move(&mut second);
+        let before = first.clone();
+
+        // The second player bets after playing and changes my bet
+        let mut tampered = second.clone();
+        tampered.bets[Player::Second.index()] = Bet::Big;
+        tampered.bets[Player::First.index()] = Bet::Little;
+        assert_eq!(
+            first.from_compressed(&encode_game(&tampered)),
+            Some(ReceiveError::ChangedBets)
+        );
+        assert_eq!(first, before);
+
+        // Each change is rejected on its own
+        let mut tampered = second.clone();
+        tampered.bets[Player::Second.index()] = Bet::Big;
+        assert_eq!(
+            first.from_compressed(&encode_game(&tampered)),
+            Some(ReceiveError::ChangedBets)
+        );
+        let mut tampered = second.clone();
+        tampered.bets[Player::First.index()] = Bet::Little;
+        assert_eq!(
+            first.from_compressed(&encode_game(&tampered)),
+            Some(ReceiveError::ChangedBets)
+        );
+        assert_eq!(first, before);
+        assert_eq!(first.from_compressed(&encode_game(&second)), None);
+    }
+
+    #[test]
+    fn test_bet_before_first_card_is_received() {
+        let mut first = Game::new_with_seed(8);
+        play_first_move(&mut first);
+        let mut second = Game::undealt();
+        assert_eq!(second.from_compressed(&encode_game(&first)), None);
+        assert_eq!(second.declare_bet(Player::Second, Bet::Little), Ok(()));
+        play_first_move(&mut second);
+        assert_eq!(first.from_compressed(&encode_game(&second)), None);
+        assert_eq!(first.bets[Player::Second.index()], Bet::Little);
+
+        // The bet can't be taken back or raised later
+        play_first_move(&mut first);
+        assert_eq!(second.from_compressed(&encode_game(&first)), None);
+        let card_ids = second.legal_moves().pop().unwrap();
+        second.try_play_cards(Player::Second, &card_ids).unwrap();
+        second.bets[Player::Second.index()] = Bet::Big;
+        assert_eq!(
+            first.from_compressed(&encode_game(&second)),
+            Some(ReceiveError::ChangedBets)
+        );
+    }
+
+    #[test]
+    fn test_reject_skipped_moves() {
+        // I go out with a sequence of pairs and a bomb, and the other two
+        // players keep playing
+        let deal = "
+            First: 2♠ 3♠ 4♠ 5♠ 6♠ 7♠ 8♠ 2♥ 3♥ 4♥ 5♥ 6♥ 7♥ 8♥ J Q K
+            Second: 9♠ 10♠ 9♥ 10♥ 2♦ 3♦ 4♦ 5♦ 6♦ 7♦ 8♦ 9♦ 10♦ 2♣ J Q K
+            Third: 3♣ 4♣ 5♣ 6♣ 7♣ 8♣ 9♣ 10♣ 2★ 3★ 4★ 5★ 6★ 7★ J Q K
+            Haggis: 8★ 9★ 10★
+            2♠ 2♥ 3♠ 3♥ 4♠ 4♥ 5♠ 5♥ 6♠ 6♥ 7♠ 7♥ 8♠ 8♥
+            pass
+            pass
+            J Q K
+            pass
+            pass
+        ";
+        let mut first = Game::from_notation(deal).unwrap();
+        let one_turn = Game::from_notation(&format!("{}\n2♦\n3♣", deal)).unwrap();
+        let two_turns = Game::from_notation(&format!("{}\n2♦\n3♣\n4♦", deal)).unwrap();
+
+        assert_eq!(first.clone().from_compressed(&encode_game(&one_turn)), None);
+        // The second player moved twice, so a game in between is missing
+        assert_eq!(
+            first.from_compressed(&encode_game(&two_turns)),
+            Some(ReceiveError::SkippedMoves)
+        );
+        assert_eq!(first.resync_from_compressed(&encode_game(&two_turns)), None);
+        assert_eq!(first.locations, two_turns.locations);
+    }
+
+    #[test]
+    fn test_reject_taking_back_seen_moves() {
+        let mut first = Game::new_with_seed(8);
+        play_first_move(&mut first);
+        let mut second = Game::undealt();
+        assert_eq!(second.from_compressed(&encode_game(&first)), None);
+        let legal_moves = second.legal_moves();
+        let mut other_second = second.clone();
+
+        play_first_move(&mut second);
+        assert_eq!(first.from_compressed(&encode_game(&second)), None);
+
+        // The second player sends a different move in place of the one I saw
+        other_second
+            .try_play_cards(Player::Second, &legal_moves[1])
+            .unwrap();
+        let data = encode_game(&other_second);
+        assert_eq!(
+            first.from_compressed(&data),
+            Some(ReceiveError::IllegalMove)
+        );
+        first.set_undo_after_shared(true);
+        assert_eq!(first.from_compressed(&data), None);
+    }
+
+    #[test]
+    fn test_reject_undecodable() {
+        let mut game = Game::new();
+        assert_eq!(
+            game.from_compressed(&[0, 1, 2]),
+            Some(ReceiveError::Undecodable)
+        );
+    }
+}
+
+mod tests_for_match_key {
+    use super::*;
+    use crate::compression::encode_game;
+    use crate::game::error::{MoveMessageError, ReceiveError};
+    use crate::game::game_match::{KeyExchange, Match, MatchKey};
+    use crate::game::tests::tests_for_match::finished_hand;
+
+    #[test]
+    fn test_match_key_from_code() {
+        assert_eq!(
+            MatchKey::from_code(" Haggis "),
+            MatchKey::from_code("haggis")
+        );
+        assert_ne!(
+            MatchKey::from_code("haggis"),
+            MatchKey::from_code("haggis2")
+        );
+    }
+
+    #[test]
+    fn test_match_key_from_exchange() {
+        let mine = KeyExchange::new();
+        let theirs = KeyExchange::new();
+        let my_message = mine.share_message();
+        let their_message = theirs.share_message();
+        assert!(KeyExchange::is_share_message(&my_message));
+        let key = mine.key(&their_message).unwrap();
+        assert_eq!(theirs.key(&my_message), Some(key.clone()));
+
+        // Someone who only reads the shares gets another key
+        let eavesdropper = KeyExchange::new();
+        assert_ne!(eavesdropper.key(&my_message), Some(key.clone()));
+        assert_ne!(eavesdropper.key(&their_message), Some(key));
+
+        assert!(!KeyExchange::is_share_message(&encode_game(&Game::new())));
+        assert_eq!(mine.key(&encode_game(&Game::new())), None);
+    }
+
+    #[test]
+    fn test_next_hand_is_signed() {
+        let key = MatchKey::from_code("haggis");
+        let mut haggis_match = Match::new();
+        haggis_match.set_match_key(&key);
+        let mut opponent_match = Match::new();
+        opponent_match.set_match_key(&key);
+
+        // The opponent wins the first hand, so I lead the second
+        let first_hand = haggis_match.next_hand().unwrap();
+        assert_eq!(first_hand.match_key, Some(key.clone()));
+        assert!(haggis_match.record_hand(&finished_hand(Player::Second, Player::First)));
+        let mut second_hand = haggis_match.next_hand().unwrap();
+        assert_eq!(second_hand.match_key, Some(key.clone()));
+        let card_ids = second_hand.legal_moves()[0].clone();
+        second_hand
+            .try_play_cards(second_hand.current_player, &card_ids)
+            .unwrap();
+
+        let data = encode_game(&second_hand);
+        assert_eq!(
+            Game::undealt().from_compressed(&data),
+            Some(ReceiveError::Unauthenticated)
+        );
+        let mut receiver = opponent_match.undealt_hand();
+        assert_eq!(receiver.from_compressed(&data), None);
+        assert_eq!(receiver.match_key, Some(key));
+    }
+
+    #[test]
+    fn test_signed_games_are_received() {
+        let key = MatchKey::from_code("haggis");
+        let mut game = Game::new_with_seed(3);
+        game.set_match_key(&key);
+        game.play_cards(&[36]);
+
+        let mut receiver = Game::undealt();
+        assert_eq!(
+            receiver.from_compressed(&encode_game(&game)),
+            Some(ReceiveError::Unauthenticated)
+        );
+        receiver.set_match_key(&MatchKey::from_code("other"));
+        assert_eq!(
+            receiver.from_compressed(&encode_game(&game)),
+            Some(ReceiveError::Unauthenticated)
+        );
+        receiver.set_match_key(&key);
+        assert_eq!(receiver.from_compressed(&encode_game(&game)), None);
+        assert_eq!(receiver.match_key, Some(key.clone()));
+
+        // Moves are signed as well
+        receiver.play_cards(&[40]);
+        let message = receiver.to_move_message().unwrap();
+        let mut unsigned = game.clone();
+        unsigned.match_key = None;
+        assert_eq!(
+            unsigned.apply_move_message(&message),
+            Err(MoveMessageError::Unauthenticated)
+        );
+        unsigned.match_key = Some(MatchKey::from_code("other"));
+        assert_eq!(
+            unsigned.apply_move_message(&message),
+            Err(MoveMessageError::Unauthenticated)
+        );
+        assert_eq!(game.apply_move_message(&message), Ok(()));
+    }
+}
+
+mod tests_for_text {
+    use image::{DynamicImage, Luma};
+    use qrcode::QrCode;
+
+    use super::*;
+    use crate::game::error::ReceiveError;
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut game = Game::new_with_seed(11);
+        game.play_cards(&[36]);
+        let text = game.to_text();
+        assert!(text.starts_with("HAGGIS:"));
+
+        let mut receiver = Game::undealt();
+        assert_eq!(receiver.from_text(&text), None);
+        assert_eq!(receiver.locations, game.locations);
+        assert_eq!(receiver.viewer, Player::Second);
+    }
+
+    #[test]
+    fn test_reject_bad_text() {
+        let mut game = Game::new();
+        for &text in &["", "hello", "HAGGIS:", "HAGGIS:BB8"] {
+            assert_eq!(game.from_text(text), Some(ReceiveError::Undecodable));
+        }
+    }
+
+    #[test]
+    fn test_read_text_qr_code() {
+        let mut game = Game::new_with_seed(11);
+        game.play_cards(&[36]);
+
+        // A qr code of the text, like one made from a link
+        let code = QrCode::new(game.to_text().as_bytes()).unwrap();
+        let image = code.render::<Luma<u8>>().max_dimensions(200, 200).build();
+        let mut receiver = Game::undealt();
+        receiver
+            .read_qr_code(DynamicImage::ImageLuma8(image))
+            .unwrap();
+        assert_eq!(receiver.locations, game.locations);
+    }
+}
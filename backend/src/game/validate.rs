@@ -0,0 +1,469 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::card::{deck_size, CardId};
+use super::card_set::CardSet;
+use super::constant::{HAGGIS_SIZE, NUM_PLAYERS, SET_ASIDE_SIZE_THREE_PLAYERS};
+use super::history::Replay;
+use super::location::Location;
+use super::player::Player;
+use super::wildcard_shape::WildcardShape;
+use super::Game;
+
+// A game that was played move by move always satisfies these invariants, but a
+// game that was decoded or built by hand might not. Checking them lets a game
+// be rejected before anything relies on it.
+
+/// An invariant that a game breaks
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Violation {
+    /// The game doesn't have a location for each card of its deck
+    WrongDeckSize { expected: usize, actual: usize },
+    /// The wrong number of cards are in the Haggis, or set aside with three players
+    WrongHaggisSize { expected: usize, actual: usize },
+    /// A wildcard is in the Haggis or in the hand of a player it doesn't belong to
+    MisplacedWildcard(CardId),
+    /// A card on the table has an order of next_order or higher
+    OrderTooHigh(CardId),
+    /// No cards on the table have an order below next_order
+    MissingOrder(usize),
+    /// The cards of a combination don't agree on who captured them or how
+    /// they were played
+    SplitCombination(usize),
+    /// A combination was left uncaptured before the last combination group
+    UncapturedBeforeLastGroup(usize),
+    /// A combination is marked as the last of its group, but it isn't
+    MisplacedLastCombination(usize),
+    /// The last combination of a captured group isn't marked as the last
+    MissingLastCombination(usize),
+    /// The cards in the hands and on the table don't add up to a deal
+    WrongHandSizes,
+    /// A captured combination couldn't have been played after the ones before
+    /// it in its group
+    IllegalCombination(usize),
+    /// The group ending with a combination wasn't captured by the player who
+    /// won it
+    WrongCaptor(usize),
+    /// last_combination_type doesn't match the combinations in the current group
+    WrongLastCombinationType,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::WrongDeckSize { expected, actual } => write!(
+                f,
+                "expected {} card locations but found {}",
+                expected, actual
+            ),
+            Violation::WrongHaggisSize { expected, actual } => write!(
+                f,
+                "expected {} cards in the Haggis but found {}",
+                expected, actual
+            ),
+            Violation::MisplacedWildcard(card_id) => {
+                write!(f, "wildcard {} is not with its owner", card_id.0)
+            }
+            Violation::OrderTooHigh(card_id) => write!(
+                f,
+                "card {} was played after the next combination",
+                card_id.0
+            ),
+            Violation::MissingOrder(order) => write!(f, "combination {} is missing", order),
+            Violation::SplitCombination(order) => {
+                write!(f, "the cards of combination {} disagree", order)
+            }
+            Violation::UncapturedBeforeLastGroup(order) => write!(
+                f,
+                "combination {} is not captured, but a later one is",
+                order
+            ),
+            Violation::MisplacedLastCombination(order) => write!(
+                f,
+                "combination {} is marked as the last of its group, but it isn't",
+                order
+            ),
+            Violation::MissingLastCombination(order) => write!(
+                f,
+                "combination {} ends its group, but it isn't marked as the last",
+                order
+            ),
+            Violation::WrongHandSizes => {
+                f.write_str("the hands and the table don't add up to a deal")
+            }
+            Violation::IllegalCombination(order) => write!(
+                f,
+                "combination {} could not have been played in its group",
+                order
+            ),
+            Violation::WrongCaptor(order) => write!(
+                f,
+                "the group ending with combination {} was captured by the wrong player",
+                order
+            ),
+            Violation::WrongLastCombinationType => f.write_str(
+                "the last combination type does not match the combinations on the table",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// What every card of a combination on the table has in common
+struct Combination {
+    order: usize,
+    card_ids: Vec<usize>,
+    captured_by: Option<Player>,
+    in_last_combination_before_pass: bool,
+    after_pass: bool,
+    wildcard_shape: Option<WildcardShape>,
+}
+
+impl Combination {
+    fn card_ids(&self) -> Vec<CardId> {
+        self.card_ids
+            .iter()
+            .map(|&card_id| CardId(card_id))
+            .collect()
+    }
+}
+
+impl Game {
+    /// Check the invariants of the game, returning every one that it breaks
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+
+        let expected = deck_size(self.num_players);
+        if self.locations.len() != expected {
+            violations.push(Violation::WrongDeckSize {
+                expected,
+                actual: self.locations.len(),
+            });
+        }
+
+        let expected = if self.num_players == NUM_PLAYERS {
+            HAGGIS_SIZE
+        } else {
+            SET_ASIDE_SIZE_THREE_PLAYERS
+        };
+        let actual = self
+            .locations
+            .iter()
+            .filter(|&location| *location == Location::Haggis)
+            .count();
+        if actual != expected {
+            violations.push(Violation::WrongHaggisSize { expected, actual });
+        }
+
+        for (card_id, location) in self.locations.iter().enumerate() {
+            let seat = match CardId(card_id).wildcard_seat() {
+                Some(seat) => seat,
+                None => continue,
+            };
+            match location {
+                Location::Hand(owner) if owner.index() == seat => {}
+                Location::Table { .. } => {}
+                _ => violations.push(Violation::MisplacedWildcard(CardId(card_id))),
+            }
+        }
+
+        let combinations = self.table_combinations(&mut violations);
+        self.validate_groups(&combinations, &mut violations);
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// The combinations on the table in the order they were played
+    fn table_combinations(&self, violations: &mut Vec<Violation>) -> Vec<Combination> {
+        let mut combinations: BTreeMap<usize, Combination> = BTreeMap::new();
+        for (card_id, location) in self.locations.iter().enumerate() {
+            if let Location::Table {
+                captured_by,
+                order,
+                in_last_combination_before_pass,
+                after_pass,
+                wildcard_shape,
+            } = *location
+            {
+                if order >= self.next_order {
+                    violations.push(Violation::OrderTooHigh(CardId(card_id)));
+                    continue;
+                }
+                let combination = combinations.entry(order).or_insert(Combination {
+                    order,
+                    card_ids: Vec::new(),
+                    captured_by,
+                    in_last_combination_before_pass,
+                    after_pass,
+                    wildcard_shape,
+                });
+                let is_split = combination.captured_by != captured_by
+                    || combination.in_last_combination_before_pass
+                        != in_last_combination_before_pass
+                    || combination.after_pass != after_pass
+                    || combination.wildcard_shape != wildcard_shape;
+                if is_split && !violations.contains(&Violation::SplitCombination(order)) {
+                    violations.push(Violation::SplitCombination(order));
+                }
+                combination.card_ids.push(card_id);
+            }
+        }
+
+        for order in 0..self.next_order {
+            if !combinations.contains_key(&order) {
+                violations.push(Violation::MissingOrder(order));
+            }
+        }
+        combinations.into_values().collect()
+    }
+
+    /// Check that every combination group but the last is captured and ends
+    /// with the one combination marked as its last, and that the last group
+    /// matches last_combination_type
+    fn validate_groups(&self, combinations: &[Combination], violations: &mut Vec<Violation>) {
+        let num_captured = combinations
+            .iter()
+            .rposition(|combination| combination.captured_by.is_some())
+            .map_or(0, |index| index + 1);
+
+        for (index, combination) in combinations.iter().enumerate() {
+            let order = combination.order;
+            match (
+                combination.captured_by,
+                combination.in_last_combination_before_pass,
+            ) {
+                (None, _) if index < num_captured => {
+                    violations.push(Violation::UncapturedBeforeLastGroup(order))
+                }
+                (None, true) => violations.push(Violation::MisplacedLastCombination(order)),
+                _ => {}
+            }
+        }
+        if let Some(last_captured) = num_captured
+            .checked_sub(1)
+            .map(|index| &combinations[index])
+        {
+            if !last_captured.in_last_combination_before_pass {
+                violations.push(Violation::MissingLastCombination(last_captured.order));
+            }
+        }
+
+        // The replay relies on the rest of the game being consistent
+        if violations.is_empty() {
+            self.validate_captures(combinations, num_captured, violations);
+        }
+
+        // Replay the combinations of the last group, which nobody has captured
+        let mut last_combination_type = None;
+        for combination in &combinations[num_captured..] {
+            match Game::combination_type_after(
+                &last_combination_type,
+                CardSet::from_ids(&combination.card_ids),
+                combination.wildcard_shape,
+            ) {
+                Ok(combination_type) => last_combination_type = Some(combination_type),
+                Err(_) => {
+                    violations.push(Violation::WrongLastCombinationType);
+                    return;
+                }
+            }
+        }
+        if last_combination_type != self.last_combination_type {
+            violations.push(Violation::WrongLastCombinationType);
+        }
+    }
+
+    /// Replay the first num_captured combinations, ending a group at each
+    /// combination marked as the last, and check that every group is captured
+    /// by the same player as in the game. A mark on a combination that didn't
+    /// end its group gives the group to the wrong player, and a missing mark
+    /// joins two groups into one that can't be played or is won by the wrong
+    /// player.
+    fn validate_captures(
+        &self,
+        combinations: &[Combination],
+        num_captured: usize,
+        violations: &mut Vec<Violation>,
+    ) {
+        let cards_on_table: Vec<CardId> = combinations
+            .iter()
+            .flat_map(Combination::card_ids)
+            .collect();
+        let mut replay = match Replay::new(self, &cards_on_table) {
+            Some(replay) => replay,
+            None => {
+                violations.push(Violation::WrongHandSizes);
+                return;
+            }
+        };
+
+        let mut group_start = 0;
+        for (index, combination) in combinations[..num_captured].iter().enumerate() {
+            let played = (!combination.after_pass || replay.pass().is_some())
+                && replay
+                    .play(combination.card_ids(), combination.wildcard_shape)
+                    .is_some();
+            if !played {
+                violations.push(Violation::IllegalCombination(combination.order));
+                return;
+            }
+            if !combination.in_last_combination_before_pass {
+                continue;
+            }
+
+            let ended = replay.end_group().is_some();
+            let group = &combinations[group_start..=index];
+            let same_captors = group.iter().all(|grouped| {
+                grouped.card_ids.iter().all(|&card_id| {
+                    replay.game.locations[card_id].captured_by() == grouped.captured_by
+                })
+            });
+            if !ended || !same_captors {
+                violations.push(Violation::WrongCaptor(combination.order));
+                return;
+            }
+            group_start = index + 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mark whether the combination of a card on the table ended its group
+    fn mark_last_combination(game: &mut Game, card_id: usize, last: bool) {
+        let mut location = game.locations[card_id].clone();
+        if let Location::Table {
+            in_last_combination_before_pass,
+            ..
+        } = &mut location
+        {
+            *in_last_combination_before_pass = last;
+        }
+        game.locations.set(card_id, location);
+    }
+
+    #[test]
+    fn test_every_violation_is_returned() {
+        let mut game = Game::new_with_seed(3);
+        game.play_cards(&[36]);
+        game.play_cards(&[40]);
+
+        // Give my K to the opponent and take a card out of the Haggis
+        game.locations.set(38, Location::Hand(Player::Second));
+        let haggis_card = game
+            .locations
+            .iter()
+            .position(|location| *location == Location::Haggis)
+            .unwrap();
+        game.locations
+            .set(haggis_card, Location::Hand(Player::First));
+
+        assert_eq!(
+            game.validate(),
+            Err(vec![
+                Violation::WrongHaggisSize {
+                    expected: HAGGIS_SIZE,
+                    actual: HAGGIS_SIZE - 1,
+                },
+                Violation::MisplacedWildcard(CardId(38)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_table_violations() {
+        let mut game = Game::new_with_seed(4);
+        game.play_cards(&[36]);
+        game.play_cards(&[40]);
+        game.play_cards(&[]);
+        game.play_cards(&[41]);
+
+        // The captured J and Q are not the last group
+        let mut uncaptured = game.clone();
+        uncaptured.locations.set(
+            36,
+            Location::Table {
+                captured_by: None,
+                order: 0,
+                in_last_combination_before_pass: false,
+                after_pass: false,
+                wildcard_shape: None,
+            },
+        );
+        assert_eq!(
+            uncaptured.validate(),
+            Err(vec![Violation::UncapturedBeforeLastGroup(0)])
+        );
+
+        // A combination is missing and one was played after next_order
+        let mut reordered = game.clone();
+        reordered.next_order = 2;
+        assert_eq!(
+            reordered.validate(),
+            Err(vec![
+                Violation::OrderTooHigh(CardId(41)),
+                Violation::WrongLastCombinationType,
+            ])
+        );
+
+        // The last group has not ended yet
+        let mut flagged = game.clone();
+        flagged.locations.set(
+            41,
+            Location::Table {
+                captured_by: None,
+                order: 2,
+                in_last_combination_before_pass: true,
+                after_pass: false,
+                wildcard_shape: None,
+            },
+        );
+        assert_eq!(
+            flagged.validate(),
+            Err(vec![Violation::MisplacedLastCombination(2)])
+        );
+
+        let mut wrong_type = game;
+        wrong_type.last_combination_type = None;
+        assert_eq!(
+            wrong_type.validate(),
+            Err(vec![Violation::WrongLastCombinationType])
+        );
+    }
+
+    #[test]
+    fn test_missing_last_combination() {
+        // I capture my J, then lead and capture my Q
+        let mut game = Game::new_with_seed(4);
+        game.play_cards(&[36]);
+        game.play_cards(&[]);
+        game.play_cards(&[37]);
+        game.play_cards(&[]);
+        assert_eq!(game.validate(), Ok(()));
+
+        // Without a mark on the J, the opponent would have played my Q
+        mark_last_combination(&mut game, 36, false);
+        assert_eq!(game.validate(), Err(vec![Violation::IllegalCombination(1)]));
+    }
+
+    #[test]
+    fn test_misplaced_last_combination() {
+        // The opponent beats my J with their Q and captures both
+        let mut game = Game::new_with_seed(4);
+        game.play_cards(&[36]);
+        game.play_cards(&[40]);
+        game.play_cards(&[]);
+        assert_eq!(game.validate(), Ok(()));
+
+        // With a mark on the J, I would have captured it
+        mark_last_combination(&mut game, 36, true);
+        assert_eq!(game.validate(), Err(vec![Violation::WrongCaptor(0)]));
+    }
+}
@@ -0,0 +1,14 @@
+use wasm_bindgen::prelude::*;
+
+/// When a combination has more wildcards than it needs, the extra wildcards
+/// can either extend it with more ranks or with more suits. For example,
+/// 7♣ J Q can be played as the sequence 7♣ 8♣ 9♣ or as three 7s. The player
+/// declares which one they mean when they play it.
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WildcardShape {
+    /// The extra wildcards make the combination longer
+    MoreRanks,
+    /// The extra wildcards add suits to every rank of the combination
+    MoreSuits,
+}
@@ -1,6 +1,9 @@
 mod compression;
 mod game;
 
+pub use game::hidden;
+pub use game::Game;
+
 use wasm_bindgen::prelude::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
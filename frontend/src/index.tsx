@@ -1,4 +1,12 @@
-import type { Game, GameStage, CardFrontendState } from "../dist/qr_haggis";
+import type {
+  Bet,
+  Game,
+  GameStage,
+  CardFrontendState,
+  MatchStage,
+  ScoreBreakdown,
+  WildcardShape,
+} from "../dist/qr_haggis";
 
 import * as React from "react";
 import * as ReactDOM from "react-dom";
@@ -7,10 +15,21 @@ const QR_WIDTH = 296;
 const QR_HEIGHT = 296;
 
 import("../dist/qr_haggis").then((module) => {
-  let game = module.Game.new();
+  // Dealt when I lead the hand, or seated by the first game I receive
+  let game = module.Game.undealt();
+  // Running totals across the hands of the match
+  let haggisMatch = module.Match.new();
+
+  // A link that opens the current game, for sharing it as text
+  function gameLink(): string {
+    const url = new URL(window.location.href);
+    url.hash = encodeURIComponent(game.to_text());
+    return url.toString();
+  }
 
   type AppState = {
     stage: GameStage;
+    matchStage: MatchStage;
     // Store qr code for copying
     outputQrBlob: Blob | null;
     // Store url to qr code to display in img
@@ -30,6 +49,7 @@ import("../dist/qr_haggis").then((module) => {
       super({});
       this.state = {
         stage: module.GameStage.BeforeGame,
+        matchStage: haggisMatch.stage(),
         outputQrBlob: null,
         outputQrObjectUrl: null,
         myScore: 0,
@@ -39,25 +59,45 @@ import("../dist/qr_haggis").then((module) => {
         websocket: null,
       };
       this.buttonHandler = this.buttonHandler.bind(this);
+      this.betHandler = this.betHandler.bind(this);
       this.cardClickHandler = this.cardClickHandler.bind(this);
       this.qrReadHandler = this.qrReadHandler.bind(this);
     }
 
+    // Read the game in the link that opened the app, if there is one
+    componentDidMount() {
+      const text = decodeURIComponent(window.location.hash.slice(1));
+      if (!text) {
+        return;
+      }
+      window.history.replaceState(null, "", window.location.pathname);
+      const error = game.from_text(text);
+      if (error !== undefined) {
+        alert(`Could not read the link (${module.ReceiveError[error]}).`);
+      } else {
+        this.updateGame(this.createWebsocket());
+      }
+    }
+
     // Update the game after a move has been made and close the websocket
-    // if the game is over.
+    // if the game is over. A finished hand is added to the match totals.
     // To set a new websocket, include a newWebsocket argument.
     updateGame(newWebsocket?: WebSocket) {
-      const scores = game.calculate_score();
+      let scores = game.calculate_score();
       const stage = game.game_stage();
 
       let websocket = newWebsocket || this.state.websocket;
       if (stage == module.GameStage.GameOver) {
+        game.finish_hand();
         this.state.websocket?.close();
         websocket = null;
+        haggisMatch.record_hand(game);
+        scores = haggisMatch.scores();
       }
 
       this.setState({
         stage,
+        matchStage: haggisMatch.stage(),
         selectedCards: new Set(),
         myScore: scores[0],
         opponentScore: scores[1],
@@ -73,14 +113,66 @@ import("../dist/qr_haggis").then((module) => {
       const websocket = new WebSocket("wss://qr-haggis.herokuapp.com/v1");
       websocket.binaryType = "arraybuffer";
 
-      // Update the game when receiving messages
+      // The first websocket of a match agrees on the match key, which then
+      // signs every game and move of the match
+      const keyExchange = haggisMatch.has_match_key()
+        ? null
+        : module.KeyExchange.new();
+
+      // Update the game when receiving messages. Moves are sent on their own,
+      // and the whole game is only sent when the opponent asks for it.
+      // Only the answer to our own resync request may skip moves.
+      let awaitingResync = false;
       websocket.addEventListener("message", (event) => {
         const array = new Uint8Array(event.data);
-        if (array) {
-          const success = game.from_compressed(array);
-          if (!success) {
-            console.warn("Error reading data from server");
-          } else {
+        if (!array) {
+          return;
+        }
+        if (module.KeyExchange.is_share_message(array)) {
+          const key = haggisMatch.has_match_key()
+            ? undefined
+            : keyExchange?.key(array);
+          if (key !== undefined) {
+            haggisMatch.set_match_key(key);
+            game.set_match_key(key);
+            // My share may have been sent before the opponent connected
+            websocket.send(keyExchange!.share_message());
+          }
+          return;
+        }
+        if (module.Game.is_resync_request(array)) {
+          websocket.send(game.to_compressed());
+          return;
+        }
+        const error = game.apply_move_message(array);
+        if (error === undefined) {
+          this.updateGame();
+        } else if (error == module.MoveMessageError.StateMismatch) {
+          // We missed a move, so ask for the whole game
+          awaitingResync = true;
+          websocket.send(module.Game.resync_request());
+        } else {
+          // The whole game, sent for a resync request or with the last move
+          const receiveError = awaitingResync
+            ? game.resync_from_compressed(array)
+            : game.from_compressed(array);
+          awaitingResync = false;
+          if (receiveError === undefined) {
+            this.updateGame();
+          } else if (
+            receiveError == module.ReceiveError.Undecodable ||
+            receiveError == module.ReceiveError.Unauthenticated ||
+            receiveError == module.ReceiveError.OlderState
+          ) {
+            console.warn(
+              `Error reading data from server (${module.ReceiveError[receiveError]})`
+            );
+          } else if (
+            confirm(
+              `The opponent's game does not follow from this one (${module.ReceiveError[receiveError]}). Replace this game with theirs?`
+            )
+          ) {
+            game.reset_from_compressed(array);
             this.updateGame();
           }
         }
@@ -89,6 +181,9 @@ import("../dist/qr_haggis").then((module) => {
       // The server expects the first message to be the client id
       websocket.addEventListener("open", () => {
         websocket.send(game.get_client_id());
+        if (keyExchange) {
+          websocket.send(keyExchange.share_message());
+        }
       });
 
       return websocket;
@@ -99,6 +194,11 @@ import("../dist/qr_haggis").then((module) => {
       switch (this.state.stage) {
         // Start the game
         case module.GameStage.BeforeGame:
+          // The opponent leads this hand, so we wait for their qr code
+          if (this.state.matchStage == module.MatchStage.OpponentLead) {
+            break;
+          }
+          game = haggisMatch.next_hand()!;
           this.setState({
             stage: module.GameStage.Play,
             websocket: this.createWebsocket(),
@@ -106,21 +206,66 @@ import("../dist/qr_haggis").then((module) => {
           break;
         // Play the selected cards
         case module.GameStage.Play:
+        case module.GameStage.Bet:
           if (this.state.isSelectionValid) {
-            game.play_cards(Uint32Array.from(this.state.selectedCards));
-
-            this.state.websocket?.send(game.to_compressed());
+            const cardIds = Uint32Array.from(this.state.selectedCards);
+            let error;
+            if (game.needs_wildcard_shape(cardIds)) {
+              // The extra wildcards can be used in two ways, so ask which one
+              const shape = confirm(
+                "Use the extra wildcards to make a longer sequence? Cancel to add more suits instead."
+              )
+                ? module.WildcardShape.MoreRanks
+                : module.WildcardShape.MoreSuits;
+              error = game.try_play_cards_with_shape(cardIds, shape);
+            } else {
+              error = game.try_play_cards(cardIds);
+            }
+            if (error !== undefined) {
+              alert(
+                `Could not play the selected cards (${module.GameError[error]}).`
+              );
+              break;
+            }
+            // If that was the last move of the hand, capture the last
+            // combination group before the hand is sent
+            game.finish_hand();
+
+            if (this.state.websocket) {
+              // The websocket closes once the hand is over, so the last move
+              // is sent with the whole game in case the opponent needs it
+              const message =
+                game.game_stage() == module.GameStage.GameOver
+                  ? game.to_compressed()
+                  : game.to_move_message() ?? game.to_compressed();
+              this.state.websocket.send(message);
+              // The opponent has seen the move, so it can't be taken back
+              game.mark_shared();
+            }
             this.updateGame();
             this.renderOutputQRCode();
           } else {
             alert("You did not select a valid card combination.");
           }
           break;
-        // Reset App to BeforeGame
+        // Take back the move that the opponent hasn't seen yet
+        case module.GameStage.Wait: {
+          const error = game.undo();
+          if (error !== undefined) {
+            console.warn(`Could not undo (${module.GameError[error]}).`);
+          }
+          this.updateGame();
+          break;
+        }
+        // Reset App to BeforeGame, starting a new match if this one is over
         case module.GameStage.GameOver:
-          game = module.Game.new();
+          if (haggisMatch.stage() == module.MatchStage.MatchOver) {
+            haggisMatch = module.Match.new();
+          }
+          game = haggisMatch.undealt_hand();
           this.setState({
             stage: module.GameStage.BeforeGame,
+            matchStage: haggisMatch.stage(),
             outputQrBlob: null,
             myScore: 0,
             opponentScore: 0,
@@ -131,6 +276,15 @@ import("../dist/qr_haggis").then((module) => {
       }
     }
 
+    // Handle the user declaring a bet before playing their first card
+    betHandler(bet: Bet) {
+      const error = game.declare_bet(bet);
+      if (error !== undefined) {
+        console.warn(`Could not bet (${module.GameError[error]}).`);
+      }
+      this.updateGame();
+    }
+
     // Asynchronously generate a qr code representing the current game state
     // and update this.state.outputQrBlob/this.state.outputQrObjectUrl
     renderOutputQRCode() {
@@ -178,9 +332,9 @@ import("../dist/qr_haggis").then((module) => {
     // Update the game based on an input qr image and create a new websocket
     // connection if none exists
     qrReadHandler(imageData: ArrayBuffer) {
-      const success = game.from_qr_code(new Uint8Array(imageData));
-      if (!success) {
-        console.warn("Error reading qr code");
+      const error = game.from_qr_code(new Uint8Array(imageData));
+      if (error !== undefined) {
+        alert(`Could not read the qr code (${error}).`);
       } else if (this.state.websocket === null) {
         this.updateGame(this.createWebsocket());
       } else {
@@ -189,7 +343,16 @@ import("../dist/qr_haggis").then((module) => {
     }
 
     render() {
-      const scores = game.calculate_score();
+      // Show the match totals plus the score of the hand being played
+      const matchScores = haggisMatch.scores();
+      const handScores =
+        this.state.stage == module.GameStage.GameOver
+          ? [0, 0]
+          : game.calculate_score();
+      const scores = [
+        matchScores[0] + handScores[0],
+        matchScores[1] + handScores[1],
+      ];
       const handSizes = game.hand_sizes();
       // Organize player data by first/second player instead of by me/opponent
       // so that the two players are assigned different colors and sides
@@ -230,14 +393,24 @@ import("../dist/qr_haggis").then((module) => {
           />
           <Sidebar
             stage={this.state.stage}
+            matchStage={this.state.matchStage}
             isSelectionValid={this.state.isSelectionValid}
             isSelectionEmpty={this.state.selectedCards.size == 0}
             qrReadHandler={this.qrReadHandler}
             buttonHandler={this.buttonHandler}
+            betHandler={this.betHandler}
+            canUndo={game.can_undo()}
+            lastWildcardShape={game.last_wildcard_shape()}
+            lastCombination={game.last_combination()?.description()}
             outputQrBlob={this.state.outputQrBlob}
             outputQrObjectUrl={this.state.outputQrObjectUrl}
             myScore={this.state.myScore}
             opponentScore={this.state.opponentScore}
+            breakdowns={
+              this.state.stage == module.GameStage.GameOver
+                ? [game.score_breakdown(0), game.score_breakdown(1)]
+                : []
+            }
           />
         </div>
       );
@@ -350,7 +523,8 @@ import("../dist/qr_haggis").then((module) => {
       className += ` state${this.props.frontendState}`;
 
       if (
-        this.props.stage == module.GameStage.Play &&
+        (this.props.stage == module.GameStage.Play ||
+          this.props.stage == module.GameStage.Bet) &&
         this.props.frontendState == module.CardFrontendState.InMyHand
       ) {
         return (
@@ -373,6 +547,7 @@ import("../dist/qr_haggis").then((module) => {
 
   type SidebarProps = {
     stage: GameStage;
+    matchStage: MatchStage;
     myScore: number;
     opponentScore: number;
     outputQrBlob: Blob | null;
@@ -380,7 +555,14 @@ import("../dist/qr_haggis").then((module) => {
     isSelectionValid: boolean;
     isSelectionEmpty: boolean;
     buttonHandler: () => void;
+    betHandler: (bet: Bet) => void;
+    canUndo: boolean;
+    lastWildcardShape: WildcardShape | undefined;
+    // What the opponent just played, if it hasn't been captured
+    lastCombination: string | undefined;
     qrReadHandler: (imageData: ArrayBuffer) => void;
+    // Where my and the opponent's points for the finished hand came from
+    breakdowns: ScoreBreakdown[];
   };
 
   // Holds QRDisplay, the sidebar button, and QRReader and shows them only when relevant
@@ -407,9 +589,11 @@ import("../dist/qr_haggis").then((module) => {
       const button = (
         <Button
           stage={this.props.stage}
+          matchStage={this.props.matchStage}
           isSelectionValid={this.props.isSelectionValid}
           isSelectionEmpty={this.props.isSelectionEmpty}
           buttonHandler={this.props.buttonHandler}
+          canUndo={this.props.canUndo}
           outcome={outcome}
         />
       );
@@ -419,6 +603,26 @@ import("../dist/qr_haggis").then((module) => {
           qrReadHandler={this.props.qrReadHandler}
         />
       );
+      // Tell the player how the opponent used their extra wildcards
+      const wildcardShape =
+        this.props.lastWildcardShape === undefined ? (
+          <></>
+        ) : (
+          <div id="wildcard_shape">
+            {this.props.lastWildcardShape == module.WildcardShape.MoreRanks
+              ? "wildcards played as a longer sequence"
+              : "wildcards played as more suits"}
+          </div>
+        );
+
+      const lastCombination =
+        this.props.lastCombination === undefined ? (
+          <></>
+        ) : (
+          <div id="last_combination">
+            Opponent played: {this.props.lastCombination}
+          </div>
+        );
 
       switch (this.props.stage) {
         case module.GameStage.BeforeGame:
@@ -429,7 +633,22 @@ import("../dist/qr_haggis").then((module) => {
             </>
           );
         case module.GameStage.Play:
-          return button;
+          return (
+            <>
+              {button}
+              {lastCombination}
+              {wildcardShape}
+            </>
+          );
+        case module.GameStage.Bet:
+          return (
+            <>
+              {button}
+              <BetButtons betHandler={this.props.betHandler} />
+              {lastCombination}
+              {wildcardShape}
+            </>
+          );
         case module.GameStage.Wait:
           return (
             <>
@@ -443,12 +662,52 @@ import("../dist/qr_haggis").then((module) => {
             <>
               {qrDisplay}
               {button}
+              <Scoresheet breakdowns={this.props.breakdowns} />
             </>
           );
       }
     }
   }
 
+  type ScoresheetProps = {
+    breakdowns: ScoreBreakdown[];
+  };
+
+  // Explain the score of the finished hand, one row per source of points
+  class Scoresheet extends React.Component<ScoresheetProps> {
+    render() {
+      const rows: [string, (breakdown: ScoreBreakdown) => number][] = [
+        ["captured", (breakdown) => breakdown.captured],
+        ["cards left", (breakdown) => breakdown.cards_left],
+        ["points left", (breakdown) => breakdown.points_left],
+        ["haggis", (breakdown) => breakdown.haggis],
+        ["bets", (breakdown) => breakdown.bets],
+        ["hand total", (breakdown) => breakdown.total()],
+      ];
+      return (
+        <table id="scoresheet">
+          <thead>
+            <tr>
+              <th></th>
+              <th>me</th>
+              <th>opponent</th>
+            </tr>
+          </thead>
+          <tbody>
+            {rows.map(([name, points]) => (
+              <tr key={name}>
+                <th>{name}</th>
+                {this.props.breakdowns.map((breakdown, player) => (
+                  <td key={player}>{points(breakdown)}</td>
+                ))}
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      );
+    }
+  }
+
   type QRDisplayProps = {
     outputQrBlob: Blob;
     outputQrObjectUrl: string;
@@ -462,7 +721,8 @@ import("../dist/qr_haggis").then((module) => {
       this.copy = this.copy.bind(this);
     }
 
-    // Copy the qr code as a png blob to the clipboard (supported only in Chrome)
+    // Copy the qr code as a png blob to the clipboard (supported only in
+    // Chrome), or a link to the game in other browsers
     copy() {
       try {
         // @ts-ignore
@@ -473,7 +733,7 @@ import("../dist/qr_haggis").then((module) => {
           }),
         ]);
       } catch (e) {
-        console.error(e);
+        navigator.clipboard.writeText(gameLink()).catch(console.error);
       }
     }
 
@@ -502,17 +762,22 @@ import("../dist/qr_haggis").then((module) => {
 
   type ButtonProps = {
     stage: GameStage;
+    matchStage: MatchStage;
     outcome: Outcome;
     isSelectionValid: boolean;
     isSelectionEmpty: boolean;
     buttonHandler: () => void;
+    canUndo: boolean;
   };
 
-  // Button to start the game or play cards
+  // Button to start the game, play cards or take back a move
   class Button extends React.Component<ButtonProps> {
     render() {
       switch (this.props.stage) {
         case module.GameStage.BeforeGame:
+          if (this.props.matchStage == module.MatchStage.OpponentLead) {
+            return <div id="button">wait</div>;
+          }
           return (
             <div
               id="button"
@@ -523,6 +788,7 @@ import("../dist/qr_haggis").then((module) => {
             </div>
           );
         case module.GameStage.Play:
+        case module.GameStage.Bet:
           if (this.props.isSelectionValid) {
             const text = this.props.isSelectionEmpty ? "pass" : "play";
             return (
@@ -538,8 +804,30 @@ import("../dist/qr_haggis").then((module) => {
             return <div id="button">play</div>;
           }
         case module.GameStage.Wait:
+          if (this.props.canUndo) {
+            return (
+              <div
+                id="button"
+                className="enabled"
+                onClick={this.props.buttonHandler}
+              >
+                undo
+              </div>
+            );
+          }
           return <div id="button">wait</div>;
         case module.GameStage.GameOver:
+          if (this.props.matchStage != module.MatchStage.MatchOver) {
+            return (
+              <div
+                id="button"
+                className="enabled"
+                onClick={this.props.buttonHandler}
+              >
+                next hand
+              </div>
+            );
+          }
           switch (this.props.outcome) {
             case Outcome.Won:
               return (
@@ -576,6 +864,32 @@ import("../dist/qr_haggis").then((module) => {
     }
   }
 
+  type BetButtonsProps = {
+    betHandler: (bet: Bet) => void;
+  };
+
+  // Buttons to declare a little or big bet before playing the first card
+  class BetButtons extends React.Component<BetButtonsProps> {
+    render() {
+      return (
+        <div id="bet_buttons">
+          <div
+            className="bet_button enabled"
+            onClick={() => this.props.betHandler(module.Bet.Little)}
+          >
+            bet 15
+          </div>
+          <div
+            className="bet_button enabled"
+            onClick={() => this.props.betHandler(module.Bet.Big)}
+          >
+            bet 30
+          </div>
+        </div>
+      );
+    }
+  }
+
   type QRReaderProps = {
     outputQrObjectUrl: string | null;
     qrReadHandler: (imageData: ArrayBuffer) => void;
@@ -716,7 +1030,7 @@ import("../dist/qr_haggis").then((module) => {
             }
           });
       } catch (e) {
-        console.error(e);
+        navigator.clipboard.writeText(gameLink()).catch(console.error);
       }
     }
 